@@ -0,0 +1,436 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+
+use crate::graph::store::{GraphStore, NodeId};
+use crate::graph::witchvm::Entry;
+use crate::kv::witchvm_kv::number_to_json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// Procedures callable from Cypher with CALL, every one yields one row per node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Procedure {
+    PageRank,
+    Wcc,
+    Degree,
+    TriangleCount,
+}
+
+impl Procedure {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "algo.pageRank" => Some(Procedure::PageRank),
+            "algo.wcc" => Some(Procedure::Wcc),
+            "algo.degree" => Some(Procedure::Degree),
+            "algo.triangleCount" => Some(Procedure::TriangleCount),
+            _ => None,
+        }
+    }
+
+    // Names of the values a row yields, in order
+    pub fn fields(self) -> &'static [&'static str] {
+        match self {
+            Procedure::PageRank | Procedure::Degree => &["node", "score"],
+            Procedure::Wcc => &["node", "componentId"],
+            Procedure::TriangleCount => &["node", "triangleCount"],
+        }
+    }
+
+    // Arguments are (label, relationship type, config), all optional, null means any
+    pub fn call(
+        self,
+        graph: &GraphStore,
+        arguments: Vec<serde_json::Value>,
+    ) -> Result<Vec<Vec<Entry>>, String> {
+        if arguments.len() > 3 {
+            return Err(format!(
+                "Procedure takes at most 3 arguments, got {}",
+                arguments.len()
+            ));
+        }
+        let mut arguments = arguments.into_iter();
+        let label = optional_string(arguments.next(), "label")?;
+        let rel_type = optional_string(arguments.next(), "relationship type")?;
+        let config = match arguments.next() {
+            None | Some(serde_json::Value::Null) => serde_json::Map::new(),
+            Some(serde_json::Value::Object(config)) => config,
+            Some(value) => return Err(format!("Expected a config map, got {}", value)),
+        };
+
+        let projection = Projection::new(graph, label.as_deref(), rel_type.as_deref());
+        let scores = match self {
+            Procedure::PageRank => {
+                let iterations = config_number(
+                    &config,
+                    "iterations",
+                    20.0,
+                    |n| n.fract() == 0.0 && (1.0..=MAX_ITERATIONS).contains(&n),
+                    &format!("a whole number from 1 to {}", MAX_ITERATIONS),
+                )? as usize;
+                let damping_factor = config_number(
+                    &config,
+                    "dampingFactor",
+                    0.85,
+                    |n| (0.0..1.0).contains(&n),
+                    "a number from 0 up to, but not including, 1",
+                )?;
+                page_rank(&projection, iterations, damping_factor)
+            }
+            Procedure::Wcc => weakly_connected_components(&projection),
+            Procedure::Degree => {
+                let orientation = match config.get("orientation") {
+                    None => "NATURAL",
+                    Some(serde_json::Value::String(orientation)) => orientation.as_str(),
+                    Some(value) => return Err(format!("Expected an orientation, got {}", value)),
+                };
+                degree(&projection, orientation)?
+            }
+            Procedure::TriangleCount => triangle_count(&projection),
+        };
+
+        Ok(scores
+            .into_iter()
+            .map(|(id, score)| vec![Entry::Node(id), Entry::Value(number_to_json(score))])
+            .collect())
+    }
+}
+
+fn optional_string(
+    argument: Option<serde_json::Value>,
+    what: &str,
+) -> Result<Option<String>, String> {
+    match argument {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(value)) => Ok(Some(value)),
+        Some(value) => Err(format!("Expected a {}, got {}", what, value)),
+    }
+}
+
+// The graph stays locked while an algorithm runs, so the work it may ask for is bounded
+const MAX_ITERATIONS: f64 = 1000.0;
+
+fn config_number(
+    config: &serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    default: f64,
+    valid: impl Fn(f64) -> bool,
+    expected: &str,
+) -> Result<f64, String> {
+    match config.get(key) {
+        None => Ok(default),
+        Some(value) => value
+            .as_f64()
+            .filter(|number| valid(*number))
+            .ok_or(format!(
+                "Config '{}' must be {}, got {}",
+                key, expected, value
+            )),
+    }
+}
+
+// The nodes with the label and the relationships of the type between them
+struct Projection {
+    nodes: BTreeSet<NodeId>,
+    // (start, end) of every relationship, a pair appears once per relationship
+    relationships: Vec<(NodeId, NodeId)>,
+}
+
+impl Projection {
+    fn new(graph: &GraphStore, label: Option<&str>, rel_type: Option<&str>) -> Self {
+        let nodes: BTreeSet<NodeId> = graph
+            .nodes()
+            .filter(|node| label.is_none_or(|label| node.labels.iter().any(|l| l == label)))
+            .map(|node| node.id)
+            .collect();
+        let relationships = graph
+            .relationships()
+            .filter(|relationship| {
+                rel_type.is_none_or(|rel_type| relationship.rel_type == rel_type)
+                    && nodes.contains(&relationship.start)
+                    && nodes.contains(&relationship.end)
+            })
+            .map(|relationship| (relationship.start, relationship.end))
+            .collect();
+        Self {
+            nodes,
+            relationships,
+        }
+    }
+
+    // Neighbours ignoring direction, without loops and duplicates
+    fn undirected(&self) -> BTreeMap<NodeId, BTreeSet<NodeId>> {
+        let mut neighbours: BTreeMap<NodeId, BTreeSet<NodeId>> =
+            self.nodes.iter().map(|id| (*id, BTreeSet::new())).collect();
+        for (start, end) in self.relationships.iter() {
+            if start != end {
+                neighbours.entry(*start).or_default().insert(*end);
+                neighbours.entry(*end).or_default().insert(*start);
+            }
+        }
+        neighbours
+    }
+}
+
+// score = (1 - d) + d * sum(score of m / out degree of m) over the nodes m pointing at the node
+fn page_rank(
+    projection: &Projection,
+    iterations: usize,
+    damping_factor: f64,
+) -> Vec<(NodeId, f64)> {
+    let mut out_degree: HashMap<NodeId, usize> = HashMap::new();
+    for (start, _) in projection.relationships.iter() {
+        *out_degree.entry(*start).or_default() += 1;
+    }
+
+    let mut scores: BTreeMap<NodeId, f64> = projection
+        .nodes
+        .iter()
+        .map(|id| (*id, 1.0 - damping_factor))
+        .collect();
+    for _ in 0..iterations {
+        let mut next: BTreeMap<NodeId, f64> = projection
+            .nodes
+            .iter()
+            .map(|id| (*id, 1.0 - damping_factor))
+            .collect();
+        for (start, end) in projection.relationships.iter() {
+            let share = scores[start] / out_degree[start] as f64;
+            *next.entry(*end).or_default() += damping_factor * share;
+        }
+        scores = next;
+    }
+    scores.into_iter().collect()
+}
+
+// The component of a node is the smallest node id in it
+fn weakly_connected_components(projection: &Projection) -> Vec<(NodeId, f64)> {
+    let mut parents: HashMap<NodeId, NodeId> =
+        projection.nodes.iter().map(|id| (*id, *id)).collect();
+
+    fn find(parents: &mut HashMap<NodeId, NodeId>, id: NodeId) -> NodeId {
+        let mut root = id;
+        while parents[&root] != root {
+            root = parents[&root];
+        }
+        // Path compression
+        let mut current = id;
+        while parents[&current] != root {
+            let next = parents[&current];
+            parents.insert(current, root);
+            current = next;
+        }
+        root
+    }
+
+    for (start, end) in projection.relationships.iter() {
+        let (a, b) = (find(&mut parents, *start), find(&mut parents, *end));
+        if a != b {
+            parents.insert(a.max(b), a.min(b));
+        }
+    }
+    projection
+        .nodes
+        .iter()
+        .map(|id| (*id, find(&mut parents, *id) as f64))
+        .collect()
+}
+
+// NATURAL counts outgoing relationships, REVERSE incoming, UNDIRECTED both
+fn degree(projection: &Projection, orientation: &str) -> Result<Vec<(NodeId, f64)>, String> {
+    let (outgoing, incoming) = match orientation.to_uppercase().as_str() {
+        "NATURAL" => (true, false),
+        "REVERSE" => (false, true),
+        "UNDIRECTED" => (true, true),
+        _ => {
+            return Err(format!(
+                "Unknown orientation '{}', expected NATURAL, REVERSE or UNDIRECTED",
+                orientation
+            ))
+        }
+    };
+
+    let mut degrees: BTreeMap<NodeId, f64> = projection.nodes.iter().map(|id| (*id, 0.0)).collect();
+    for (start, end) in projection.relationships.iter() {
+        if outgoing {
+            *degrees.entry(*start).or_default() += 1.0;
+        }
+        if incoming {
+            *degrees.entry(*end).or_default() += 1.0;
+        }
+    }
+    Ok(degrees.into_iter().collect())
+}
+
+// Triangles a node is part of, relationships count in both directions
+fn triangle_count(projection: &Projection) -> Vec<(NodeId, f64)> {
+    let neighbours = projection.undirected();
+    neighbours
+        .iter()
+        .map(|(id, around)| {
+            let mut triangles = 0;
+            for a in around.iter() {
+                for b in around.range(a + 1..) {
+                    if neighbours[a].contains(b) {
+                        triangles += 1;
+                    }
+                }
+            }
+            (*id, triangles as f64)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::store::Properties;
+
+    // Ann -> Bob -> Cid -> Ann is a triangle, Dan -> Ann hangs off it, Eve is alone
+    fn graph() -> GraphStore {
+        let mut graph = GraphStore::new();
+        for _ in 0..5 {
+            graph.create_node(vec!["Person".to_string()], Properties::new());
+        }
+        graph.create_node(vec!["City".to_string()], Properties::new());
+        for (from, to, rel_type) in [
+            (0, 1, "KNOWS"),
+            (1, 2, "KNOWS"),
+            (2, 0, "KNOWS"),
+            (3, 0, "KNOWS"),
+            (4, 5, "LIVES_IN"),
+        ] {
+            graph
+                .create_relationship(from, to, rel_type.to_string(), Properties::new())
+                .unwrap();
+        }
+        graph
+    }
+
+    fn scores(procedure: Procedure, arguments: Vec<serde_json::Value>) -> Result<Vec<f64>, String> {
+        Ok(procedure
+            .call(&graph(), arguments)?
+            .into_iter()
+            .map(|row| match &row[1] {
+                Entry::Value(score) => score.as_f64().unwrap(),
+                _ => panic!("Expected a score"),
+            })
+            .collect())
+    }
+
+    #[test]
+    fn test_algorithms() {
+        let person = || serde_json::json!("Person");
+        let knows = || serde_json::json!("KNOWS");
+
+        let ranks = scores(
+            Procedure::PageRank,
+            vec![person(), knows(), serde_json::json!({"iterations": 50})],
+        )
+        .unwrap();
+        assert_eq!(ranks.len(), 5);
+        // Ann is pointed at twice, Dan and Eve only have the base score
+        assert!(ranks[0] > ranks[1] && ranks[1] > ranks[3]);
+        assert!((ranks[3] - 0.15).abs() < 1e-9);
+        assert!((ranks[4] - 0.15).abs() < 1e-9);
+
+        assert_eq!(
+            scores(Procedure::Wcc, vec![person(), knows()]).unwrap(),
+            vec![0.0, 0.0, 0.0, 0.0, 4.0]
+        );
+        // Without a label the LIVES_IN relationship joins Eve and the city
+        assert_eq!(
+            scores(Procedure::Wcc, Vec::new()).unwrap(),
+            vec![0.0, 0.0, 0.0, 0.0, 4.0, 4.0]
+        );
+        assert_eq!(
+            scores(Procedure::Degree, vec![person(), knows()]).unwrap(),
+            vec![1.0, 1.0, 1.0, 1.0, 0.0]
+        );
+        assert_eq!(
+            scores(
+                Procedure::Degree,
+                vec![
+                    person(),
+                    knows(),
+                    serde_json::json!({"orientation": "UNDIRECTED"})
+                ]
+            )
+            .unwrap(),
+            vec![3.0, 2.0, 2.0, 1.0, 0.0]
+        );
+        assert_eq!(
+            scores(Procedure::TriangleCount, vec![person(), knows()]).unwrap(),
+            vec![1.0, 1.0, 1.0, 0.0, 0.0]
+        );
+
+        assert!(scores(Procedure::Degree, vec![serde_json::json!(1)]).is_err());
+        for config in [
+            serde_json::json!({"iterations": 0}),
+            serde_json::json!({"iterations": 2.5}),
+            serde_json::json!({"iterations": 1e12}),
+            serde_json::json!({"iterations": "5"}),
+            serde_json::json!({"dampingFactor": 1}),
+            serde_json::json!({"dampingFactor": -0.1}),
+        ] {
+            assert!(
+                scores(Procedure::PageRank, vec![person(), knows(), config.clone()]).is_err(),
+                "{}",
+                config
+            );
+        }
+        assert!(scores(
+            Procedure::PageRank,
+            vec![
+                person(),
+                knows(),
+                serde_json::json!({"iterations": 1000, "dampingFactor": 0})
+            ]
+        )
+        .is_ok());
+        assert!(scores(
+            Procedure::Degree,
+            vec![person(), knows(), serde_json::json!({"orientation": "UP"})]
+        )
+        .is_err());
+    }
+}
@@ -43,10 +43,17 @@
 // MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
 // MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
 
-// Lexer: Converts raw Cypher into tokens
+use crate::common::{FieldType, FieldValue};
+use crate::graph::algorithms::Procedure;
+use crate::graph::error::{Error, Location};
+use crate::graph::store::Properties;
+use crate::graph::witchvm::{
+    evaluate_binary, Aggregation, Entry, Evaluator, Instruction, RelationshipFilter, Statistics,
+};
+use crate::kv::witchvm_kv::{number_to_json, IndexPlan};
+use std::collections::HashSet;
 
-use std::iter::Peekable;
-use std::str::Chars;
+// Lexer: Converts raw Cypher into tokens
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
@@ -55,29 +62,81 @@ pub enum Token {
     Return,
     As,
     Where,
+    Create,
+    Merge,
+    On,
+    Set,
+    Remove,
+    Delete,
+    Detach,
+    And,
+    Or,
+    Xor,
+    Not,
+    In,
+    Is,
+    Starts,
+    Ends,
+    With,
+    Contains,
+    Distinct,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Skip,
+    Limit,
+    Call,
+    Yield,
 
     // Symbols
-    LeftParen,  // (
-    RightParen, // )
-    LeftBrace,  // {
-    RightBrace, // }
-    Colon,      // :
-    Comma,      // ,
-    Dot,        // .
+    LeftParen,    // (
+    RightParen,   // )
+    LeftBrace,    // {
+    RightBrace,   // }
+    Colon,        // :
+    Comma,        // ,
+    Dot,          // .
+    LeftBracket,  // [
+    RightBracket, // ]
+    Minus,        // -
+    RightArrow,   // ->
+    LeftArrow,    // <-
+    Pipe,         // |
+    Asterisk,     // *
+    Equal,        // =
+    NotEqual,     // <> or !=
+    Less,         // <
+    LessEqual,    // <=
+    Greater,      // >
+    GreaterEqual, // >=
+    Plus,         // +
+    Slash,        // /
+    Percent,      // %
 
     // Values
     Identifier(String),
     String(String),
     Number(f64),
+    // $name
+    Parameter(String),
+
+    // Text the lexer can not read, with the reason
+    Invalid(String),
 
     // End of input
-    EOF,
+    Eof,
 }
 
+#[derive(Clone)]
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
     current: char,
+    line: usize,
+    column: usize,
+    // Position, line and column where the last token starts
+    token_start: (usize, usize, usize),
 }
 
 impl Lexer {
@@ -88,14 +147,34 @@ impl Lexer {
             input: chars,
             position: 0,
             current,
+            line: 1,
+            column: 1,
+            token_start: (0, 1, 1),
         }
     }
 
     fn advance(&mut self) {
+        if self.current == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         self.position += 1;
         self.current = self.input.get(self.position).copied().unwrap_or('\0');
     }
 
+    // Line, column and text of the last token
+    pub fn location(&self) -> Location {
+        let (start, line, column) = self.token_start;
+        let end = self.position.min(self.input.len());
+        Location {
+            line,
+            column,
+            token: self.input[start.min(end)..end].iter().collect(),
+        }
+    }
+
     fn peek(&self) -> char {
         self.input.get(self.position + 1).copied().unwrap_or('\0')
     }
@@ -115,22 +194,37 @@ impl Lexer {
         identifier
     }
 
-    fn read_string(&mut self) -> String {
+    fn read_number(&mut self) -> f64 {
+        let mut number = String::new();
+        while self.current.is_ascii_digit() || (self.current == '.' && self.peek().is_ascii_digit())
+        {
+            number.push(self.current);
+            self.advance();
+        }
+        number.parse().unwrap_or_default()
+    }
+
+    // None if the input ends before the closing quote
+    fn read_string(&mut self) -> Option<String> {
         let mut string = String::new();
         self.advance(); // Skip opening quote
-        while self.current != '\'' && self.current != '\0' {
+        while self.current != '\'' {
+            if self.current == '\0' {
+                return None;
+            }
             string.push(self.current);
             self.advance();
         }
         self.advance(); // Skip closing quote
-        string
+        Some(string)
     }
 
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
+        self.token_start = (self.position, self.line, self.column);
 
         let token = match self.current {
-            '\0' => Token::EOF,
+            '\0' => Token::Eof,
             '(' => {
                 self.advance();
                 Token::LeftParen
@@ -159,7 +253,84 @@ impl Lexer {
                 self.advance();
                 Token::Dot
             }
-            '\'' => Token::String(self.read_string()),
+            '[' => {
+                self.advance();
+                Token::LeftBracket
+            }
+            ']' => {
+                self.advance();
+                Token::RightBracket
+            }
+            '|' => {
+                self.advance();
+                Token::Pipe
+            }
+            '*' => {
+                self.advance();
+                Token::Asterisk
+            }
+            '=' => {
+                self.advance();
+                Token::Equal
+            }
+            '-' if self.peek() == '>' => {
+                self.advance();
+                self.advance();
+                Token::RightArrow
+            }
+            '-' => {
+                self.advance();
+                Token::Minus
+            }
+            '<' if self.peek() == '-' => {
+                self.advance();
+                self.advance();
+                Token::LeftArrow
+            }
+            '<' | '>' | '!' if self.peek() == '=' => {
+                let token = match self.current {
+                    '<' => Token::LessEqual,
+                    '>' => Token::GreaterEqual,
+                    _ => Token::NotEqual,
+                };
+                self.advance();
+                self.advance();
+                token
+            }
+            '<' if self.peek() == '>' => {
+                self.advance();
+                self.advance();
+                Token::NotEqual
+            }
+            '<' => {
+                self.advance();
+                Token::Less
+            }
+            '>' => {
+                self.advance();
+                Token::Greater
+            }
+            '+' => {
+                self.advance();
+                Token::Plus
+            }
+            '/' => {
+                self.advance();
+                Token::Slash
+            }
+            '%' => {
+                self.advance();
+                Token::Percent
+            }
+            '\'' => match self.read_string() {
+                Some(string) => Token::String(string),
+                None => Token::Invalid("Unterminated string".to_string()),
+            },
+            '$' => {
+                self.advance();
+                Token::Parameter(self.read_identifier())
+            }
+            c if c.is_ascii_digit() => Token::Number(self.read_number()),
             c if c.is_alphabetic() => {
                 let ident = self.read_identifier();
                 match ident.to_uppercase().as_str() {
@@ -167,12 +338,38 @@ impl Lexer {
                     "RETURN" => Token::Return,
                     "AS" => Token::As,
                     "WHERE" => Token::Where,
+                    "CREATE" => Token::Create,
+                    "MERGE" => Token::Merge,
+                    "ON" => Token::On,
+                    "SET" => Token::Set,
+                    "REMOVE" => Token::Remove,
+                    "DELETE" => Token::Delete,
+                    "DETACH" => Token::Detach,
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "XOR" => Token::Xor,
+                    "NOT" => Token::Not,
+                    "IN" => Token::In,
+                    "IS" => Token::Is,
+                    "STARTS" => Token::Starts,
+                    "ENDS" => Token::Ends,
+                    "WITH" => Token::With,
+                    "CONTAINS" => Token::Contains,
+                    "DISTINCT" => Token::Distinct,
+                    "ORDER" => Token::Order,
+                    "BY" => Token::By,
+                    "ASC" | "ASCENDING" => Token::Asc,
+                    "DESC" | "DESCENDING" => Token::Desc,
+                    "SKIP" => Token::Skip,
+                    "LIMIT" => Token::Limit,
+                    "CALL" => Token::Call,
+                    "YIELD" => Token::Yield,
                     _ => Token::Identifier(ident),
                 }
             }
-            _ => {
+            c => {
                 self.advance();
-                Token::EOF
+                Token::Invalid(format!("Unexpected character '{}'", c))
             }
         };
 
@@ -181,15 +378,119 @@ impl Lexer {
 }
 
 // AST Structures
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Query(Box<Query>),
+    // CREATE INDEX ON :Person(age) TYPE number
+    CreateIndex {
+        label: String,
+        property: String,
+        field_type: FieldType,
+    },
+    // DROP INDEX ON :Person(age)
+    DropIndex {
+        label: String,
+        property: String,
+    },
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Query {
+    // CALL algo.pageRank(...) YIELD node, score runs before the MATCH
+    pub call: Option<CallClause>,
+    // Without MATCH the patterns are empty
     pub match_clause: MatchClause,
+    pub where_clause: Option<Expression>,
+    // CREATE, MERGE, SET, REMOVE and DELETE in query order
+    pub updates: Vec<UpdateClause>,
+    // Without RETURN the items are empty, with a next part this is the WITH projection
     pub return_clause: ReturnClause,
+    // The rest of the query after WITH
+    pub next: Option<Box<Query>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CallClause {
+    pub procedure: String,
+    pub arguments: Vec<Expression>,
+    // (field, variable) pairs, empty yields every field under its own name
+    pub yields: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UpdateClause {
+    // Nodes of the pattern that are not bound yet are created
+    Create(MatchClause),
+    Merge(MergeClause),
+    Set(Vec<SetItem>),
+    Remove(Vec<RemoveItem>),
+    Delete {
+        variables: Vec<String>,
+        detach: bool,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MergeClause {
+    pub pattern: MatchClause,
+    pub on_create: Vec<SetItem>,
+    pub on_match: Vec<SetItem>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SetItem {
+    // n.prop = value
+    Property {
+        variable: String,
+        property: String,
+        value: Expression,
+    },
+    // n:Label:Other
+    Labels {
+        variable: String,
+        labels: Vec<String>,
+    },
+    // doc(n) = ['storage', 'key'], null unlinks the document
+    Document {
+        variable: String,
+        value: Expression,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
+pub enum RemoveItem {
+    Property {
+        variable: String,
+        property: String,
+    },
+    Labels {
+        variable: String,
+        labels: Vec<String>,
+    },
+    Document {
+        variable: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct MatchClause {
+    // Every node of the MATCH in order of appearance, a variable used twice appears twice
     pub patterns: Vec<Pattern>,
+    // Relationships between those nodes, a chain (a)-->(b)-->(c) gives two
+    pub relationships: Vec<RelationshipPattern>,
+    // Comma separated parts of the MATCH
+    pub paths: Vec<PathPattern>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PathPattern {
+    // p in p = (a)-->(b)
+    pub variable: Option<String>,
+    // shortestPath((a)-[*]-(b))
+    pub shortest: bool,
+    pub start: String,
+    // Variables of the relationships from start on, in order
+    pub relationships: Vec<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -199,6 +500,47 @@ pub struct Pattern {
     pub properties: Vec<Property>,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    // (left)-->(right)
+    Outgoing,
+    // (left)<--(right)
+    Incoming,
+    // (left)--(right)
+    Both,
+}
+
+impl Direction {
+    pub fn reverse(self) -> Self {
+        match self {
+            Direction::Outgoing => Direction::Incoming,
+            Direction::Incoming => Direction::Outgoing,
+            Direction::Both => Direction::Both,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RelationshipPattern {
+    pub variable: String,
+    // Any of the types matches, none matches every type
+    pub types: Vec<String>,
+    pub properties: Vec<Property>,
+    // Variables of the nodes on the left and on the right side in the query text
+    pub left: String,
+    pub right: String,
+    pub direction: Direction,
+    // Number of hops of a variable length relationship, e.g. *1..5
+    pub length: Option<Length>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Length {
+    pub min: usize,
+    // None is unbounded
+    pub max: Option<usize>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Property {
     pub key: String,
@@ -210,11 +552,24 @@ pub enum Value {
     String(String),
     Number(f64),
     Boolean(bool),
+    Null,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct ReturnClause {
+    pub distinct: bool,
     pub items: Vec<ReturnItem>,
+    pub order_by: Vec<OrderItem>,
+    pub skip: Option<usize>,
+    pub limit: Option<usize>,
+    // WITH ... WHERE filters the projected rows
+    pub where_clause: Option<Expression>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OrderItem {
+    pub expression: Expression,
+    pub descending: bool,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -225,183 +580,1122 @@ pub struct ReturnItem {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Property { variable: String, property: String },
+    Property {
+        variable: String,
+        property: String,
+    },
     Variable(String),
+    Literal(Value),
+    // [1, 2, n.age]
+    List(Vec<Expression>),
+    // {iterations: 20}
+    Map(Vec<(String, Expression)>),
+    Not(Box<Expression>),
+    Negate(Box<Expression>),
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    IsNull {
+        expression: Box<Expression>,
+        negated: bool,
+    },
+    // count(*) has no argument
+    Aggregate {
+        function: AggregateFunction,
+        distinct: bool,
+        argument: Option<Box<Expression>>,
+    },
+    // doc(n), the key value document referenced by a node
+    Document(Box<Expression>),
+    // doc(n).age, a property of a value that is not a variable
+    Field {
+        expression: Box<Expression>,
+        property: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AggregateFunction {
+    Count,
+    Collect,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "count" => Some(AggregateFunction::Count),
+            "collect" => Some(AggregateFunction::Collect),
+            "sum" => Some(AggregateFunction::Sum),
+            "avg" => Some(AggregateFunction::Avg),
+            "min" => Some(AggregateFunction::Min),
+            "max" => Some(AggregateFunction::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Collect => "collect",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOperator {
+    Or,
+    Xor,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    In,
+    StartsWith,
+    EndsWith,
+    Contains,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+impl BinaryOperator {
+    fn text(self) -> &'static str {
+        match self {
+            BinaryOperator::Or => "OR",
+            BinaryOperator::Xor => "XOR",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Equal => "=",
+            BinaryOperator::NotEqual => "<>",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::In => "IN",
+            BinaryOperator::StartsWith => "STARTS WITH",
+            BinaryOperator::EndsWith => "ENDS WITH",
+            BinaryOperator::Contains => "CONTAINS",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+        }
+    }
 }
 
 // Parser Implementation
 pub struct Parser {
     lexer: Lexer,
     current_token: Token,
+    location: Location,
+    parameters: serde_json::Map<String, serde_json::Value>,
+    anonymous_count: usize,
 }
 
 impl Parser {
     pub fn new(input: &str) -> Self {
         let mut lexer = Lexer::new(input);
         let current_token = lexer.next_token();
+        let location = lexer.location();
         Parser {
             lexer,
             current_token,
+            location,
+            parameters: serde_json::Map::new(),
+            anonymous_count: 0,
+        }
+    }
+
+    // $name placeholders are replaced by the values of the parameters while parsing,
+    // so they are never read as query text
+    pub fn with_parameters(
+        mut self,
+        parameters: serde_json::Map<String, serde_json::Value>,
+    ) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    // Error at the current token, text the lexer could not read is reported as such
+    fn error(&self, message: impl Into<String>) -> Error {
+        let message = match &self.current_token {
+            Token::Invalid(reason) => reason.clone(),
+            _ => message.into(),
+        };
+        Error::SyntaxError {
+            message,
+            location: self.location.clone(),
+        }
+    }
+
+    fn parameter_error(&self, message: impl Into<String>) -> Error {
+        Error::ParameterError {
+            message: message.into(),
+            location: self.location.clone(),
         }
     }
 
+    // Value of the $parameter at the current token
+    fn parameter(&self, name: &str) -> Result<serde_json::Value, Error> {
+        self.parameters
+            .get(name)
+            .cloned()
+            .ok_or_else(|| self.parameter_error(format!("Missing parameter ${}", name)))
+    }
+
+    // Names for nodes and relationships without variable, they can not clash with identifiers
+    fn anonymous_variable(&mut self) -> String {
+        self.anonymous_count += 1;
+        format!("  anon_{}", self.anonymous_count)
+    }
+
     fn advance(&mut self) {
         self.current_token = self.lexer.next_token();
+        self.location = self.lexer.location();
     }
 
-    fn expect_token(&mut self, expected: Token) -> Result<(), String> {
+    fn expect_token(&mut self, expected: Token) -> Result<(), Error> {
         if self.current_token == expected {
             self.advance();
             Ok(())
         } else {
-            Err(format!(
+            Err(self.error(format!(
                 "Expected {:?}, got {:?}",
                 expected, self.current_token
-            ))
+            )))
         }
     }
 
-    pub fn parse_query(&mut self) -> Result<Query, String> {
-        let match_clause = self.parse_match_clause()?;
-        let return_clause = self.parse_return_clause()?;
+    pub fn parse_query(&mut self) -> Result<Query, Error> {
+        let query = self.parse_query_part(true)?;
+        self.expect_token(Token::Eof)?;
+        Ok(query)
+    }
 
-        Ok(Query {
-            match_clause,
-            return_clause,
-        })
+    // A query or an index command
+    pub fn parse_statement(&mut self) -> Result<Statement, Error> {
+        let create_index = self.current_token == Token::Create
+            && matches!(self.peek_token(), Token::Identifier(word) if word.eq_ignore_ascii_case("INDEX"));
+        let drop_index = self.is_word("DROP");
+        if !create_index && !drop_index {
+            return Ok(Statement::Query(Box::new(self.parse_query()?)));
+        }
+
+        self.advance();
+        self.expect_word("INDEX")?;
+        self.expect_token(Token::On)?;
+        self.expect_token(Token::Colon)?;
+        let label = self.parse_name("label")?;
+        self.expect_token(Token::LeftParen)?;
+        let property = self.parse_name("property name")?;
+        self.expect_token(Token::RightParen)?;
+        let statement = if drop_index {
+            Statement::DropIndex { label, property }
+        } else {
+            // Like SQL indexes, string unless TYPE says otherwise
+            let field_type = if self.is_word("TYPE") {
+                self.advance();
+                let type_name = self.parse_name("index type after TYPE")?;
+                match type_name.to_lowercase().as_str() {
+                    "string" => FieldType::String,
+                    "number" => FieldType::Number,
+                    _ => {
+                        return Err(self.error(format!(
+                            "Unknown index type '{}', expected string or number",
+                            type_name
+                        )))
+                    }
+                }
+            } else {
+                FieldType::String
+            };
+            Statement::CreateIndex {
+                label,
+                property,
+                field_type,
+            }
+        };
+        self.expect_token(Token::Eof)?;
+        Ok(statement)
     }
 
-    fn parse_match_clause(&mut self) -> Result<MatchClause, String> {
-        self.expect_token(Token::Match)?;
+    fn peek_token(&self) -> Token {
+        self.lexer.clone().next_token()
+    }
 
-        let mut patterns = Vec::new();
-        patterns.push(self.parse_pattern()?);
+    // Words like INDEX are only keywords in one place, elsewhere they are names
+    fn is_word(&self, word: &str) -> bool {
+        matches!(&self.current_token, Token::Identifier(name) if name.eq_ignore_ascii_case(word))
+    }
 
-        while self.current_token == Token::Comma {
+    fn expect_word(&mut self, word: &str) -> Result<(), Error> {
+        if self.is_word(word) {
             self.advance();
-            patterns.push(self.parse_pattern()?);
+            Ok(())
+        } else {
+            Err(self.error(format!("Expected {}, got {:?}", word, self.current_token)))
         }
-
-        Ok(MatchClause { patterns })
     }
 
-    fn parse_pattern(&mut self) -> Result<Pattern, String> {
-        self.expect_token(Token::LeftParen)?;
-
-        let variable = match &self.current_token {
-            Token::Identifier(name) => {
-                let var = name.clone();
+    // MATCH ... WHERE ... updates, then RETURN or WITH and the next part
+    fn parse_query_part(&mut self, first: bool) -> Result<Query, Error> {
+        let call = if self.current_token == Token::Call {
+            Some(self.parse_call_clause()?)
+        } else {
+            None
+        };
+        // Consecutive MATCH clauses are joined into one, their WHERE conditions with AND
+        let mut match_clause = MatchClause::default();
+        let mut where_clause = None;
+        loop {
+            if self.current_token == Token::Match {
+                let next_match = self.parse_match_clause()?;
+                match_clause.patterns.extend(next_match.patterns);
+                match_clause.relationships.extend(next_match.relationships);
+                match_clause.paths.extend(next_match.paths);
+            }
+            if self.current_token == Token::Where {
                 self.advance();
-                var
+                let condition = self.parse_expression()?;
+                where_clause = Some(match where_clause {
+                    Some(left) => binary(BinaryOperator::And, left, condition),
+                    None => condition,
+                });
+            }
+            if self.current_token != Token::Match {
+                break;
+            }
+        }
+
+        let mut updates = Vec::new();
+        while let Some(update) = self.parse_update_clause()? {
+            updates.push(update);
+        }
+        if first && call.is_none() && match_clause.patterns.is_empty() && updates.is_empty() {
+            return Err(self.error(format!(
+                "Expected MATCH, CREATE, MERGE or CALL, got {:?}",
+                self.current_token
+            )));
+        }
+
+        let mut next = None;
+        let return_clause = match self.current_token {
+            Token::With => {
+                let clause = self.parse_return_clause(Token::With)?;
+                next = Some(Box::new(self.parse_query_part(false)?));
+                clause
+            }
+            Token::Return => self.parse_return_clause(Token::Return)?,
+            // A CALL on its own returns what it yields
+            Token::Eof if call.is_some() && match_clause.patterns.is_empty() => {
+                ReturnClause::default()
             }
-            _ => return Err("Expected identifier".to_string()),
+            _ if !updates.is_empty() => ReturnClause::default(),
+            _ => self.parse_return_clause(Token::Return)?,
         };
 
-        let mut labels = Vec::new();
-        let mut properties = Vec::new();
+        Ok(Query {
+            call,
+            match_clause,
+            where_clause,
+            updates,
+            return_clause,
+            next,
+        })
+    }
 
-        // Parse labels
-        while self.current_token == Token::Colon {
+    // CALL algo.pageRank('Person', 'KNOWS', {iterations: 20}) YIELD node, score AS rank
+    fn parse_call_clause(&mut self) -> Result<CallClause, Error> {
+        self.expect_token(Token::Call)?;
+        let mut procedure = self.parse_name("procedure name")?;
+        while self.current_token == Token::Dot {
             self.advance();
-            if let Token::Identifier(label) = &self.current_token {
-                labels.push(label.clone());
-                self.advance();
+            procedure = format!("{}.{}", procedure, self.parse_name("procedure name")?);
+        }
+
+        self.expect_token(Token::LeftParen)?;
+        let mut arguments = Vec::new();
+        while self.current_token != Token::RightParen {
+            arguments.push(self.parse_expression()?);
+            if self.current_token != Token::Comma {
+                break;
             }
+            self.advance();
         }
+        self.expect_token(Token::RightParen)?;
 
-        // Parse properties
-        if self.current_token == Token::LeftBrace {
+        let mut yields = Vec::new();
+        if self.current_token == Token::Yield {
             self.advance();
-            while self.current_token != Token::RightBrace {
-                properties.push(self.parse_property()?);
-                if self.current_token == Token::Comma {
+            loop {
+                let field = self.parse_name("yield field")?;
+                let variable = if self.current_token == Token::As {
                     self.advance();
+                    self.parse_name("variable")?
+                } else {
+                    field.clone()
+                };
+                yields.push((field, variable));
+                if self.current_token != Token::Comma {
+                    break;
                 }
+                self.advance();
             }
-            self.advance(); // consume RightBrace
         }
 
-        self.expect_token(Token::RightParen)?;
-
-        Ok(Pattern {
-            variable,
-            labels,
-            properties,
+        Ok(CallClause {
+            procedure,
+            arguments,
+            yields,
         })
     }
 
-    fn parse_property(&mut self) -> Result<Property, String> {
-        let key = match &self.current_token {
-            Token::Identifier(name) => {
-                let k = name.clone();
+    fn parse_update_clause(&mut self) -> Result<Option<UpdateClause>, Error> {
+        let update = match self.current_token {
+            Token::Create => {
                 self.advance();
-                k
+                let mut pattern = MatchClause::default();
+                self.parse_path(&mut pattern)?;
+                while self.current_token == Token::Comma {
+                    self.advance();
+                    self.parse_path(&mut pattern)?;
+                }
+                UpdateClause::Create(pattern)
             }
-            _ => return Err("Expected property key".to_string()),
-        };
-
-        self.expect_token(Token::Colon)?;
-
-        let value = {
-            let token = &self.current_token;
-            let val = match token {
-                Token::String(s) => Value::String(s.clone()),
-                Token::Number(n) => Value::Number(*n),
-                _ => return Err("Expected property value".to_string()),
-            };
-            self.advance();
-            val
+            Token::Merge => {
+                self.advance();
+                let mut merge = MergeClause {
+                    pattern: MatchClause::default(),
+                    on_create: Vec::new(),
+                    on_match: Vec::new(),
+                };
+                self.parse_path(&mut merge.pattern)?;
+                while self.current_token == Token::On {
+                    self.advance();
+                    let items = match self.current_token {
+                        Token::Create => &mut merge.on_create,
+                        Token::Match => &mut merge.on_match,
+                        _ => {
+                            return Err(self.error(format!(
+                                "Expected CREATE or MATCH after ON, got {:?}",
+                                self.current_token
+                            )))
+                        }
+                    };
+                    self.advance();
+                    self.expect_token(Token::Set)?;
+                    items.extend(self.parse_set_items()?);
+                }
+                UpdateClause::Merge(merge)
+            }
+            Token::Set => {
+                self.advance();
+                UpdateClause::Set(self.parse_set_items()?)
+            }
+            Token::Remove => {
+                self.advance();
+                let mut items = Vec::new();
+                loop {
+                    let variable = self.parse_name("variable")?;
+                    if self.is_document_target(&variable) {
+                        let variable = self.parse_document_target()?;
+                        items.push(RemoveItem::Document { variable });
+                    } else if self.current_token == Token::Dot {
+                        self.advance();
+                        let property = self.parse_name("property name")?;
+                        items.push(RemoveItem::Property { variable, property });
+                    } else {
+                        let labels = self.parse_labels()?;
+                        items.push(RemoveItem::Labels { variable, labels });
+                    }
+                    if self.current_token != Token::Comma {
+                        break;
+                    }
+                    self.advance();
+                }
+                UpdateClause::Remove(items)
+            }
+            Token::Delete | Token::Detach => {
+                let detach = self.current_token == Token::Detach;
+                if detach {
+                    self.advance();
+                }
+                self.expect_token(Token::Delete)?;
+                let mut variables = vec![self.parse_name("variable")?];
+                while self.current_token == Token::Comma {
+                    self.advance();
+                    variables.push(self.parse_name("variable")?);
+                }
+                UpdateClause::Delete { variables, detach }
+            }
+            _ => return Ok(None),
         };
-
-        Ok(Property { key, value })
+        Ok(Some(update))
     }
 
-    fn parse_return_clause(&mut self) -> Result<ReturnClause, String> {
-        self.expect_token(Token::Return)?;
-
+    // n.prop = value, n:Label, ...
+    fn parse_set_items(&mut self) -> Result<Vec<SetItem>, Error> {
         let mut items = Vec::new();
         loop {
-            items.push(self.parse_return_item()?);
-
+            let variable = self.parse_name("variable")?;
+            if self.is_document_target(&variable) {
+                let variable = self.parse_document_target()?;
+                self.expect_token(Token::Equal)?;
+                let value = self.parse_expression()?;
+                items.push(SetItem::Document { variable, value });
+            } else if self.current_token == Token::Dot {
+                self.advance();
+                let property = self.parse_name("property name")?;
+                self.expect_token(Token::Equal)?;
+                let value = self.parse_expression()?;
+                items.push(SetItem::Property {
+                    variable,
+                    property,
+                    value,
+                });
+            } else {
+                let labels = self.parse_labels()?;
+                items.push(SetItem::Labels { variable, labels });
+            }
             if self.current_token != Token::Comma {
                 break;
             }
             self.advance();
         }
+        Ok(items)
+    }
 
-        Ok(ReturnClause { items })
+    // SET doc(n) = ... and REMOVE doc(n) change the document a node references
+    fn is_document_target(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case("doc") && self.current_token == Token::LeftParen
     }
 
-    fn parse_return_item(&mut self) -> Result<ReturnItem, String> {
-        let expression = self.parse_expression()?;
+    fn parse_document_target(&mut self) -> Result<String, Error> {
+        self.expect_token(Token::LeftParen)?;
+        let variable = self.parse_name("variable")?;
+        self.expect_token(Token::RightParen)?;
+        Ok(variable)
+    }
 
-        let alias = if self.current_token == Token::As {
+    // :Label:Other
+    fn parse_labels(&mut self) -> Result<Vec<String>, Error> {
+        let mut labels = Vec::new();
+        self.expect_token(Token::Colon)?;
+        labels.push(self.parse_name("label")?);
+        while self.current_token == Token::Colon {
             self.advance();
-            if let Token::Identifier(name) = &self.current_token {
-                let alias = Some(name.clone());
-                self.advance();
-                alias
-            } else {
-                return Err("Expected identifier after AS".to_string());
-            }
-        } else {
-            None
-        };
-
-        Ok(ReturnItem { expression, alias })
+            labels.push(self.parse_name("label")?);
+        }
+        Ok(labels)
     }
 
-    fn parse_expression(&mut self) -> Result<Expression, String> {
-        let variable = match &self.current_token {
-            Token::Identifier(name) => {
-                let var = name.clone();
-                self.advance();
-                var
-            }
-            _ => return Err("Expected identifier".to_string()),
-        };
+    fn parse_match_clause(&mut self) -> Result<MatchClause, Error> {
+        self.expect_token(Token::Match)?;
 
-        if self.current_token == Token::Dot {
+        let mut match_clause = MatchClause::default();
+        self.parse_path(&mut match_clause)?;
+
+        while self.current_token == Token::Comma {
+            self.advance();
+            self.parse_path(&mut match_clause)?;
+        }
+
+        Ok(match_clause)
+    }
+
+    // p = shortestPath((a)-[r:TYPE]->(b)<-(c)...) adds its nodes and relationships to the MATCH
+    fn parse_path(&mut self, match_clause: &mut MatchClause) -> Result<(), Error> {
+        let mut variable = None;
+        let mut shortest = false;
+        if let Token::Identifier(name) = &self.current_token {
+            let name = name.clone();
+            self.advance();
+            if self.current_token == Token::Equal {
+                self.advance();
+                variable = Some(name);
+                if let Token::Identifier(name) = &self.current_token {
+                    let name = name.clone();
+                    self.advance();
+                    shortest = self.expect_shortest_path(&name)?;
+                }
+            } else {
+                shortest = self.expect_shortest_path(&name)?;
+            }
+        }
+        if shortest {
+            self.expect_token(Token::LeftParen)?;
+        }
+
+        let mut left = self.parse_pattern()?;
+        let mut path = PathPattern {
+            variable,
+            shortest,
+            start: left.variable.clone(),
+            relationships: Vec::new(),
+        };
+        while matches!(self.current_token, Token::Minus | Token::LeftArrow) {
+            let mut relationship = self.parse_relationship()?;
+            let right = self.parse_pattern()?;
+            relationship.left = left.variable.clone();
+            relationship.right = right.variable.clone();
+            path.relationships.push(relationship.variable.clone());
+            match_clause.relationships.push(relationship);
+            match_clause.patterns.push(left);
+            left = right;
+        }
+        match_clause.patterns.push(left);
+
+        if shortest {
+            self.expect_token(Token::RightParen)?;
+            if path.relationships.len() != 1 {
+                return Err(self.error("shortestPath needs a pattern with one relationship"));
+            }
+        }
+        match_clause.paths.push(path);
+        Ok(())
+    }
+
+    fn expect_shortest_path(&self, name: &str) -> Result<bool, Error> {
+        if name.eq_ignore_ascii_case("shortestPath") {
+            Ok(true)
+        } else {
+            Err(self.error(format!("Expected pattern or shortestPath, got '{}'", name)))
+        }
+    }
+
+    // *, *3, *1..5, *..5 or *2..
+    fn parse_length(&mut self) -> Result<Length, Error> {
+        self.expect_token(Token::Asterisk)?;
+        let mut min = None;
+        if let Token::Number(n) = self.current_token {
+            min = Some(n as usize);
+            self.advance();
+        }
+        if self.current_token != Token::Dot {
+            return Ok(match min {
+                Some(n) => Length {
+                    min: n,
+                    max: Some(n),
+                },
+                None => Length { min: 1, max: None },
+            });
+        }
+        self.advance();
+        self.expect_token(Token::Dot)?;
+        let mut max = None;
+        if let Token::Number(n) = self.current_token {
+            max = Some(n as usize);
+            self.advance();
+        }
+        let length = Length {
+            min: min.unwrap_or(1),
+            max,
+        };
+        if length.max.is_some_and(|max| max < length.min) {
+            return Err(self.error(format!(
+                "Relationship length *{}..{} is empty",
+                length.min,
+                max.unwrap_or_default()
+            )));
+        }
+        Ok(length)
+    }
+
+    // -[r:TYPE {key: value}]-> with both ends left to parse_path
+    fn parse_relationship(&mut self) -> Result<RelationshipPattern, Error> {
+        let incoming = self.current_token == Token::LeftArrow;
+        self.advance();
+
+        let mut variable = None;
+        let mut types = Vec::new();
+        let mut properties = Vec::new();
+        let mut length = None;
+        if self.current_token == Token::LeftBracket {
+            self.advance();
+            if let Token::Identifier(name) = &self.current_token {
+                variable = Some(name.clone());
+                self.advance();
+            }
+            if self.current_token == Token::Colon {
+                self.advance();
+                types.push(self.parse_name("relationship type")?);
+                while self.current_token == Token::Pipe {
+                    self.advance();
+                    if self.current_token == Token::Colon {
+                        self.advance();
+                    }
+                    types.push(self.parse_name("relationship type")?);
+                }
+            }
+            if self.current_token == Token::Asterisk {
+                length = Some(self.parse_length()?);
+            }
+            if self.current_token == Token::LeftBrace {
+                properties = self.parse_properties()?;
+            }
+            self.expect_token(Token::RightBracket)?;
+        }
+
+        let outgoing = match self.current_token {
+            Token::RightArrow => true,
+            Token::Minus => false,
+            _ => {
+                return Err(self.error(format!(
+                    "Expected - or -> after relationship, got {:?}",
+                    self.current_token
+                )))
+            }
+        };
+        self.advance();
+
+        let direction = match (incoming, outgoing) {
+            (false, true) => Direction::Outgoing,
+            (true, false) => Direction::Incoming,
+            (false, false) => Direction::Both,
+            (true, true) => {
+                return Err(self.error("Relationship can not point both ways, use -[]- instead"))
+            }
+        };
+
+        Ok(RelationshipPattern {
+            variable: match variable {
+                Some(variable) => variable,
+                None => self.anonymous_variable(),
+            },
+            types,
+            properties,
+            left: String::new(),
+            right: String::new(),
+            direction,
+            length,
+        })
+    }
+
+    fn parse_name(&mut self, what: &str) -> Result<String, Error> {
+        match &self.current_token {
+            Token::Identifier(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            token => Err(self.error(format!("Expected {}, got {:?}", what, token))),
+        }
+    }
+
+    fn parse_properties(&mut self) -> Result<Vec<Property>, Error> {
+        let mut properties = Vec::new();
+        self.expect_token(Token::LeftBrace)?;
+        while self.current_token != Token::RightBrace {
+            properties.push(self.parse_property()?);
+            if self.current_token == Token::Comma {
+                self.advance();
+            }
+        }
+        self.advance(); // consume RightBrace
+        Ok(properties)
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, Error> {
+        self.expect_token(Token::LeftParen)?;
+
+        let variable = match &self.current_token {
+            Token::Identifier(name) => {
+                let var = name.clone();
+                self.advance();
+                var
+            }
+            _ => self.anonymous_variable(),
+        };
+
+        let mut labels = Vec::new();
+        let mut properties = Vec::new();
+
+        // Parse labels
+        while self.current_token == Token::Colon {
+            self.advance();
+            if let Token::Identifier(label) = &self.current_token {
+                labels.push(label.clone());
+                self.advance();
+            }
+        }
+
+        // Parse properties
+        if self.current_token == Token::LeftBrace {
+            properties = self.parse_properties()?;
+        }
+
+        self.expect_token(Token::RightParen)?;
+
+        Ok(Pattern {
+            variable,
+            labels,
+            properties,
+        })
+    }
+
+    fn parse_property(&mut self) -> Result<Property, Error> {
+        let key = match &self.current_token {
+            Token::Identifier(name) => {
+                let k = name.clone();
+                self.advance();
+                k
+            }
+            _ => return Err(self.error("Expected property key")),
+        };
+
+        self.expect_token(Token::Colon)?;
+
+        let value = self.parse_value()?;
+
+        Ok(Property { key, value })
+    }
+
+    fn parse_value(&mut self) -> Result<Value, Error> {
+        let negative = self.current_token == Token::Minus;
+        if negative {
+            self.advance();
+        }
+        let value = match &self.current_token {
+            Token::Number(n) if negative => Value::Number(-n),
+            Token::Number(n) => Value::Number(*n),
+            _ if negative => return Err(self.error("Expected number after -")),
+            Token::String(s) => Value::String(s.clone()),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("true") => Value::Boolean(true),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("false") => Value::Boolean(false),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("null") => Value::Null,
+            Token::Parameter(name) => match self.parameter(name)? {
+                serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+                    return Err(self.parameter_error(format!(
+                        "Parameter ${} must be a string, number, boolean or null",
+                        name
+                    )))
+                }
+                value => json_to_value(value),
+            },
+            _ => return Err(self.error("Expected property value")),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    // RETURN or WITH [DISTINCT] items [ORDER BY ...] [SKIP n] [LIMIT n], WITH can add WHERE
+    fn parse_return_clause(&mut self, keyword: Token) -> Result<ReturnClause, Error> {
+        let with = keyword == Token::With;
+        self.expect_token(keyword)?;
+
+        let mut clause = ReturnClause::default();
+        if self.current_token == Token::Distinct {
+            self.advance();
+            clause.distinct = true;
+        }
+        loop {
+            clause.items.push(self.parse_return_item()?);
+
+            if self.current_token != Token::Comma {
+                break;
+            }
+            self.advance();
+        }
+
+        if self.current_token == Token::Order {
+            self.advance();
+            self.expect_token(Token::By)?;
+            loop {
+                let expression = self.parse_expression()?;
+                let descending = match self.current_token {
+                    Token::Desc => {
+                        self.advance();
+                        true
+                    }
+                    Token::Asc => {
+                        self.advance();
+                        false
+                    }
+                    _ => false,
+                };
+                clause.order_by.push(OrderItem {
+                    expression,
+                    descending,
+                });
+                if self.current_token != Token::Comma {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        if self.current_token == Token::Skip {
+            self.advance();
+            clause.skip = Some(self.parse_count("SKIP")?);
+        }
+        if self.current_token == Token::Limit {
+            self.advance();
+            clause.limit = Some(self.parse_count("LIMIT")?);
+        }
+        if with && self.current_token == Token::Where {
+            self.advance();
+            clause.where_clause = Some(self.parse_expression()?);
+        }
+
+        Ok(clause)
+    }
+
+    fn parse_count(&mut self, what: &str) -> Result<usize, Error> {
+        match self.current_token {
+            Token::Number(n) if n >= 0.0 && n.fract() == 0.0 => {
+                self.advance();
+                Ok(n as usize)
+            }
+            Token::Parameter(ref name) => {
+                let count = self.parameter(name)?.as_u64().ok_or_else(|| {
+                    self.parameter_error(format!(
+                        "Parameter ${} after {} must be a whole number",
+                        name, what
+                    ))
+                })?;
+                self.advance();
+                Ok(count as usize)
+            }
+            _ => Err(self.error(format!(
+                "Expected a whole number after {}, got {:?}",
+                what, self.current_token
+            ))),
+        }
+    }
+
+    fn parse_return_item(&mut self) -> Result<ReturnItem, Error> {
+        let expression = self.parse_expression()?;
+
+        let alias = if self.current_token == Token::As {
+            self.advance();
+            if let Token::Identifier(name) = &self.current_token {
+                let alias = Some(name.clone());
+                self.advance();
+                alias
+            } else {
+                return Err(self.error("Expected identifier after AS"));
+            }
+        } else {
+            None
+        };
+
+        Ok(ReturnItem { expression, alias })
+    }
+
+    // Operators from the loosest to the tightest binding:
+    // OR, XOR, AND, NOT, comparisons, + -, * / %, unary -
+    fn parse_expression(&mut self) -> Result<Expression, Error> {
+        let mut left = self.parse_xor()?;
+        while self.current_token == Token::Or {
+            self.advance();
+            let right = self.parse_xor()?;
+            left = binary(BinaryOperator::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_xor(&mut self) -> Result<Expression, Error> {
+        let mut left = self.parse_and()?;
+        while self.current_token == Token::Xor {
+            self.advance();
+            let right = self.parse_and()?;
+            left = binary(BinaryOperator::Xor, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, Error> {
+        let mut left = self.parse_not()?;
+        while self.current_token == Token::And {
+            self.advance();
+            let right = self.parse_not()?;
+            left = binary(BinaryOperator::And, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, Error> {
+        if self.current_token == Token::Not {
+            self.advance();
+            return Ok(Expression::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, Error> {
+        let left = self.parse_additive()?;
+        let operator = match self.current_token {
+            Token::Equal => BinaryOperator::Equal,
+            Token::NotEqual => BinaryOperator::NotEqual,
+            Token::Less => BinaryOperator::Less,
+            Token::LessEqual => BinaryOperator::LessEqual,
+            Token::Greater => BinaryOperator::Greater,
+            Token::GreaterEqual => BinaryOperator::GreaterEqual,
+            Token::In => BinaryOperator::In,
+            Token::Contains => BinaryOperator::Contains,
+            Token::Starts | Token::Ends => {
+                let operator = if self.current_token == Token::Starts {
+                    BinaryOperator::StartsWith
+                } else {
+                    BinaryOperator::EndsWith
+                };
+                self.advance();
+                if self.current_token != Token::With {
+                    return Err(self.error(format!("Expected WITH, got {:?}", self.current_token)));
+                }
+                operator
+            }
+            Token::Is => {
+                self.advance();
+                let negated = self.current_token == Token::Not;
+                if negated {
+                    self.advance();
+                }
+                match &self.current_token {
+                    Token::Identifier(word) if word.eq_ignore_ascii_case("null") => self.advance(),
+                    token => {
+                        return Err(self.error(format!("Expected NULL after IS, got {:?}", token)))
+                    }
+                }
+                return Ok(Expression::IsNull {
+                    expression: Box::new(left),
+                    negated,
+                });
+            }
+            _ => return Ok(left),
+        };
+        self.advance();
+        let right = self.parse_additive()?;
+        Ok(binary(operator, left, right))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expression, Error> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let operator = match self.current_token {
+                Token::Plus => BinaryOperator::Add,
+                Token::Minus => BinaryOperator::Subtract,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_multiplicative()?;
+            left = binary(operator, left, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expression, Error> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let operator = match self.current_token {
+                Token::Asterisk => BinaryOperator::Multiply,
+                Token::Slash => BinaryOperator::Divide,
+                Token::Percent => BinaryOperator::Modulo,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_unary()?;
+            left = binary(operator, left, right);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, Error> {
+        if self.current_token != Token::Minus {
+            return self.parse_atom();
+        }
+        self.advance();
+        Ok(match self.parse_unary()? {
+            Expression::Literal(Value::Number(n)) => Expression::Literal(Value::Number(-n)),
+            expression => Expression::Negate(Box::new(expression)),
+        })
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression, Error> {
+        let variable = match &self.current_token {
+            Token::LeftParen => {
+                self.advance();
+                let expression = self.parse_expression()?;
+                self.expect_token(Token::RightParen)?;
+                return Ok(expression);
+            }
+            Token::LeftBracket => {
+                self.advance();
+                let mut items = Vec::new();
+                while self.current_token != Token::RightBracket {
+                    items.push(self.parse_expression()?);
+                    if self.current_token != Token::Comma {
+                        break;
+                    }
+                    self.advance();
+                }
+                self.expect_token(Token::RightBracket)?;
+                return Ok(Expression::List(items));
+            }
+            Token::LeftBrace => {
+                self.advance();
+                let mut entries = Vec::new();
+                while self.current_token != Token::RightBrace {
+                    let key = self.parse_name("map key")?;
+                    self.expect_token(Token::Colon)?;
+                    entries.push((key, self.parse_expression()?));
+                    if self.current_token != Token::Comma {
+                        break;
+                    }
+                    self.advance();
+                }
+                self.expect_token(Token::RightBrace)?;
+                return Ok(Expression::Map(entries));
+            }
+            Token::Identifier(word)
+                if ["true", "false", "null"]
+                    .iter()
+                    .any(|literal| word.eq_ignore_ascii_case(literal)) =>
+            {
+                return Ok(Expression::Literal(self.parse_value()?));
+            }
+            Token::String(_) | Token::Number(_) => {
+                return Ok(Expression::Literal(self.parse_value()?));
+            }
+            Token::Parameter(name) => {
+                let value = self.parameter(name)?;
+                self.advance();
+                return Ok(json_to_expression(value));
+            }
+            Token::Identifier(name) => {
+                let var = name.clone();
+                self.advance();
+                var
+            }
+            token => return Err(self.error(format!("Expected expression, got {:?}", token))),
+        };
+
+        if self.current_token == Token::LeftParen {
+            let mut expression = self.parse_function(&variable)?;
+            while self.current_token == Token::Dot {
+                self.advance();
+                let property = self.parse_name("property name")?;
+                expression = Expression::Field {
+                    expression: Box::new(expression),
+                    property,
+                };
+            }
+            return Ok(expression);
+        }
+
+        if self.current_token == Token::Dot {
             self.advance();
             if let Token::Identifier(property) = &self.current_token {
                 let prop = property.clone();
@@ -411,14 +1705,1039 @@ impl Parser {
                     property: prop,
                 })
             } else {
-                Err("Expected property name".to_string())
+                Err(self.error("Expected property name"))
+            }
+        } else {
+            Ok(Expression::Variable(variable))
+        }
+    }
+
+    // count(*), count(DISTINCT n.name), collect(n) ...
+    fn parse_function(&mut self, name: &str) -> Result<Expression, Error> {
+        if name.eq_ignore_ascii_case("doc") {
+            self.expect_token(Token::LeftParen)?;
+            let argument = self.parse_expression()?;
+            self.expect_token(Token::RightParen)?;
+            return Ok(Expression::Document(Box::new(argument)));
+        }
+        let function = AggregateFunction::from_name(name)
+            .ok_or_else(|| self.error(format!("Unknown function '{}'", name)))?;
+        self.expect_token(Token::LeftParen)?;
+
+        let distinct = self.current_token == Token::Distinct;
+        if distinct {
+            self.advance();
+        }
+        let argument = if function == AggregateFunction::Count
+            && !distinct
+            && self.current_token == Token::Asterisk
+        {
+            self.advance();
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+        self.expect_token(Token::RightParen)?;
+
+        Ok(Expression::Aggregate {
+            function,
+            distinct,
+            argument,
+        })
+    }
+}
+
+fn binary(operator: BinaryOperator, left: Expression, right: Expression) -> Expression {
+    Expression::Binary {
+        operator,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+fn json_to_value(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::String(s) => Value::String(s),
+        serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+        serde_json::Value::Bool(b) => Value::Boolean(b),
+        _ => Value::Null,
+    }
+}
+
+// Parameters are bound as values, lists and maps become list and map expressions
+fn json_to_expression(value: serde_json::Value) -> Expression {
+    match value {
+        serde_json::Value::Array(items) => {
+            Expression::List(items.into_iter().map(json_to_expression).collect())
+        }
+        serde_json::Value::Object(entries) => Expression::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key, json_to_expression(value)))
+                .collect(),
+        ),
+        value => Expression::Literal(json_to_value(value)),
+    }
+}
+
+// Code Generator: Transforms the AST into graph VM instructions
+pub struct CodeGenerator {
+    pub instructions: Vec<Instruction>,
+    // Names of the returned columns, in RETURN order
+    pub columns: Vec<String>,
+}
+
+impl CodeGenerator {
+    pub fn new() -> Self {
+        CodeGenerator {
+            instructions: Vec::new(),
+            columns: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    pub fn generate_statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Query(query) => self.generate(query),
+            Statement::CreateIndex {
+                label,
+                property,
+                field_type,
+            } => {
+                self.emit(Instruction::CreateIndex {
+                    label: label.clone(),
+                    property: property.clone(),
+                    field_type: field_type.clone(),
+                });
+                Ok(())
+            }
+            Statement::DropIndex { label, property } => {
+                self.emit(Instruction::DropIndex {
+                    label: label.clone(),
+                    property: property.clone(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    pub fn generate(&mut self, query: &Query) -> Result<(), String> {
+        let mut bound = HashSet::new();
+        let mut part = query;
+        loop {
+            if let Some(call) = &part.call {
+                self.generate_call(call, &mut bound)?;
+            }
+            self.generate_match(&part.match_clause, part.where_clause.as_ref(), &mut bound)?;
+            if let Some(condition) = &part.where_clause {
+                self.generate_filter(condition)?;
+            }
+            for update in part.updates.iter() {
+                self.generate_update(update, &mut bound)?;
+            }
+
+            let Some(next) = &part.next else {
+                break;
+            };
+            self.generate_projection(&part.return_clause, &mut bound, false)?;
+            part = next;
+        }
+
+        // A CALL on its own returns everything it yields
+        if let (Some(call), true) = (&part.call, part.return_clause.items.is_empty()) {
+            if part.updates.is_empty() {
+                let items = self.call_variables(call)?;
+                let clause = ReturnClause {
+                    items: items
+                        .into_iter()
+                        .map(|variable| ReturnItem {
+                            expression: Expression::Variable(variable),
+                            alias: None,
+                        })
+                        .collect(),
+                    ..ReturnClause::default()
+                };
+                return self.generate_projection(&clause, &mut bound, true);
+            }
+        }
+
+        // Write queries without RETURN report what they changed
+        if part.return_clause.items.is_empty() {
+            self.columns = Statistics::COLUMNS
+                .iter()
+                .map(|column| column.to_string())
+                .collect();
+            self.emit(Instruction::ReturnStatistics);
+            return Ok(());
+        }
+        self.generate_projection(&part.return_clause, &mut bound, true)
+    }
+
+    fn generate_call(
+        &mut self,
+        call: &CallClause,
+        bound: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        let procedure = Procedure::from_name(&call.procedure)
+            .ok_or(format!("Unknown procedure '{}'", call.procedure))?;
+        let fields = procedure.fields();
+
+        let mut yields = Vec::new();
+        if call.yields.is_empty() {
+            for (index, field) in fields.iter().enumerate() {
+                yields.push((index, field.to_string()));
+            }
+        }
+        for (field, variable) in call.yields.iter() {
+            let index = fields.iter().position(|name| name == field).ok_or(format!(
+                "Procedure {} yields {}, not '{}'",
+                call.procedure,
+                fields.join(", "),
+                field
+            ))?;
+            yields.push((index, variable.clone()));
+        }
+        for (_, variable) in yields.iter() {
+            if !bound.insert(variable.clone()) {
+                return Err(format!("Variable '{}' is already defined", variable));
+            }
+        }
+
+        for argument in call.arguments.iter() {
+            if contains_aggregate(argument) {
+                return Err("Aggregate functions are not allowed in CALL".to_string());
+            }
+        }
+        self.emit(Instruction::Call {
+            procedure,
+            arguments: call.arguments.iter().map(generate_expression).collect(),
+            yields,
+        });
+        Ok(())
+    }
+
+    // Variables a CALL binds, in yield order
+    fn call_variables(&self, call: &CallClause) -> Result<Vec<String>, String> {
+        if !call.yields.is_empty() {
+            return Ok(call
+                .yields
+                .iter()
+                .map(|(_, variable)| variable.clone())
+                .collect());
+        }
+        let procedure = Procedure::from_name(&call.procedure)
+            .ok_or(format!("Unknown procedure '{}'", call.procedure))?;
+        Ok(procedure
+            .fields()
+            .iter()
+            .map(|field| field.to_string())
+            .collect())
+    }
+
+    fn generate_filter(&mut self, condition: &Expression) -> Result<(), String> {
+        if contains_aggregate(condition) {
+            return Err("Aggregate functions are not allowed in WHERE".to_string());
+        }
+        self.emit(Instruction::Filter {
+            predicate: generate_expression(condition),
+        });
+        Ok(())
+    }
+
+    // RETURN or WITH. Items without aggregate functions are the grouping keys
+    // when any item aggregates, WITH keeps only the projected variables bound
+    fn generate_projection(
+        &mut self,
+        clause: &ReturnClause,
+        bound: &mut HashSet<String>,
+        is_return: bool,
+    ) -> Result<(), String> {
+        let aggregating = clause
+            .items
+            .iter()
+            .any(|item| contains_aggregate(&item.expression));
+
+        let mut columns: Vec<String> = Vec::new();
+        let mut items = Vec::new();
+        let mut keys = Vec::new();
+        let mut aggregations = Vec::new();
+        for item in clause.items.iter() {
+            let name = match (&item.alias, &item.expression) {
+                (Some(alias), _) => alias.clone(),
+                (None, Expression::Variable(variable)) => variable.clone(),
+                (None, expression) if is_return => expression_name(expression),
+                (None, expression) => {
+                    return Err(format!(
+                        "Expression '{}' in WITH needs an alias",
+                        expression_name(expression)
+                    ))
+                }
+            };
+            if columns.contains(&name) {
+                return Err(format!("Column '{}' is returned more than once", name));
+            }
+            columns.push(name.clone());
+
+            let evaluator = if !aggregating {
+                generate_expression(&item.expression)
+            } else if contains_aggregate(&item.expression) {
+                generate_expression(&extract_aggregates(&item.expression, &mut aggregations)?)
+            } else {
+                let key = format!("  key_{}", keys.len());
+                keys.push((key.clone(), generate_expression(&item.expression)));
+                generate_expression(&Expression::Variable(key))
+            };
+            items.push((name, evaluator));
+        }
+
+        if aggregating {
+            self.emit(Instruction::Aggregate { keys, aggregations });
+        }
+        self.emit(Instruction::Project { items });
+        if clause.distinct {
+            self.emit(Instruction::Distinct {
+                columns: columns.clone(),
+            });
+        }
+        if !clause.order_by.is_empty() {
+            let mut keys = Vec::new();
+            for item in clause.order_by.iter() {
+                if contains_aggregate(&item.expression) {
+                    return Err(
+                        "Aggregate functions in ORDER BY need to be returned with an alias"
+                            .to_string(),
+                    );
+                }
+                keys.push((generate_expression(&item.expression), item.descending));
             }
-        } else {
-            Ok(Expression::Variable(variable))
+            self.emit(Instruction::Sort { keys });
+        }
+        if clause.skip.is_some() || clause.limit.is_some() {
+            self.emit(Instruction::Slice {
+                skip: clause.skip.unwrap_or_default(),
+                limit: clause.limit,
+            });
+        }
+
+        if is_return {
+            self.columns = columns.clone();
+            self.emit(Instruction::Return { columns });
+            return Ok(());
+        }
+        *bound = columns.iter().cloned().collect();
+        self.emit(Instruction::Scope { columns });
+        if let Some(condition) = &clause.where_clause {
+            self.generate_filter(condition)?;
+        }
+        Ok(())
+    }
+
+    // Relationships are followed from a node that is already bound,
+    // so only the first node of every path is looked up among all nodes
+    // The WHERE condition only helps to choose indexes, it is checked by a Filter afterwards
+    fn generate_match(
+        &mut self,
+        match_clause: &MatchClause,
+        condition: Option<&Expression>,
+        bound: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        let mut relationship_variables: Vec<String> = Vec::new();
+        let shortest: HashSet<&String> = match_clause
+            .paths
+            .iter()
+            .filter(|path| path.shortest)
+            .flat_map(|path| path.relationships.iter())
+            .collect();
+
+        for relationship in match_clause.relationships.iter() {
+            let filter = RelationshipFilter {
+                types: relationship.types.clone(),
+                properties: properties_to_json(&relationship.properties),
+            };
+
+            // Both ends of a shortest path are matched first, then searched between
+            if shortest.contains(&relationship.variable) {
+                for variable in [&relationship.left, &relationship.right] {
+                    if !bound.contains(variable) {
+                        self.generate_node(match_clause, condition, variable, bound);
+                    }
+                }
+                let length = relationship.length.unwrap_or(Length {
+                    min: 1,
+                    max: Some(1),
+                });
+                if length.min > 1 {
+                    return Err("shortestPath supports a minimal length of 0 or 1".to_string());
+                }
+                self.emit(Instruction::ShortestPath {
+                    from: relationship.left.clone(),
+                    variable: relationship.variable.clone(),
+                    filter,
+                    direction: relationship.direction,
+                    max_length: length.max,
+                    to: relationship.right.clone(),
+                    distinct_from: relationship_variables.clone(),
+                });
+                relationship_variables.push(relationship.variable.clone());
+                bound.insert(relationship.variable.clone());
+                continue;
+            }
+
+            if !bound.contains(&relationship.left) && !bound.contains(&relationship.right) {
+                self.generate_node(match_clause, condition, &relationship.left, bound);
+            }
+            let reversed = !bound.contains(&relationship.left);
+            let (from, to, direction) = if reversed {
+                (
+                    &relationship.right,
+                    &relationship.left,
+                    relationship.direction.reverse(),
+                )
+            } else {
+                (
+                    &relationship.left,
+                    &relationship.right,
+                    relationship.direction,
+                )
+            };
+
+            self.emit(Instruction::Expand {
+                from: from.clone(),
+                variable: relationship.variable.clone(),
+                filter,
+                direction,
+                length: relationship.length,
+                reversed,
+                to: to.clone(),
+                distinct_from: relationship_variables.clone(),
+            });
+            relationship_variables.push(relationship.variable.clone());
+            bound.insert(relationship.variable.clone());
+            let to = to.clone();
+            bound.insert(to.clone());
+            self.generate_node(match_clause, condition, &to, bound);
+        }
+
+        for pattern in match_clause.patterns.iter() {
+            if !bound.contains(&pattern.variable) {
+                self.generate_node(match_clause, condition, &pattern.variable, bound);
+            }
+        }
+
+        self.generate_paths(match_clause, bound)
+    }
+
+    fn generate_paths(
+        &mut self,
+        match_clause: &MatchClause,
+        bound: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        for path in match_clause.paths.iter() {
+            if let Some(variable) = &path.variable {
+                if !bound.insert(variable.clone()) {
+                    return Err(format!("Variable '{}' is already defined", variable));
+                }
+                self.emit(Instruction::BindPath {
+                    variable: variable.clone(),
+                    start: path.start.clone(),
+                    relationships: path.relationships.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_update(
+        &mut self,
+        update: &UpdateClause,
+        bound: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        match update {
+            UpdateClause::Create(pattern) => self.generate_create(pattern, bound)?,
+            UpdateClause::Merge(merge) => {
+                let mut generator = CodeGenerator::new();
+                generator.generate_match(&merge.pattern, None, &mut bound.clone())?;
+                let match_instructions = generator.instructions;
+
+                let mut generator = CodeGenerator::new();
+                generator.generate_create(&merge.pattern, bound)?;
+                let create_instructions = generator.instructions;
+
+                self.emit(Instruction::Merge {
+                    match_instructions,
+                    create_instructions,
+                    on_match: set_instructions(&merge.on_match, bound)?,
+                    on_create: set_instructions(&merge.on_create, bound)?,
+                });
+            }
+            UpdateClause::Set(items) => {
+                for instruction in set_instructions(items, bound)? {
+                    self.emit(instruction);
+                }
+            }
+            UpdateClause::Remove(items) => {
+                for item in items.iter() {
+                    let instruction = match item {
+                        RemoveItem::Property { variable, property } => {
+                            check_bound(bound, variable)?;
+                            Instruction::SetProperty {
+                                variable: variable.clone(),
+                                property: property.clone(),
+                                value: generate_expression(&Expression::Literal(Value::Null)),
+                            }
+                        }
+                        RemoveItem::Labels { variable, labels } => {
+                            check_bound(bound, variable)?;
+                            Instruction::SetLabels {
+                                variable: variable.clone(),
+                                labels: labels.clone(),
+                                remove: true,
+                            }
+                        }
+                        RemoveItem::Document { variable } => {
+                            check_bound(bound, variable)?;
+                            Instruction::SetDocument {
+                                variable: variable.clone(),
+                                document: None,
+                            }
+                        }
+                    };
+                    self.emit(instruction);
+                }
+            }
+            UpdateClause::Delete { variables, detach } => {
+                for variable in variables.iter() {
+                    check_bound(bound, variable)?;
+                }
+                self.emit(Instruction::Delete {
+                    variables: variables.clone(),
+                    detach: *detach,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Bound nodes of the pattern are connected, everything else is created
+    fn generate_create(
+        &mut self,
+        pattern: &MatchClause,
+        bound: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        for node in pattern.patterns.iter() {
+            if bound.contains(&node.variable) {
+                if !node.labels.is_empty() || !node.properties.is_empty() {
+                    return Err(format!("Variable '{}' is already defined", node.variable));
+                }
+                continue;
+            }
+            self.emit(Instruction::CreateNode {
+                variable: node.variable.clone(),
+                labels: node.labels.clone(),
+                properties: properties_to_json(&node.properties),
+            });
+            bound.insert(node.variable.clone());
+        }
+
+        for relationship in pattern.relationships.iter() {
+            if bound.contains(&relationship.variable) {
+                return Err(format!(
+                    "Variable '{}' is already defined",
+                    relationship.variable
+                ));
+            }
+            let [rel_type] = relationship.types.as_slice() else {
+                return Err("A created relationship needs exactly one type".to_string());
+            };
+            if relationship.length.is_some() {
+                return Err("A variable length relationship can not be created".to_string());
+            }
+            let (start, end) = match relationship.direction {
+                Direction::Outgoing => (&relationship.left, &relationship.right),
+                Direction::Incoming => (&relationship.right, &relationship.left),
+                Direction::Both => {
+                    return Err("A created relationship needs a direction".to_string())
+                }
+            };
+            self.emit(Instruction::CreateRelationship {
+                variable: relationship.variable.clone(),
+                rel_type: rel_type.clone(),
+                properties: properties_to_json(&relationship.properties),
+                start: start.clone(),
+                end: end.clone(),
+            });
+            bound.insert(relationship.variable.clone());
+        }
+
+        if pattern.paths.iter().any(|path| path.shortest) {
+            return Err("shortestPath can not be created".to_string());
+        }
+        self.generate_paths(pattern, bound)
+    }
+
+    // Binds the variable if needed and checks the labels and properties of every occurrence
+    fn generate_node(
+        &mut self,
+        match_clause: &MatchClause,
+        condition: Option<&Expression>,
+        variable: &String,
+        bound: &mut HashSet<String>,
+    ) {
+        for pattern in match_clause
+            .patterns
+            .iter()
+            .filter(|pattern| pattern.variable == *variable)
+        {
+            if bound.contains(variable)
+                && pattern.labels.is_empty()
+                && pattern.properties.is_empty()
+            {
+                continue;
+            }
+            let index_plan = and_plans(
+                pattern
+                    .properties
+                    .iter()
+                    .filter_map(|property| {
+                        compare_plan(&property.key, BinaryOperator::Equal, &property.value)
+                    })
+                    .chain(condition.and_then(|condition| index_plan(variable, condition)))
+                    .collect(),
+            );
+            self.emit(Instruction::MatchNode {
+                variable: variable.clone(),
+                labels: pattern.labels.clone(),
+                properties: properties_to_json(&pattern.properties),
+                index_plan,
+            });
+            bound.insert(variable.clone());
+        }
+    }
+}
+
+// Index lookups for the comparisons of a node property with a literal, like generate_index_plan in SQL
+fn index_plan(variable: &String, condition: &Expression) -> Option<IndexPlan> {
+    let Expression::Binary {
+        operator,
+        left,
+        right,
+    } = condition
+    else {
+        return None;
+    };
+    match (operator, left.as_ref(), right.as_ref()) {
+        (BinaryOperator::And, left, right) => and_plans(
+            [index_plan(variable, left), index_plan(variable, right)]
+                .into_iter()
+                .flatten()
+                .collect(),
+        ),
+        (BinaryOperator::Or, left, right) => Some(IndexPlan::Or(
+            Box::new(index_plan(variable, left)?),
+            Box::new(index_plan(variable, right)?),
+        )),
+        (
+            operator,
+            Expression::Property {
+                variable: v,
+                property,
+            },
+            Expression::Literal(value),
+        ) if v == variable => compare_plan(property, *operator, value),
+        (
+            operator,
+            Expression::Literal(value),
+            Expression::Property {
+                variable: v,
+                property,
+            },
+        ) if v == variable => {
+            let flipped = match operator {
+                BinaryOperator::Less => BinaryOperator::Greater,
+                BinaryOperator::LessEqual => BinaryOperator::GreaterEqual,
+                BinaryOperator::Greater => BinaryOperator::Less,
+                BinaryOperator::GreaterEqual => BinaryOperator::LessEqual,
+                operator => *operator,
+            };
+            compare_plan(property, flipped, value)
+        }
+        _ => None,
+    }
+}
+
+fn compare_plan(property: &str, operator: BinaryOperator, value: &Value) -> Option<IndexPlan> {
+    let operator = match operator {
+        BinaryOperator::Equal => "=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::LessEqual => "<=",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::GreaterEqual => ">=",
+        _ => return None,
+    };
+    let value = match value {
+        Value::String(s) => FieldValue::String(s.clone()),
+        Value::Number(n) => FieldValue::Number(*n),
+        Value::Boolean(_) | Value::Null => return None,
+    };
+    Some(IndexPlan::Compare {
+        field: property.to_string(),
+        operator: operator.to_string(),
+        value,
+    })
+}
+
+fn and_plans(plans: Vec<IndexPlan>) -> Option<IndexPlan> {
+    plans
+        .into_iter()
+        .reduce(|left, right| IndexPlan::And(Box::new(left), Box::new(right)))
+}
+
+fn generate_expression(expression: &Expression) -> Evaluator {
+    match expression {
+        Expression::Variable(variable) => {
+            let variable = variable.clone();
+            Box::new(move |_, row| {
+                row.get(&variable)
+                    .cloned()
+                    .ok_or(format!("Variable '{}' is not defined", variable))
+            })
+        }
+        Expression::Property { variable, property } => {
+            let variable = variable.clone();
+            let property = property.clone();
+            Box::new(move |context, row| {
+                row.get(&variable)
+                    .ok_or(format!("Variable '{}' is not defined", variable))?
+                    .property(context.graph, &property)
+            })
+        }
+        Expression::Literal(value) => {
+            let value = value_to_json(value);
+            Box::new(move |_, _| Ok(Entry::Value(value.clone())))
+        }
+        Expression::List(items) => {
+            let items: Vec<Evaluator> = items.iter().map(generate_expression).collect();
+            Box::new(move |context, row| {
+                let entries = items
+                    .iter()
+                    .map(|item| item(context, row))
+                    .collect::<Result<Vec<Entry>, String>>()?;
+                Ok(Entry::List(entries))
+            })
         }
+        Expression::Map(entries) => {
+            let entries: Vec<(String, Evaluator)> = entries
+                .iter()
+                .map(|(key, expression)| (key.clone(), generate_expression(expression)))
+                .collect();
+            Box::new(move |context, row| {
+                let mut map = serde_json::Map::new();
+                for (key, expression) in entries.iter() {
+                    map.insert(
+                        key.clone(),
+                        expression(context, row)?.to_json(context.graph),
+                    );
+                }
+                Ok(Entry::Value(serde_json::Value::Object(map)))
+            })
+        }
+        Expression::Not(expression) => {
+            let expression = generate_expression(expression);
+            Box::new(move |context, row| {
+                let truth = expression(context, row)?.truth()?;
+                Ok(Entry::from_truth(truth.map(|value| !value)))
+            })
+        }
+        Expression::Negate(expression) => {
+            let expression = generate_expression(expression);
+            Box::new(move |context, row| {
+                evaluate_binary(
+                    BinaryOperator::Subtract,
+                    Entry::Value(serde_json::json!(0)),
+                    expression(context, row)?,
+                )
+            })
+        }
+        Expression::Binary {
+            operator,
+            left,
+            right,
+        } => {
+            let operator = *operator;
+            let left = generate_expression(left);
+            let right = generate_expression(right);
+            Box::new(move |context, row| {
+                evaluate_binary(operator, left(context, row)?, right(context, row)?)
+            })
+        }
+        Expression::Aggregate { function, .. } => {
+            let name = function.name();
+            Box::new(move |_, _| {
+                Err(format!(
+                    "Aggregate function {} is only allowed in RETURN and WITH",
+                    name
+                ))
+            })
+        }
+        Expression::IsNull {
+            expression,
+            negated,
+        } => {
+            let negated = *negated;
+            let expression = generate_expression(expression);
+            Box::new(move |context, row| {
+                let is_null = expression(context, row)? == Entry::Value(serde_json::Value::Null);
+                Ok(Entry::Value(serde_json::Value::Bool(is_null != negated)))
+            })
+        }
+        Expression::Document(expression) => {
+            let expression = generate_expression(expression);
+            Box::new(move |context, row| expression(context, row)?.document(context))
+        }
+        Expression::Field {
+            expression,
+            property,
+        } => {
+            let expression = generate_expression(expression);
+            let property = property.clone();
+            Box::new(move |context, row| {
+                expression(context, row)?.property(context.graph, &property)
+            })
+        }
+    }
+}
+
+fn contains_aggregate(expression: &Expression) -> bool {
+    match expression {
+        Expression::Aggregate { .. } => true,
+        Expression::Property { .. } | Expression::Variable(_) | Expression::Literal(_) => false,
+        Expression::List(items) => items.iter().any(contains_aggregate),
+        Expression::Map(entries) => entries
+            .iter()
+            .any(|(_, expression)| contains_aggregate(expression)),
+        Expression::Not(expression)
+        | Expression::Negate(expression)
+        | Expression::IsNull { expression, .. }
+        | Expression::Document(expression)
+        | Expression::Field { expression, .. } => contains_aggregate(expression),
+        Expression::Binary { left, right, .. } => {
+            contains_aggregate(left) || contains_aggregate(right)
+        }
+    }
+}
+
+// Replaces every aggregate function by a variable the Aggregate instruction binds
+fn extract_aggregates(
+    expression: &Expression,
+    aggregations: &mut Vec<Aggregation>,
+) -> Result<Expression, String> {
+    let extract = |expression: &Expression, aggregations: &mut Vec<Aggregation>| {
+        extract_aggregates(expression, aggregations).map(Box::new)
+    };
+    Ok(match expression {
+        Expression::Aggregate {
+            function,
+            distinct,
+            argument,
+        } => {
+            if argument.as_deref().is_some_and(contains_aggregate) {
+                return Err("Aggregate functions can not be nested".to_string());
+            }
+            let variable = format!("  aggregate_{}", aggregations.len());
+            aggregations.push(Aggregation {
+                variable: variable.clone(),
+                function: *function,
+                distinct: *distinct,
+                argument: argument.as_deref().map(generate_expression),
+            });
+            Expression::Variable(variable)
+        }
+        Expression::List(items) => Expression::List(
+            items
+                .iter()
+                .map(|item| extract_aggregates(item, aggregations))
+                .collect::<Result<Vec<Expression>, String>>()?,
+        ),
+        Expression::Map(entries) => Expression::Map(
+            entries
+                .iter()
+                .map(|(key, expression)| {
+                    Ok((key.clone(), extract_aggregates(expression, aggregations)?))
+                })
+                .collect::<Result<Vec<(String, Expression)>, String>>()?,
+        ),
+        Expression::Not(expression) => Expression::Not(extract(expression, aggregations)?),
+        Expression::Negate(expression) => Expression::Negate(extract(expression, aggregations)?),
+        Expression::Document(expression) => {
+            Expression::Document(extract(expression, aggregations)?)
+        }
+        Expression::Field {
+            expression,
+            property,
+        } => Expression::Field {
+            expression: extract(expression, aggregations)?,
+            property: property.clone(),
+        },
+        Expression::IsNull {
+            expression,
+            negated,
+        } => Expression::IsNull {
+            expression: extract(expression, aggregations)?,
+            negated: *negated,
+        },
+        Expression::Binary {
+            operator,
+            left,
+            right,
+        } => Expression::Binary {
+            operator: *operator,
+            left: extract(left, aggregations)?,
+            right: extract(right, aggregations)?,
+        },
+        expression => expression.clone(),
+    })
+}
+
+fn set_instructions(
+    items: &[SetItem],
+    bound: &HashSet<String>,
+) -> Result<Vec<Instruction>, String> {
+    let mut instructions = Vec::new();
+    for item in items.iter() {
+        instructions.push(match item {
+            SetItem::Property {
+                variable,
+                property,
+                value,
+            } => {
+                check_bound(bound, variable)?;
+                Instruction::SetProperty {
+                    variable: variable.clone(),
+                    property: property.clone(),
+                    value: generate_expression(value),
+                }
+            }
+            SetItem::Labels { variable, labels } => {
+                check_bound(bound, variable)?;
+                Instruction::SetLabels {
+                    variable: variable.clone(),
+                    labels: labels.clone(),
+                    remove: false,
+                }
+            }
+            SetItem::Document { variable, value } => {
+                check_bound(bound, variable)?;
+                Instruction::SetDocument {
+                    variable: variable.clone(),
+                    document: Some(generate_expression(value)),
+                }
+            }
+        });
+    }
+    Ok(instructions)
+}
+
+fn check_bound(bound: &HashSet<String>, variable: &String) -> Result<(), String> {
+    if bound.contains(variable) {
+        Ok(())
+    } else {
+        Err(format!("Variable '{}' is not defined", variable))
+    }
+}
+
+// Column name of a RETURN item without alias, e.g. n.name
+fn expression_name(expression: &Expression) -> String {
+    match expression {
+        Expression::Variable(variable) => variable.clone(),
+        Expression::Property { variable, property } => format!("{}.{}", variable, property),
+        Expression::Literal(Value::String(s)) => format!("'{}'", s),
+        Expression::Literal(value) => value_to_json(value).to_string(),
+        Expression::List(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(expression_name)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Expression::Map(entries) => format!(
+            "{{{}}}",
+            entries
+                .iter()
+                .map(|(key, expression)| format!("{}: {}", key, expression_name(expression)))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Expression::Not(expression) => format!("NOT {}", operand_name(expression)),
+        Expression::Negate(expression) => format!("-{}", operand_name(expression)),
+        Expression::Binary {
+            operator,
+            left,
+            right,
+        } => format!(
+            "{} {} {}",
+            operand_name(left),
+            operator.text(),
+            operand_name(right)
+        ),
+        Expression::Aggregate {
+            function,
+            distinct,
+            argument,
+        } => format!(
+            "{}({}{})",
+            function.name(),
+            if *distinct { "DISTINCT " } else { "" },
+            argument
+                .as_deref()
+                .map(expression_name)
+                .unwrap_or("*".to_string())
+        ),
+        Expression::IsNull {
+            expression,
+            negated,
+        } => format!(
+            "{} IS {}NULL",
+            operand_name(expression),
+            if *negated { "NOT " } else { "" }
+        ),
+        Expression::Document(expression) => format!("doc({})", expression_name(expression)),
+        Expression::Field {
+            expression,
+            property,
+        } => format!("{}.{}", operand_name(expression), property),
+    }
+}
+
+// Nested operators are put in parentheses, so the name keeps the meaning of the query
+fn operand_name(expression: &Expression) -> String {
+    match expression {
+        Expression::Binary { .. } | Expression::IsNull { .. } | Expression::Not(_) => {
+            format!("({})", expression_name(expression))
+        }
+        _ => expression_name(expression),
+    }
+}
+
+fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::Number(n) => number_to_json(*n),
+        Value::Boolean(b) => serde_json::Value::Bool(*b),
+        Value::Null => serde_json::Value::Null,
     }
 }
 
+fn properties_to_json(properties: &[Property]) -> Properties {
+    properties
+        .iter()
+        .map(|property| (property.key.clone(), value_to_json(&property.value)))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -446,7 +2765,7 @@ mod tests {
             Token::Identifier("name".to_string()),
             Token::As,
             Token::Identifier("name".to_string()),
-            Token::EOF,
+            Token::Eof,
         ];
 
         for expected in expected_tokens {
@@ -496,4 +2815,395 @@ mod tests {
         }
         assert_eq!(second_item.alias, Some("born".to_string()));
     }
+
+    #[test]
+    fn test_relationship_patterns() {
+        let input = "MATCH (a:Person)-[r:KNOWS|LIKES {since: 2020}]->(b:Person)<-[:WORKS_AT]-(c), (b)--(d) RETURN r";
+        let query = Parser::new(input).parse_query().unwrap();
+        let match_clause = &query.match_clause;
+
+        let variables = match_clause
+            .patterns
+            .iter()
+            .map(|pattern| pattern.variable.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(variables, vec!["a", "b", "c", "b", "d"]);
+        assert_eq!(match_clause.relationships[1].variable, "  anon_1");
+
+        assert_eq!(match_clause.relationships.len(), 3);
+        let knows = &match_clause.relationships[0];
+        assert_eq!(knows.variable, "r");
+        assert_eq!(knows.types, vec!["KNOWS", "LIKES"]);
+        assert_eq!(knows.properties[0].value, Value::Number(2020.0));
+        assert_eq!((knows.left.as_str(), knows.right.as_str()), ("a", "b"));
+        assert_eq!(knows.direction, Direction::Outgoing);
+
+        let works_at = &match_clause.relationships[1];
+        assert_eq!(
+            (works_at.left.as_str(), works_at.right.as_str()),
+            ("b", "c")
+        );
+        assert_eq!(works_at.direction, Direction::Incoming);
+        assert_eq!(match_clause.relationships[2].direction, Direction::Both);
+
+        assert!(Parser::new("MATCH (a)<-[r]->(b) RETURN a")
+            .parse_query()
+            .is_err());
+    }
+
+    #[test]
+    fn test_variable_length_and_paths() {
+        let input =
+            "MATCH p = (a)-[:KNOWS*1..5]->(b)-[*]-(c), q = shortestPath((a)-[*..3]-(d)) RETURN p";
+        let query = Parser::new(input).parse_query().unwrap();
+        let match_clause = &query.match_clause;
+
+        let lengths = match_clause
+            .relationships
+            .iter()
+            .map(|relationship| relationship.length)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lengths,
+            vec![
+                Some(Length {
+                    min: 1,
+                    max: Some(5)
+                }),
+                Some(Length { min: 1, max: None }),
+                Some(Length {
+                    min: 1,
+                    max: Some(3)
+                }),
+            ]
+        );
+
+        assert_eq!(match_clause.paths.len(), 2);
+        assert_eq!(match_clause.paths[0].variable, Some("p".to_string()));
+        assert_eq!(match_clause.paths[0].start, "a");
+        assert_eq!(match_clause.paths[0].relationships.len(), 2);
+        assert!(!match_clause.paths[0].shortest);
+        assert!(match_clause.paths[1].shortest);
+
+        for (input, length) in [("*3", (3, Some(3))), ("*2..", (2, None))] {
+            let query = Parser::new(&format!("MATCH (a)-[{}]->(b) RETURN a", input))
+                .parse_query()
+                .unwrap();
+            let relationship_length = query.match_clause.relationships[0].length.unwrap();
+            assert_eq!((relationship_length.min, relationship_length.max), length);
+        }
+        assert!(Parser::new("MATCH (a)-[*5..2]->(b) RETURN a")
+            .parse_query()
+            .is_err());
+        assert!(Parser::new("MATCH shortestPath((a)-->(b)-->(c)) RETURN a")
+            .parse_query()
+            .is_err());
+    }
+
+    #[test]
+    fn test_update_clauses() {
+        let input = "MATCH (a {name: 'Ann'}) CREATE (a)-[:KNOWS]->(b:Person {name: 'Bob', age: -3}) \
+            MERGE (c:City {name: 'Oslo'}) ON CREATE SET c.created = true ON MATCH SET c.seen = c.name \
+            SET b.age = 31, b:Admin REMOVE a.age, a:Guest DETACH DELETE a RETURN b";
+        let query = Parser::new(input).parse_query().unwrap();
+        assert_eq!(query.updates.len(), 5);
+
+        let UpdateClause::Create(create) = &query.updates[0] else {
+            panic!("Expected CREATE");
+        };
+        assert_eq!(create.relationships[0].types, vec!["KNOWS"]);
+        assert_eq!(create.patterns[1].properties[1].value, Value::Number(-3.0));
+
+        let UpdateClause::Merge(merge) = &query.updates[1] else {
+            panic!("Expected MERGE");
+        };
+        assert_eq!(merge.pattern.patterns[0].labels, vec!["City"]);
+        assert_eq!(
+            merge.on_create,
+            vec![SetItem::Property {
+                variable: "c".to_string(),
+                property: "created".to_string(),
+                value: Expression::Literal(Value::Boolean(true)),
+            }]
+        );
+        assert_eq!(merge.on_match.len(), 1);
+
+        assert_eq!(
+            query.updates[2],
+            UpdateClause::Set(vec![
+                SetItem::Property {
+                    variable: "b".to_string(),
+                    property: "age".to_string(),
+                    value: Expression::Literal(Value::Number(31.0)),
+                },
+                SetItem::Labels {
+                    variable: "b".to_string(),
+                    labels: vec!["Admin".to_string()],
+                },
+            ])
+        );
+        assert_eq!(
+            query.updates[3],
+            UpdateClause::Remove(vec![
+                RemoveItem::Property {
+                    variable: "a".to_string(),
+                    property: "age".to_string(),
+                },
+                RemoveItem::Labels {
+                    variable: "a".to_string(),
+                    labels: vec!["Guest".to_string()],
+                },
+            ])
+        );
+        assert_eq!(
+            query.updates[4],
+            UpdateClause::Delete {
+                variables: vec!["a".to_string()],
+                detach: true,
+            }
+        );
+        assert_eq!(query.return_clause.items.len(), 1);
+
+        // Write queries may end without RETURN, read queries may not
+        assert!(Parser::new("CREATE (n)").parse_query().is_ok());
+        assert!(Parser::new("MATCH (n)").parse_query().is_err());
+        assert!(Parser::new("MATCH (n) RETURN n n").parse_query().is_err());
+    }
+
+    #[test]
+    fn test_where_expressions() {
+        let input = "MATCH (n:Person) WHERE n.age > 30 AND (n.city = 'Oslo' OR n.vip = true) AND n.name STARTS WITH 'J' RETURN n";
+        let query = Parser::new(input).parse_query().unwrap();
+        assert_eq!(
+            expression_name(query.where_clause.as_ref().unwrap()),
+            "((n.age > 30) AND ((n.city = 'Oslo') OR (n.vip = true))) AND (n.name STARTS WITH 'J')"
+        );
+
+        // AND binds tighter than OR, * tighter than +, NOT looser than comparisons
+        let names = [
+            ("a OR b AND c", "a OR (b AND c)"),
+            ("1 + 2 * -x", "1 + (2 * -x)"),
+            ("NOT n.age IN [1, 2]", "NOT (n.age IN [1, 2])"),
+            (
+                "n.age IS NOT NULL XOR n.x <> -1.5",
+                "(n.age IS NOT NULL) XOR (n.x <> -1.5)",
+            ),
+            (
+                "n.name ENDS WITH 'a' OR n.name CONTAINS 'b'",
+                "(n.name ENDS WITH 'a') OR (n.name CONTAINS 'b')",
+            ),
+        ];
+        for (input, name) in names {
+            let query = Parser::new(&format!("MATCH (n) WHERE {} RETURN n", input))
+                .parse_query()
+                .unwrap();
+            assert_eq!(expression_name(query.where_clause.as_ref().unwrap()), name);
+        }
+
+        assert!(Parser::new("MATCH (n) WHERE n.name STARTS 'J' RETURN n")
+            .parse_query()
+            .is_err());
+        assert!(Parser::new("MATCH (n) WHERE (n.age > 1 RETURN n")
+            .parse_query()
+            .is_err());
+    }
+
+    #[test]
+    fn test_projections() {
+        let input = "MATCH (n:Person) WITH n.city AS city, count(*) AS people WHERE people > 1 \
+            RETURN DISTINCT city, people ORDER BY people DESC, city SKIP 1 LIMIT 10";
+        let query = Parser::new(input).parse_query().unwrap();
+
+        let with = &query.return_clause;
+        assert_eq!(with.items[1].alias, Some("people".to_string()));
+        assert_eq!(
+            with.items[1].expression,
+            Expression::Aggregate {
+                function: AggregateFunction::Count,
+                distinct: false,
+                argument: None,
+            }
+        );
+        assert!(with.where_clause.is_some());
+
+        let next = query.next.as_ref().unwrap();
+        assert!(next.match_clause.patterns.is_empty());
+        let clause = &next.return_clause;
+        assert!(clause.distinct);
+        assert_eq!(clause.order_by.len(), 2);
+        assert!(clause.order_by[0].descending);
+        assert!(!clause.order_by[1].descending);
+        assert_eq!((clause.skip, clause.limit), (Some(1), Some(10)));
+
+        let query = Parser::new("MATCH (n) RETURN count(DISTINCT n.city), collect(n.name) + [1]")
+            .parse_query()
+            .unwrap();
+        let names = query
+            .return_clause
+            .items
+            .iter()
+            .map(|item| expression_name(&item.expression))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            names,
+            vec!["count(DISTINCT n.city)", "collect(n.name) + [1]"]
+        );
+
+        assert!(Parser::new("MATCH (n) RETURN lower(n.name)")
+            .parse_query()
+            .is_err());
+        assert!(Parser::new("MATCH (n) RETURN n LIMIT -1")
+            .parse_query()
+            .is_err());
+    }
+
+    #[test]
+    fn test_call_clause() {
+        let input = "CALL algo.pageRank('Person', 'KNOWS', {iterations: 20, dampingFactor: 0.5}) YIELD node, score AS rank RETURN node.name, rank";
+        let query = Parser::new(input).parse_query().unwrap();
+        let call = query.call.as_ref().unwrap();
+        assert_eq!(call.procedure, "algo.pageRank");
+        assert_eq!(call.arguments.len(), 3);
+        assert_eq!(
+            expression_name(&call.arguments[2]),
+            "{iterations: 20, dampingFactor: 0.5}"
+        );
+        assert_eq!(
+            call.yields,
+            vec![
+                ("node".to_string(), "node".to_string()),
+                ("score".to_string(), "rank".to_string())
+            ]
+        );
+
+        let query = Parser::new("CALL algo.wcc()").parse_query().unwrap();
+        assert!(query.call.unwrap().arguments.is_empty());
+        assert!(query.return_clause.items.is_empty());
+    }
+
+    #[test]
+    fn test_documents() {
+        let input =
+            "MATCH (f) SET doc(f) = ['people', 'ann'] REMOVE doc(f) RETURN doc(f).address.city";
+        let query = Parser::new(input).parse_query().unwrap();
+        assert_eq!(
+            query.updates,
+            vec![
+                UpdateClause::Set(vec![SetItem::Document {
+                    variable: "f".to_string(),
+                    value: Expression::List(vec![
+                        Expression::Literal(Value::String("people".to_string())),
+                        Expression::Literal(Value::String("ann".to_string())),
+                    ]),
+                }]),
+                UpdateClause::Remove(vec![RemoveItem::Document {
+                    variable: "f".to_string(),
+                }]),
+            ]
+        );
+        let expression = &query.return_clause.items[0].expression;
+        assert_eq!(expression_name(expression), "doc(f).address.city");
+        assert!(matches!(
+            expression,
+            Expression::Field { expression, .. } if matches!(**expression, Expression::Field { .. })
+        ));
+    }
+
+    #[test]
+    fn test_index_statements() {
+        assert_eq!(
+            Parser::new("CREATE INDEX ON :Person(age) TYPE number")
+                .parse_statement()
+                .unwrap(),
+            Statement::CreateIndex {
+                label: "Person".to_string(),
+                property: "age".to_string(),
+                field_type: FieldType::Number,
+            }
+        );
+        assert_eq!(
+            Parser::new("drop index on :Person(email)")
+                .parse_statement()
+                .unwrap(),
+            Statement::DropIndex {
+                label: "Person".to_string(),
+                property: "email".to_string(),
+            }
+        );
+        assert!(matches!(
+            Parser::new("CREATE (index:Index)").parse_statement(),
+            Ok(Statement::Query(_))
+        ));
+        assert!(Parser::new("CREATE INDEX ON :Person(age) TYPE date")
+            .parse_statement()
+            .is_err());
+    }
+
+    #[test]
+    fn test_errors_and_parameters() {
+        let error = Parser::new("MATCH (n:Person)\nRETURN n.name ORDER n.name")
+            .parse_statement()
+            .unwrap_err();
+        assert_eq!(
+            error.location(),
+            Some(&Location {
+                line: 2,
+                column: 21,
+                token: "n".to_string(),
+            })
+        );
+        assert_eq!(
+            Parser::new("MATCH (n")
+                .parse_statement()
+                .unwrap_err()
+                .into_string(),
+            "Expected RightParen, got Eof at line 1, column 9 (end of query)"
+        );
+
+        let parameters = serde_json::json!({"name": "Ann", "ids": [1, 2], "limit": 3});
+        let statement =
+            Parser::new("MATCH (n {name: $name}) WHERE n.id IN $ids RETURN n LIMIT $limit")
+                .with_parameters(parameters.as_object().unwrap().clone())
+                .parse_query()
+                .unwrap();
+        assert_eq!(
+            statement.match_clause.patterns[0].properties,
+            vec![Property {
+                key: "name".to_string(),
+                value: Value::String("Ann".to_string()),
+            }]
+        );
+        assert_eq!(statement.return_clause.limit, Some(3));
+
+        let error = Parser::new("MATCH (n) WHERE n.name = $name RETURN n")
+            .parse_statement()
+            .unwrap_err();
+        assert!(matches!(error, Error::ParameterError { .. }));
+        assert_eq!(error.location().unwrap().token, "$name");
+        assert!(Parser::new("MATCH (n {name: $ids}) RETURN n")
+            .with_parameters(parameters.as_object().unwrap().clone())
+            .parse_statement()
+            .is_err());
+
+        // Unreadable text fails the query instead of ending it
+        assert_eq!(
+            Parser::new("MATCH (n) RETURN n # junk")
+                .parse_statement()
+                .unwrap_err()
+                .into_string(),
+            "Unexpected character '#' at line 1, column 20 ('#')"
+        );
+        let error = Parser::new("MATCH (n) RETURN n; DETACH DELETE n")
+            .parse_statement()
+            .unwrap_err();
+        assert!(matches!(error, Error::SyntaxError { .. }));
+        assert_eq!(error.location().unwrap().token, ";");
+        assert_eq!(
+            Parser::new("MATCH (n {name: 'Ann}) RETURN n")
+                .parse_statement()
+                .unwrap_err()
+                .into_string(),
+            "Unterminated string at line 1, column 17 (''Ann}) RETURN n')"
+        );
+    }
 }
@@ -0,0 +1,141 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+
+use serde::{Deserialize, Serialize};
+
+// Where in the query a syntax error was found
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    // Both start at 1
+    pub line: usize,
+    pub column: usize,
+    // Text of the offending token, empty at the end of the query
+    pub token: String,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Error {
+    SyntaxError { message: String, location: Location },
+    // A $parameter that is missing or has a value that can not be used there
+    ParameterError { message: String, location: Location },
+    // The query parses but can not be planned, e.g. an unknown variable
+    QueryError(String),
+    // Running the query failed, its changes were rolled back
+    ExecutionError(String),
+    // A CSV or GraphML file that can not be imported, nothing was created
+    ImportError(String),
+    JsonError(String),
+}
+
+impl Error {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Error::SyntaxError { location, .. } | Error::ParameterError { location, .. } => {
+                Some(location)
+            }
+            Error::QueryError(_)
+            | Error::ExecutionError(_)
+            | Error::ImportError(_)
+            | Error::JsonError(_) => None,
+        }
+    }
+
+    pub fn into_string(self) -> String {
+        match self {
+            Error::SyntaxError { message, location }
+            | Error::ParameterError { message, location } => {
+                let token = if location.token.is_empty() {
+                    "end of query".to_string()
+                } else {
+                    format!("'{}'", location.token)
+                };
+                format!(
+                    "{} at line {}, column {} ({})",
+                    message, location.line, location.column, token
+                )
+            }
+            Error::QueryError(s) => s,
+            Error::ExecutionError(s) => s,
+            Error::ImportError(s) => s,
+            Error::JsonError(s) => s,
+        }
+    }
+
+    pub fn into_response_string(self) -> Result<String, Error> {
+        ErrorResponse::from(self).try_to_string()
+    }
+}
+
+// The error message, with the position of the offending token when there is one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl ErrorResponse {
+    fn try_to_string(self) -> Result<String, Error> {
+        serde_json::to_string_pretty(&self)
+            .map_err(|x: serde_json::Error| Error::JsonError(x.to_string()))
+    }
+}
+
+impl From<Error> for ErrorResponse {
+    fn from(error: Error) -> Self {
+        let location = error.location().cloned();
+        ErrorResponse {
+            error: error.into_string(),
+            line: location.as_ref().map(|location| location.line),
+            column: location.as_ref().map(|location| location.column),
+            token: location.map(|location| location.token),
+        }
+    }
+}
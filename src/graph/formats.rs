@@ -0,0 +1,987 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+
+use crate::graph::store::{GraphStore, NodeId, Properties};
+use crate::kv::witchvm_kv::number_to_json;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+// Imports parse and check the whole input first, so a bad file leaves the graph as it was
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ImportSummary {
+    pub nodes_created: usize,
+    pub relationships_created: usize,
+}
+
+struct NodeRecord {
+    labels: Vec<String>,
+    properties: Properties,
+}
+
+struct RelationshipRecord {
+    start: usize,
+    end: usize,
+    rel_type: String,
+    properties: Properties,
+}
+
+fn create_records(
+    graph: &mut GraphStore,
+    nodes: Vec<NodeRecord>,
+    relationships: Vec<RelationshipRecord>,
+) -> Result<ImportSummary, String> {
+    let summary = ImportSummary {
+        nodes_created: nodes.len(),
+        relationships_created: relationships.len(),
+    };
+    let ids: Vec<NodeId> = nodes
+        .into_iter()
+        .map(|node| graph.create_node(node.labels, node.properties))
+        .collect();
+    for relationship in relationships {
+        graph.create_relationship(
+            ids[relationship.start],
+            ids[relationship.end],
+            relationship.rel_type,
+            relationship.properties,
+        )?;
+    }
+    Ok(summary)
+}
+
+// CSV
+
+// Rows of the input with the line each row starts on. Fields may be quoted with ",
+// a quoted field can hold commas, line breaks and "" for a quote
+fn parse_csv(input: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                // Blank lines are skipped
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push((row_line, std::mem::take(&mut row)));
+                }
+                row.clear();
+                line += 1;
+                row_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("Unterminated quoted field on line {}", row_line));
+    }
+    row.push(field);
+    if row.len() > 1 || !row[0].is_empty() {
+        rows.push((row_line, row));
+    }
+    Ok(rows)
+}
+
+#[derive(Clone, Copy)]
+enum ColumnType {
+    Inferred,
+    String,
+    Number,
+    Boolean,
+}
+
+// Property columns are named like "age" or "age:number", the type can be string, number or boolean
+fn parse_column(header: &str) -> Result<(String, ColumnType), String> {
+    let (name, column_type) = match header.rsplit_once(':') {
+        Some((name, "string")) => (name, ColumnType::String),
+        Some((name, "number")) => (name, ColumnType::Number),
+        Some((name, "boolean")) => (name, ColumnType::Boolean),
+        _ => (header, ColumnType::Inferred),
+    };
+    if name.is_empty() {
+        return Err(format!("Column '{}' has no name", header));
+    }
+    Ok((name.to_string(), column_type))
+}
+
+fn csv_value(cell: &str, column_type: ColumnType) -> Option<serde_json::Value> {
+    let number = || {
+        cell.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite())
+            .map(number_to_json)
+    };
+    let boolean = || match cell.trim() {
+        "true" => Some(serde_json::Value::Bool(true)),
+        "false" => Some(serde_json::Value::Bool(false)),
+        _ => None,
+    };
+    match column_type {
+        ColumnType::String => Some(serde_json::Value::String(cell.to_string())),
+        ColumnType::Number => number(),
+        ColumnType::Boolean => boolean(),
+        ColumnType::Inferred => Some(
+            number()
+                .or_else(boolean)
+                .unwrap_or(serde_json::Value::String(cell.to_string())),
+        ),
+    }
+}
+
+struct CsvTable {
+    rows: Vec<(usize, Vec<String>)>,
+    // Position of every required and optional column, in the order they were asked for
+    columns: Vec<Option<usize>>,
+    // Position, name and type of the other columns
+    properties: Vec<(usize, String, ColumnType)>,
+}
+
+impl CsvTable {
+    fn parse(input: &str, required: &[&str], optional: &[&str]) -> Result<Self, String> {
+        let mut rows = parse_csv(input)?.into_iter();
+        let (_, header) = rows
+            .next()
+            .ok_or_else(|| format!("Missing header, expected {}", required.join(",")))?;
+        let header: Vec<&str> = header.iter().map(|column| column.trim()).collect();
+        let mut table = CsvTable {
+            rows: Vec::new(),
+            columns: Vec::new(),
+            properties: Vec::new(),
+        };
+        for column in required.iter().chain(optional) {
+            table
+                .columns
+                .push(header.iter().position(|name| name == column));
+        }
+        for (column, position) in required.iter().zip(&table.columns) {
+            if position.is_none() {
+                return Err(format!("Missing column '{}'", column));
+            }
+        }
+        for (position, column) in header.iter().enumerate() {
+            if !table.columns.contains(&Some(position)) {
+                let (name, column_type) = parse_column(column)?;
+                table.properties.push((position, name, column_type));
+            }
+        }
+        for (line, row) in rows {
+            if row.len() != header.len() {
+                return Err(format!(
+                    "Line {} has {} fields, the header has {}",
+                    line,
+                    row.len(),
+                    header.len()
+                ));
+            }
+            table.rows.push((line, row));
+        }
+        Ok(table)
+    }
+
+    // Cell of a column asked for in parse, empty for a missing optional column
+    fn cell<'a>(&self, row: &'a [String], column: usize) -> &'a str {
+        self.columns[column]
+            .map(|position| row[position].as_str())
+            .unwrap_or("")
+    }
+
+    // Empty cells are left out
+    fn properties(&self, line: usize, row: &[String]) -> Result<Properties, String> {
+        let mut properties = Properties::new();
+        for (position, name, column_type) in &self.properties {
+            let cell = &row[*position];
+            if cell.is_empty() {
+                continue;
+            }
+            let value = csv_value(cell, *column_type).ok_or_else(|| {
+                format!("Invalid value '{}' for '{}' on line {}", cell, name, line)
+            })?;
+            properties.insert(name.clone(), value);
+        }
+        Ok(properties)
+    }
+}
+
+// Nodes have the columns id, labels and properties, labels are separated by ;
+// Relationships have the columns src, dst, type and properties, src and dst are node ids of the node file.
+// The ids only connect the two files, they are not stored
+pub fn import_csv(
+    graph: &mut GraphStore,
+    nodes_csv: &str,
+    relationships_csv: &str,
+) -> Result<ImportSummary, String> {
+    let table = CsvTable::parse(nodes_csv, &["id"], &["labels"])?;
+    let mut ids = HashMap::new();
+    let mut nodes = Vec::new();
+    for (line, row) in &table.rows {
+        let id = table.cell(row, 0);
+        if ids.insert(id.to_string(), nodes.len()).is_some() {
+            return Err(format!("Duplicate node id '{}' on line {}", id, line));
+        }
+        nodes.push(NodeRecord {
+            labels: table
+                .cell(row, 1)
+                .split(';')
+                .map(|label| label.trim())
+                .filter(|label| !label.is_empty())
+                .map(|label| label.to_string())
+                .collect(),
+            properties: table.properties(*line, row)?,
+        });
+    }
+
+    let mut relationships = Vec::new();
+    if !relationships_csv.trim().is_empty() {
+        let table = CsvTable::parse(relationships_csv, &["src", "dst", "type"], &[])?;
+        for (line, row) in &table.rows {
+            let node = |column: usize| {
+                let id = table.cell(row, column);
+                ids.get(id)
+                    .copied()
+                    .ok_or_else(|| format!("Unknown node id '{}' on line {}", id, line))
+            };
+            let rel_type = table.cell(row, 2).trim();
+            if rel_type.is_empty() {
+                return Err(format!("Missing relationship type on line {}", line));
+            }
+            relationships.push(RelationshipRecord {
+                start: node(0)?,
+                end: node(1)?,
+                rel_type: rel_type.to_string(),
+                properties: table.properties(*line, row)?,
+            });
+        }
+    }
+    create_records(graph, nodes, relationships)
+}
+
+// GraphML
+
+// GraphML has no labels and relationship types, they are kept in the data keys with the ids
+// "labels" of nodes (":Person:Admin") and "label" of edges, like other graph databases do.
+// Exported properties get generated ids, so properties with these names do not collide
+const LABELS_KEY: &str = "labels";
+const TYPE_KEY: &str = "label";
+// Type of edges without a label
+const DEFAULT_TYPE: &str = "RELATED";
+
+#[derive(Debug, PartialEq)]
+enum XmlEvent {
+    Start {
+        name: String,
+        attributes: HashMap<String, String>,
+        // <node id="n0"/>
+        empty: bool,
+    },
+    End(String),
+    Text(String),
+}
+
+fn unescape_xml(text: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .ok_or_else(|| format!("Unterminated entity in '{}'", text))?;
+        let entity = &rest[start + 1..start + end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|n| n.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+                    .ok_or_else(|| format!("Unknown entity '&{};'", entity))?
+            }
+        };
+        result.push(c);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+// Namespace prefixes are dropped, GraphML only uses its own elements
+fn local_name(name: &str) -> String {
+    name.rsplit(':').next().unwrap_or(name).to_string()
+}
+
+// Just enough XML for GraphML: elements, attributes, text, CDATA and entities.
+// Declarations, comments and doctypes are skipped
+fn parse_xml(input: &str) -> Result<Vec<XmlEvent>, String> {
+    let mut events = Vec::new();
+    let mut rest = input;
+    let unterminated = |what: &str| format!("Unterminated {}", what);
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            events.push(XmlEvent::Text(unescape_xml(rest)?));
+            break;
+        };
+        if start > 0 {
+            events.push(XmlEvent::Text(unescape_xml(&rest[..start])?));
+        }
+        rest = &rest[start..];
+        if let Some(body) = rest.strip_prefix("<!--") {
+            let end = body.find("-->").ok_or_else(|| unterminated("comment"))?;
+            rest = &body[end + 3..];
+        } else if let Some(body) = rest.strip_prefix("<![CDATA[") {
+            let end = body.find("]]>").ok_or_else(|| unterminated("CDATA"))?;
+            events.push(XmlEvent::Text(body[..end].to_string()));
+            rest = &body[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest.find('>').ok_or_else(|| unterminated("declaration"))?;
+            rest = &rest[end + 1..];
+        } else if let Some(body) = rest.strip_prefix("</") {
+            let end = body.find('>').ok_or_else(|| unterminated("tag"))?;
+            events.push(XmlEvent::End(local_name(body[..end].trim())));
+            rest = &body[end + 1..];
+        } else {
+            let (event, remaining) = parse_start_tag(&rest[1..])?;
+            events.push(event);
+            rest = remaining;
+        }
+    }
+    Ok(events)
+}
+
+// Element name and attributes up to the closing >, returns the input after it
+fn parse_start_tag(input: &str) -> Result<(XmlEvent, &str), String> {
+    let name_end = input
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .ok_or("Unterminated tag")?;
+    let name = local_name(&input[..name_end]);
+    let mut attributes = HashMap::new();
+    let mut rest = &input[name_end..];
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            let event = XmlEvent::Start {
+                name,
+                attributes,
+                empty: true,
+            };
+            return Ok((event, after));
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            let event = XmlEvent::Start {
+                name,
+                attributes,
+                empty: false,
+            };
+            return Ok((event, after));
+        }
+        let (attribute, value) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid attribute in <{}>", name))?;
+        let value = value.trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| format!("Attribute value of <{}> must be quoted", name))?;
+        let end = value[1..]
+            .find(quote)
+            .ok_or_else(|| format!("Unterminated attribute value in <{}>", name))?;
+        attributes.insert(
+            local_name(attribute.trim()),
+            unescape_xml(&value[1..end + 1])?,
+        );
+        rest = &value[end + 2..];
+    }
+}
+
+struct GraphMLKey {
+    name: String,
+    attr_type: String,
+    default: Option<String>,
+}
+
+fn graphml_value(key: &GraphMLKey, text: &str) -> Result<serde_json::Value, String> {
+    let invalid = || {
+        format!(
+            "Invalid {} value '{}' for key '{}'",
+            key.attr_type, text, key.name
+        )
+    };
+    let text = text.trim();
+    Ok(match key.attr_type.as_str() {
+        "boolean" => match text {
+            "true" | "1" => serde_json::Value::Bool(true),
+            "false" | "0" => serde_json::Value::Bool(false),
+            _ => return Err(invalid()),
+        },
+        "int" | "long" => serde_json::Value::from(text.parse::<i64>().map_err(|_| invalid())?),
+        "float" | "double" => {
+            let n = text.parse::<f64>().map_err(|_| invalid())?;
+            if !n.is_finite() {
+                return Err(invalid());
+            }
+            number_to_json(n)
+        }
+        _ => serde_json::Value::String(text.to_string()),
+    })
+}
+
+// Node or edge being read, with the data of its keys
+struct GraphMLElement {
+    attributes: HashMap<String, String>,
+    data: BTreeMap<String, String>,
+}
+
+pub fn import_graphml(graph: &mut GraphStore, input: &str) -> Result<ImportSummary, String> {
+    let mut keys: HashMap<String, GraphMLKey> = HashMap::new();
+    let mut node_keys = Vec::new();
+    let mut edge_keys = Vec::new();
+    let mut ids = HashMap::new();
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+
+    // The key, node or edge that is open, and the data key and text being read
+    let mut key: Option<(String, String)> = None;
+    let mut element: Option<(String, GraphMLElement)> = None;
+    let mut data: Option<(String, String)> = None;
+    let mut in_default = false;
+
+    for event in parse_xml(input)? {
+        match event {
+            XmlEvent::Start {
+                name,
+                attributes,
+                empty,
+            } => match name.as_str() {
+                "key" => {
+                    let attribute = |name: &str| attributes.get(name).cloned();
+                    let id = attribute("id").ok_or("<key> needs an id")?;
+                    let domain = attribute("for").unwrap_or("all".to_string());
+                    if domain == "node" || domain == "all" {
+                        node_keys.push(id.clone());
+                    }
+                    if domain == "edge" || domain == "all" {
+                        edge_keys.push(id.clone());
+                    }
+                    keys.insert(
+                        id.clone(),
+                        GraphMLKey {
+                            name: attribute("attr.name").unwrap_or(id.clone()),
+                            attr_type: attribute("attr.type").unwrap_or("string".to_string()),
+                            default: None,
+                        },
+                    );
+                    if !empty {
+                        key = Some((id, String::new()));
+                    }
+                }
+                "default" if key.is_some() => in_default = true,
+                "node" | "edge" => {
+                    let element_value = GraphMLElement {
+                        attributes,
+                        data: BTreeMap::new(),
+                    };
+                    if empty {
+                        match name.as_str() {
+                            "node" => nodes.push(element_value),
+                            _ => edges.push(element_value),
+                        }
+                    } else {
+                        element = Some((name, element_value));
+                    }
+                }
+                "data" if element.is_some() && !empty => {
+                    let key = attributes.get("key").cloned().ok_or("<data> needs a key")?;
+                    data = Some((key, String::new()));
+                }
+                _ => {}
+            },
+            XmlEvent::Text(text) => {
+                if let Some((_, value)) = data.as_mut() {
+                    value.push_str(&text);
+                } else if let (true, Some((_, value))) = (in_default, key.as_mut()) {
+                    value.push_str(&text);
+                }
+            }
+            XmlEvent::End(name) => match name.as_str() {
+                "default" => in_default = false,
+                "key" => {
+                    if let Some((id, default)) = key.take() {
+                        if let Some(key) = keys.get_mut(&id) {
+                            key.default =
+                                Some(default).filter(|default| !default.trim().is_empty());
+                        }
+                    }
+                }
+                "data" => {
+                    if let (Some((key, value)), Some((_, element))) =
+                        (data.take(), element.as_mut())
+                    {
+                        element.data.insert(key, value);
+                    }
+                }
+                "node" | "edge" => {
+                    if let Some((name, element)) = element.take() {
+                        match name.as_str() {
+                            "node" => nodes.push(element),
+                            _ => edges.push(element),
+                        }
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    // The key holding labels or types has the reserved id, like in our exports,
+    // or else the reserved name, so a property of that name still round-trips
+    let reserved_key = |element_keys: &[String], name: &str| {
+        element_keys
+            .iter()
+            .find(|id| *id == name)
+            .or_else(|| element_keys.iter().find(|id| keys[*id].name == name))
+            .cloned()
+    };
+    let labels_key = reserved_key(&node_keys, LABELS_KEY);
+    let type_key = reserved_key(&edge_keys, TYPE_KEY);
+
+    // Data of the element's keys, with the defaults of the keys it does not have
+    let properties = |element: &GraphMLElement, element_keys: &[String], skip: &Option<String>| {
+        let mut properties = Properties::new();
+        for id in element_keys {
+            let key = &keys[id];
+            let Some(text) = element.data.get(id).or(key.default.as_ref()) else {
+                continue;
+            };
+            if skip.as_ref() != Some(id) {
+                properties.insert(key.name.clone(), graphml_value(key, text)?);
+            }
+        }
+        for id in element.data.keys() {
+            if !element_keys.contains(id) {
+                return Err(format!("Unknown key '{}'", id));
+            }
+        }
+        Ok::<Properties, String>(properties)
+    };
+    let label_text = |element: &GraphMLElement, id: &Option<String>| {
+        let id = id.as_ref()?;
+        element
+            .data
+            .get(id)
+            .or(keys[id].default.as_ref())
+            .map(|text| text.trim().to_string())
+    };
+
+    let mut node_records = Vec::new();
+    for node in &nodes {
+        let id = node
+            .attributes
+            .get("id")
+            .ok_or("<node> needs an id")?
+            .clone();
+        if ids.insert(id.clone(), node_records.len()).is_some() {
+            return Err(format!("Duplicate node id '{}'", id));
+        }
+        node_records.push(NodeRecord {
+            labels: label_text(node, &labels_key)
+                .unwrap_or_default()
+                .split(':')
+                .filter(|label| !label.is_empty())
+                .map(|label| label.to_string())
+                .collect(),
+            properties: properties(node, &node_keys, &labels_key)?,
+        });
+    }
+
+    let mut relationship_records = Vec::new();
+    for edge in &edges {
+        let node = |attribute: &str| {
+            let id = edge
+                .attributes
+                .get(attribute)
+                .ok_or_else(|| format!("<edge> needs a {}", attribute))?;
+            ids.get(id)
+                .copied()
+                .ok_or_else(|| format!("Unknown node id '{}'", id))
+        };
+        relationship_records.push(RelationshipRecord {
+            start: node("source")?,
+            end: node("target")?,
+            rel_type: label_text(edge, &type_key)
+                .filter(|rel_type| !rel_type.is_empty())
+                .unwrap_or(DEFAULT_TYPE.to_string()),
+            properties: properties(edge, &edge_keys, &type_key)?,
+        });
+    }
+    create_records(graph, node_records, relationship_records)
+}
+
+// GraphML type of a property, a property with values of several types is exported as string
+fn graphml_type<'a>(values: impl Iterator<Item = &'a serde_json::Value>) -> &'static str {
+    let mut attr_type = None;
+    for value in values {
+        let value_type = match value {
+            serde_json::Value::Bool(_) => "boolean",
+            serde_json::Value::Number(n) if n.is_f64() => "double",
+            serde_json::Value::Number(_) => "long",
+            _ => "string",
+        };
+        attr_type = match (attr_type, value_type) {
+            (None, value_type) => Some(value_type),
+            (Some("long"), "double") | (Some("double"), "long") => Some("double"),
+            (Some(attr_type), value_type) if attr_type == value_type => Some(attr_type),
+            _ => Some("string"),
+        };
+    }
+    attr_type.unwrap_or("string")
+}
+
+fn graphml_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Property keys of nodes or edges with their GraphML ids and types
+fn graphml_keys<'a>(
+    prefix: &str,
+    properties: impl Iterator<Item = &'a Properties>,
+) -> BTreeMap<String, (String, &'static str)> {
+    let mut values: BTreeMap<&String, Vec<&serde_json::Value>> = BTreeMap::new();
+    for properties in properties {
+        for (name, value) in properties {
+            values.entry(name).or_default().push(value);
+        }
+    }
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, values))| {
+            let key = (format!("{}{}", prefix, i), graphml_type(values.into_iter()));
+            (name.clone(), key)
+        })
+        .collect()
+}
+
+fn write_data(
+    output: &mut String,
+    keys: &BTreeMap<String, (String, &str)>,
+    properties: &Properties,
+) {
+    for (name, value) in properties {
+        output.push_str(&format!(
+            "      <data key=\"{}\">{}</data>\n",
+            keys[name].0,
+            escape_xml(&graphml_text(value))
+        ));
+    }
+}
+
+pub fn export_graphml(graph: &GraphStore) -> String {
+    let node_keys = graphml_keys("n", graph.nodes().map(|node| &node.properties));
+    let edge_keys = graphml_keys(
+        "e",
+        graph
+            .relationships()
+            .map(|relationship| &relationship.properties),
+    );
+
+    let mut output = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+    );
+    output.push_str(&format!(
+        "  <key id=\"{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"string\"/>\n",
+        LABELS_KEY
+    ));
+    output.push_str(&format!(
+        "  <key id=\"{0}\" for=\"edge\" attr.name=\"{0}\" attr.type=\"string\"/>\n",
+        TYPE_KEY
+    ));
+    for (domain, keys) in [("node", &node_keys), ("edge", &edge_keys)] {
+        for (name, (id, attr_type)) in keys {
+            output.push_str(&format!(
+                "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>\n",
+                id,
+                domain,
+                escape_xml(name),
+                attr_type
+            ));
+        }
+    }
+    output.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+    for node in graph.nodes() {
+        output.push_str(&format!("    <node id=\"n{}\">\n", node.id));
+        if !node.labels.is_empty() {
+            output.push_str(&format!(
+                "      <data key=\"{}\">:{}</data>\n",
+                LABELS_KEY,
+                escape_xml(&node.labels.join(":"))
+            ));
+        }
+        write_data(&mut output, &node_keys, &node.properties);
+        output.push_str("    </node>\n");
+    }
+    for relationship in graph.relationships() {
+        output.push_str(&format!(
+            "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">\n",
+            relationship.id, relationship.start, relationship.end
+        ));
+        output.push_str(&format!(
+            "      <data key=\"{}\">{}</data>\n",
+            TYPE_KEY,
+            escape_xml(&relationship.rel_type)
+        ));
+        write_data(&mut output, &edge_keys, &relationship.properties);
+        output.push_str("    </edge>\n");
+    }
+    output.push_str("  </graph>\n</graphml>\n");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_import() {
+        let mut graph = GraphStore::new();
+        let nodes = "id,labels,name,age,zip:string,admin\n\
+                     a,Person;Admin,\"Reeves, Keanu\",59,0123,true\r\n\
+                     \n\
+                     b,Person,\"Ann \"\"the\"\" first\",,42,false\n";
+        let edges = "src,dst,type,since\na,b,KNOWS,2020\nb,a,KNOWS,\n";
+        assert_eq!(
+            import_csv(&mut graph, nodes, edges),
+            Ok(ImportSummary {
+                nodes_created: 2,
+                relationships_created: 2,
+            })
+        );
+
+        let nodes: Vec<_> = graph.nodes().map(|node| node.to_json()).collect();
+        assert_eq!(nodes[0]["labels"], serde_json::json!(["Person", "Admin"]));
+        assert_eq!(
+            nodes[0]["properties"],
+            serde_json::json!({"name": "Reeves, Keanu", "age": 59, "zip": "0123", "admin": true})
+        );
+        assert_eq!(
+            nodes[1]["properties"],
+            serde_json::json!({"name": "Ann \"the\" first", "zip": "42", "admin": false})
+        );
+        let relationships: Vec<_> = graph.relationships().collect();
+        assert_eq!((relationships[0].start, relationships[0].end), (0, 1));
+        assert_eq!(relationships[0].rel_type, "KNOWS");
+        assert_eq!(
+            relationships[0].properties["since"],
+            serde_json::json!(2020)
+        );
+        assert!(relationships[1].properties.is_empty());
+        assert_eq!(graph.label_count("Person"), 2);
+    }
+
+    #[test]
+    fn test_csv_errors() {
+        let mut graph = GraphStore::new();
+        for (nodes, edges, error) in [
+            ("name\nAnn\n", "", "Missing column 'id'"),
+            ("id\na\na\n", "", "Duplicate node id 'a' on line 3"),
+            (
+                "id,age:number\na,old\n",
+                "",
+                "Invalid value 'old' for 'age' on line 2",
+            ),
+            ("id,name\na\n", "", "Line 2 has 1 fields, the header has 2"),
+            (
+                "id,name\na,\"Ann\n",
+                "",
+                "Unterminated quoted field on line 2",
+            ),
+            (
+                "id\na\n",
+                "src,dst,type\na,b,KNOWS\n",
+                "Unknown node id 'b' on line 2",
+            ),
+            ("id\na\n", "src,dst\na,a\n", "Missing column 'type'"),
+        ] {
+            assert_eq!(import_csv(&mut graph, nodes, edges), Err(error.to_string()));
+        }
+        // Nothing is created when a file is rejected
+        assert_eq!(graph.nodes().count(), 0);
+    }
+
+    #[test]
+    fn test_graphml_import() {
+        let mut graph = GraphStore::new();
+        let input = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!-- exported by another tool -->
+            <graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+              <key id="d0" for="node" attr.name="labels" attr.type="string"/>
+              <key id="d1" for="node" attr.name="name" attr.type="string"/>
+              <key id="d2" for="node" attr.name="score" attr.type="double">
+                <default>1.5</default>
+              </key>
+              <key id="d3" for="edge" attr.name="weight" attr.type="int"/>
+              <graph id="G" edgedefault="directed">
+                <node id="x"><data key="d0">:Person:Admin</data><data key="d1">Tom &amp; Jerry</data></node>
+                <node id='y'/>
+                <edge source="x" target="y"><data key="d3">3</data></edge>
+              </graph>
+            </graphml>"#;
+        assert_eq!(
+            import_graphml(&mut graph, input),
+            Ok(ImportSummary {
+                nodes_created: 2,
+                relationships_created: 1,
+            })
+        );
+        let nodes: Vec<_> = graph.nodes().collect();
+        assert_eq!(nodes[0].labels, vec!["Person", "Admin"]);
+        assert_eq!(
+            serde_json::Value::Object(nodes[0].properties.clone()),
+            serde_json::json!({"name": "Tom & Jerry", "score": 1.5})
+        );
+        assert!(nodes[1].labels.is_empty());
+        let relationship = graph.relationships().next().unwrap();
+        assert_eq!(relationship.rel_type, DEFAULT_TYPE);
+        assert_eq!(relationship.properties["weight"], serde_json::json!(3));
+
+        assert_eq!(
+            import_graphml(
+                &mut graph,
+                "<graphml><graph><edge source=\"a\" target=\"b\"/></graph></graphml>"
+            ),
+            Err("Unknown node id 'a'".to_string())
+        );
+        assert!(import_graphml(&mut graph, "<graphml><key id=\"k\" attr.type=\"int\"/><graph><node id=\"a\"><data key=\"k\">x</data></node></graph></graphml>").is_err());
+        assert_eq!(graph.nodes().count(), 2);
+    }
+
+    #[test]
+    fn test_graphml_round_trip() {
+        let mut graph = GraphStore::new();
+        import_csv(
+            &mut graph,
+            "id,labels,name,age,score\na,Person,<Ann & \"Bo\">,31,1.5\nb,City;Capital,Oslo,1000,2\nc,,,,\n",
+            "src,dst,type,since\na,b,LIVES_IN,2020\nb,c,NEAR,\n",
+        )
+        .unwrap();
+        // Properties named like the reserved label and type keys stay properties
+        graph
+            .create_relationship(
+                0,
+                1,
+                "TAGGED".to_string(),
+                serde_json::json!({"label": "home", "labels": "x"})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .unwrap();
+        graph.create_node(
+            vec!["Tag".to_string()],
+            serde_json::json!({"labels": ":Fake", "label": "y"})
+                .as_object()
+                .unwrap()
+                .clone(),
+        );
+        let exported = export_graphml(&graph);
+        assert!(exported.contains("attr.name=\"age\" attr.type=\"long\""));
+        assert!(exported.contains("attr.name=\"score\" attr.type=\"double\""));
+        assert!(exported.contains("&lt;Ann &amp; &quot;Bo&quot;&gt;"));
+
+        let mut copy = GraphStore::new();
+        import_graphml(&mut copy, &exported).unwrap();
+        let json = |graph: &GraphStore| {
+            (
+                graph.nodes().map(|node| node.to_json()).collect::<Vec<_>>(),
+                graph
+                    .relationships()
+                    .map(|relationship| relationship.to_json())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(json(&copy), json(&graph));
+    }
+}
@@ -43,4 +43,10 @@
 // MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
 // MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
 
+pub mod algorithms;
 pub mod cypher;
+pub mod error;
+pub mod formats;
+pub mod query_handler;
+pub mod store;
+pub mod witchvm;
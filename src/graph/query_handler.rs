@@ -0,0 +1,1052 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::graph::cypher;
+use crate::graph::error::Error;
+use crate::graph::store::GraphStore;
+use crate::graph::witchvm::WitchVMGraph;
+use crate::kv::database::Database;
+use crate::kv::query_handler::QueryResult;
+
+// The database is locked before the graph, like SQL queries do, so the two never deadlock
+pub async fn handle_cypher_query(
+    database: Arc<Mutex<Database>>,
+    graph: Arc<Mutex<GraphStore>>,
+    query: String,
+    params: serde_json::Map<String, serde_json::Value>,
+) -> Result<QueryResult, Error> {
+    let database = database.lock().await;
+    let mut graph = graph.lock().await;
+    let start = Instant::now();
+    let mut parser = cypher::Parser::new(&query).with_parameters(params);
+    let statement = parser.parse_statement()?;
+    let mut generator = cypher::CodeGenerator::new();
+    generator
+        .generate_statement(&statement)
+        .map_err(Error::QueryError)?;
+    let mut vm = WitchVMGraph::new();
+    vm.execute(&mut graph, &database, generator.instructions)
+        .map_err(Error::ExecutionError)?;
+    Ok(QueryResult::new(
+        Some(generator.columns),
+        vm.into_output(),
+        start.elapsed(),
+    ))
+}
+
+// Runs the query and reports how the nodes of every pattern were found.
+// Only reads can be explained, since the query really runs
+pub async fn explain_cypher_query(
+    database: Arc<Mutex<Database>>,
+    graph: Arc<Mutex<GraphStore>>,
+    query: String,
+    params: serde_json::Map<String, serde_json::Value>,
+) -> Result<String, Error> {
+    let database = database.lock().await;
+    let mut graph = graph.lock().await;
+    let mut parser = cypher::Parser::new(&query).with_parameters(params);
+    let statement = parser.parse_statement()?;
+    if !is_read_only(&statement) {
+        return Err(Error::QueryError(
+            "Only queries without CREATE, MERGE, SET, REMOVE, DELETE or index changes can be explained"
+                .to_string(),
+        ));
+    }
+    let mut generator = cypher::CodeGenerator::new();
+    generator
+        .generate_statement(&statement)
+        .map_err(Error::QueryError)?;
+    let mut vm = WitchVMGraph::new();
+    let steps = vm
+        .execute(&mut graph, &database, generator.instructions)
+        .map_err(Error::ExecutionError)?;
+    Ok(steps
+        .into_iter()
+        .map(|x| serde_json::to_string(&x).unwrap_or("{}".to_string()))
+        .collect::<Vec<String>>()
+        .join(","))
+}
+
+fn is_read_only(statement: &cypher::Statement) -> bool {
+    let cypher::Statement::Query(query) = statement else {
+        return false;
+    };
+    let mut part = Some(&**query);
+    while let Some(query) = part {
+        if !query.updates.is_empty() {
+            return false;
+        }
+        part = query.next.as_deref();
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::store::Properties;
+
+    fn database() -> Arc<Mutex<Database>> {
+        Arc::new(Mutex::new(Database::new()))
+    }
+
+    fn properties(value: serde_json::Value) -> Properties {
+        value.as_object().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_match_and_return() {
+        let mut graph = GraphStore::new();
+        graph.create_node(
+            vec!["Person".to_string()],
+            properties(serde_json::json!({"name": "Keanu Reeves", "born": 1964})),
+        );
+        graph.create_node(
+            vec!["Person".to_string(), "Director".to_string()],
+            properties(serde_json::json!({"name": "Lana Wachowski", "born": 1965})),
+        );
+        graph.create_node(
+            vec!["Movie".to_string()],
+            properties(serde_json::json!({"title": "The Matrix"})),
+        );
+        let graph = Arc::new(Mutex::new(graph));
+
+        let result = handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (p:Person {born: 1964}) RETURN p.name AS name, p.born".to_string(),
+            serde_json::Map::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.columns, vec!["name", "p.born"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                serde_json::json!("Keanu Reeves"),
+                serde_json::json!(1964)
+            ]]
+        );
+
+        let result = handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (d:Person:Director), (m:Movie) RETURN d.name, m".to_string(),
+            serde_json::Map::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.row_count, 1);
+        assert_eq!(result.rows[0][1]["labels"], serde_json::json!(["Movie"]));
+        assert_eq!(
+            result.rows[0][1]["properties"]["title"],
+            serde_json::json!("The Matrix")
+        );
+
+        assert!(handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (p:Person) RETURN q.name".to_string(),
+            serde_json::Map::new()
+        )
+        .await
+        .is_err());
+    }
+
+    async fn query(graph: &Arc<Mutex<GraphStore>>, query: &str) -> Vec<Vec<serde_json::Value>> {
+        handle_cypher_query(
+            database(),
+            graph.clone(),
+            query.to_string(),
+            serde_json::Map::new(),
+        )
+        .await
+        .unwrap()
+        .rows
+    }
+
+    #[tokio::test]
+    async fn test_relationship_patterns() {
+        let mut graph = GraphStore::new();
+        let mut people = Vec::new();
+        for name in ["Ann", "Bob", "Cid", "Dan"] {
+            people.push(graph.create_node(
+                vec!["Person".to_string()],
+                properties(serde_json::json!({ "name": name })),
+            ));
+        }
+        let company = graph.create_node(
+            vec!["Company".to_string()],
+            properties(serde_json::json!({"name": "Acme"})),
+        );
+        for (from, to, since) in [(0, 1, 2020), (1, 2, 2021), (2, 3, 2020)] {
+            graph
+                .create_relationship(
+                    people[from],
+                    people[to],
+                    "KNOWS".to_string(),
+                    properties(serde_json::json!({ "since": since })),
+                )
+                .unwrap();
+        }
+        graph
+            .create_relationship(
+                people[3],
+                company,
+                "WORKS_AT".to_string(),
+                Properties::new(),
+            )
+            .unwrap();
+        let graph = Arc::new(Mutex::new(graph));
+
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (a:Person)-[r:KNOWS {since: 2020}]->(b:Person) RETURN a.name, r.since, b.name"
+            )
+            .await,
+            vec![
+                vec![
+                    serde_json::json!("Ann"),
+                    serde_json::json!(2020),
+                    serde_json::json!("Bob")
+                ],
+                vec![
+                    serde_json::json!("Cid"),
+                    serde_json::json!(2020),
+                    serde_json::json!("Dan")
+                ],
+            ]
+        );
+
+        // Reverse and undirected relationships
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (b {name: 'Bob'})<-[:KNOWS]-(a) RETURN a.name"
+            )
+            .await,
+            vec![vec![serde_json::json!("Ann")]]
+        );
+        assert_eq!(
+            query(&graph, "MATCH (b {name: 'Bob'})-[:KNOWS]-(x) RETURN x.name").await,
+            vec![
+                vec![serde_json::json!("Cid")],
+                vec![serde_json::json!("Ann")]
+            ]
+        );
+
+        // Chains, with the relationships of one MATCH used once
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (a:Person)-[:KNOWS]->()-[:KNOWS]->(c)-[:KNOWS]->(d)-[:WORKS_AT]->(w:Company) RETURN a.name, w.name"
+            )
+            .await,
+            vec![vec![serde_json::json!("Ann"), serde_json::json!("Acme")]]
+        );
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (a {name: 'Bob'})-[:KNOWS]-(b)-[:KNOWS]-(c) RETURN c.name"
+            )
+            .await,
+            vec![vec![serde_json::json!("Dan")]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_variable_length_and_shortest_path() {
+        let mut graph = GraphStore::new();
+        let mut people = Vec::new();
+        for name in ["Ann", "Bob", "Cid", "Dan", "Eve"] {
+            people.push(graph.create_node(
+                vec!["Person".to_string()],
+                properties(serde_json::json!({ "name": name })),
+            ));
+        }
+        // Ann -> Bob -> Cid -> Dan -> Ann and a shortcut Ann -> Cid
+        for (from, to) in [(0, 1), (1, 2), (2, 3), (3, 0), (0, 2)] {
+            graph
+                .create_relationship(
+                    people[from],
+                    people[to],
+                    "KNOWS".to_string(),
+                    Properties::new(),
+                )
+                .unwrap();
+        }
+        let graph = Arc::new(Mutex::new(graph));
+
+        let names = |rows: Vec<Vec<serde_json::Value>>| {
+            rows.into_iter()
+                .map(|row| row[0].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            names(
+                query(
+                    &graph,
+                    "MATCH (a {name: 'Ann'})-[:KNOWS*2..3]->(b) RETURN b.name"
+                )
+                .await
+            ),
+            vec!["Cid", "Dan", "Dan", "Ann"]
+        );
+        // The cycle ends when every relationship has been used once
+        assert_eq!(
+            query(&graph, "MATCH (a {name: 'Ann'})-[*]->(b) RETURN b.name")
+                .await
+                .len(),
+            10
+        );
+        // Expanding from the right end keeps the list in pattern order
+        let rows = query(
+            &graph,
+            "MATCH (a)-[r:KNOWS*2]->(b {name: 'Dan'}) RETURN a.name, r",
+        )
+        .await;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][1][0]["start"], serde_json::json!(people[0]));
+        assert_eq!(rows[0][1][1]["end"], serde_json::json!(people[3]));
+
+        let rows = query(
+            &graph,
+            "MATCH p = shortestPath((a {name: 'Ann'})-[:KNOWS*]->(d {name: 'Dan'})) RETURN p",
+        )
+        .await;
+        assert_eq!(rows.len(), 1);
+        let nodes = rows[0][0]["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| node["properties"]["name"].as_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(nodes, vec!["Ann", "Cid", "Dan"]);
+        assert_eq!(rows[0][0]["relationships"].as_array().unwrap().len(), 2);
+
+        assert!(query(
+            &graph,
+            "MATCH p = shortestPath((a {name: 'Ann'})-[*..3]->(e {name: 'Eve'})) RETURN p"
+        )
+        .await
+        .is_empty());
+
+        // A second MATCH reuses the nodes bound by the first
+        let rows = query(
+            &graph,
+            "MATCH (a {name: 'Ann'}), (d {name: 'Dan'}) MATCH p = shortestPath((a)-[*]-(d)) RETURN p",
+        )
+        .await;
+        assert_eq!(rows.len(), 1);
+        // Without a direction Dan -> Ann is the shortest
+        assert_eq!(rows[0][0]["relationships"].as_array().unwrap().len(), 1);
+        let rows = query(
+            &graph,
+            "MATCH (a) WHERE a.name = 'Ann' MATCH (a)-->(b) WHERE b.name <> 'Bob' RETURN b.name",
+        )
+        .await;
+        assert_eq!(rows, vec![vec![serde_json::json!("Cid")]]);
+
+        let rows = query(&graph, "MATCH p = (a {name: 'Bob'})-->(b)<--(c) RETURN p").await;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0][0]["nodes"][2]["properties"]["name"],
+            serde_json::json!("Ann")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_clauses() {
+        let graph = Arc::new(Mutex::new(GraphStore::new()));
+
+        let result = handle_cypher_query(
+            database(),
+            graph.clone(),
+            "CREATE (a:Person {name: 'Ann'})-[:KNOWS {since: 2020}]->(b:Person {name: 'Bob'}), (a)-[:KNOWS]->(:Person {name: 'Cid'})"
+                .to_string(), serde_json::Map::new())
+        .await
+        .unwrap();
+        assert_eq!(
+            result.columns[..3],
+            ["nodes_created", "nodes_deleted", "relationships_created"]
+        );
+        assert_eq!(
+            result.rows[0][..3],
+            [
+                serde_json::json!(3),
+                serde_json::json!(0),
+                serde_json::json!(2)
+            ]
+        );
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (a {name: 'Ann'})-[r:KNOWS]->(b) RETURN b.name, r.since"
+            )
+            .await,
+            vec![
+                vec![serde_json::json!("Bob"), serde_json::json!(2020)],
+                vec![serde_json::json!("Cid"), serde_json::json!(null)],
+            ]
+        );
+
+        // MERGE matches the first time and creates the second time
+        let merge = "MATCH (a {name: 'Ann'}) MERGE (a)-[:LIVES_IN]->(c:City {name: 'Oslo'}) \
+            ON CREATE SET c.created = true ON MATCH SET c.matched = true RETURN c.created, c.matched";
+        assert_eq!(
+            query(&graph, merge).await,
+            vec![vec![serde_json::json!(true), serde_json::json!(null)]]
+        );
+        assert_eq!(
+            query(&graph, merge).await,
+            vec![vec![serde_json::json!(true), serde_json::json!(true)]]
+        );
+
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (b {name: 'Bob'}) SET b.age = 31, b:Admin REMOVE b:Person RETURN b.age, b"
+            )
+            .await[0][1]["labels"],
+            serde_json::json!(["Admin"])
+        );
+        assert_eq!(
+            query(&graph, "MATCH (b:Admin) REMOVE b.age RETURN b.age").await,
+            vec![vec![serde_json::json!(null)]]
+        );
+
+        // A node with relationships needs DETACH, the failed query changes nothing
+        assert!(handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (c {name: 'Cid'}) SET c.age = 1 DELETE c".to_string(),
+            serde_json::Map::new()
+        )
+        .await
+        .is_err());
+        assert_eq!(
+            query(&graph, "MATCH (c {name: 'Cid'}) RETURN c.age").await,
+            vec![vec![serde_json::json!(null)]]
+        );
+
+        query(&graph, "MATCH (c {name: 'Cid'}) DETACH DELETE c").await;
+        query(&graph, "MATCH ({name: 'Ann'})-[r:KNOWS]->() DELETE r").await;
+        assert!(query(&graph, "MATCH (c {name: 'Cid'}) RETURN c")
+            .await
+            .is_empty());
+        assert_eq!(
+            query(&graph, "MATCH (a {name: 'Ann'})-->(x) RETURN x.name").await,
+            vec![vec![serde_json::json!("Oslo")]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_where() {
+        let mut graph = GraphStore::new();
+        for person in [
+            serde_json::json!({"name": "John", "age": 35, "city": "Oslo"}),
+            serde_json::json!({"name": "Jane", "age": 41, "city": "Rome", "vip": true}),
+            serde_json::json!({"name": "Jack", "age": 25, "city": "Oslo"}),
+            serde_json::json!({"name": "Mary", "age": 50, "city": "Oslo"}),
+            serde_json::json!({"name": "Jim", "city": "Oslo"}),
+        ] {
+            graph.create_node(vec!["Person".to_string()], properties(person));
+        }
+        let graph = Arc::new(Mutex::new(graph));
+
+        let names = |rows: Vec<Vec<serde_json::Value>>| {
+            rows.into_iter()
+                .map(|row| row[0].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        let cases = [
+            (
+                "n.age > 30 AND (n.city = 'Oslo' OR n.vip = true) AND n.name STARTS WITH 'J'",
+                vec!["John", "Jane"],
+            ),
+            ("n.age IS NULL", vec!["Jim"]),
+            ("NOT n.age < 40", vec!["Jane", "Mary"]),
+            (
+                "n.name IN ['Jack', 'Jim'] OR n.age + 10 = 60",
+                vec!["Jack", "Mary", "Jim"],
+            ),
+            ("n.age % 10 = 5 AND n.name ENDS WITH 'n'", vec!["John"]),
+            ("n.city CONTAINS 'om' XOR n.age >= 50", vec!["Jane", "Mary"]),
+            ("n.age * 2 / 10 > 9 OR n.vip", vec!["Jane", "Mary"]),
+            ("n.name + '!' = 'Jim!' AND true", vec!["Jim"]),
+        ];
+        for (condition, expected) in cases {
+            let rows = query(
+                &graph,
+                &format!("MATCH (n:Person) WHERE {} RETURN n.name", condition),
+            )
+            .await;
+            assert_eq!(names(rows), expected, "{}", condition);
+        }
+
+        let result = handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (n {name: 'John'}) RETURN n.age - 5, n.age IN [35, null], n.x IN [1, null]"
+                .to_string(),
+            serde_json::Map::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            result.columns,
+            vec!["n.age - 5", "n.age IN [35, null]", "n.x IN [1, null]"]
+        );
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                serde_json::json!(30),
+                serde_json::json!(true),
+                serde_json::json!(null)
+            ]]
+        );
+
+        assert!(handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (n) WHERE n.name RETURN n".to_string(),
+            serde_json::Map::new()
+        )
+        .await
+        .is_err());
+        assert!(handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (n) WHERE n.age / 0 > 1 RETURN n".to_string(),
+            serde_json::Map::new()
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_projections() {
+        let mut graph = GraphStore::new();
+        let mut people = Vec::new();
+        for person in [
+            serde_json::json!({"name": "Ann", "age": 30, "city": "Oslo"}),
+            serde_json::json!({"name": "Bob", "age": 40, "city": "Rome"}),
+            serde_json::json!({"name": "Cid", "age": 50, "city": "Oslo"}),
+            serde_json::json!({"name": "Dan", "city": "Oslo"}),
+        ] {
+            people.push(graph.create_node(vec!["Person".to_string()], properties(person)));
+        }
+        for (from, to) in [(0, 1), (0, 2), (1, 2)] {
+            graph
+                .create_relationship(
+                    people[from],
+                    people[to],
+                    "KNOWS".to_string(),
+                    Properties::new(),
+                )
+                .unwrap();
+        }
+        let graph = Arc::new(Mutex::new(graph));
+
+        let result = handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (n:Person) RETURN n.city AS city, count(*), count(n.age) AS aged, avg(n.age), min(n.age), max(n.name), sum(n.age), collect(n.name) AS names ORDER BY city".to_string(), serde_json::Map::new())
+        .await
+        .unwrap();
+        assert_eq!(
+            result.columns,
+            vec![
+                "city",
+                "count(*)",
+                "aged",
+                "avg(n.age)",
+                "min(n.age)",
+                "max(n.name)",
+                "sum(n.age)",
+                "names"
+            ]
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    serde_json::json!("Oslo"),
+                    serde_json::json!(3),
+                    serde_json::json!(2),
+                    serde_json::json!(40),
+                    serde_json::json!(30),
+                    serde_json::json!("Dan"),
+                    serde_json::json!(80),
+                    serde_json::json!(["Ann", "Cid", "Dan"]),
+                ],
+                vec![
+                    serde_json::json!("Rome"),
+                    serde_json::json!(1),
+                    serde_json::json!(1),
+                    serde_json::json!(40),
+                    serde_json::json!(40),
+                    serde_json::json!("Bob"),
+                    serde_json::json!(40),
+                    serde_json::json!(["Bob"]),
+                ],
+            ]
+        );
+
+        // Aggregating no rows without grouping keys gives one row
+        assert_eq!(
+            query(&graph, "MATCH (n:Company) RETURN count(*), avg(n.age)").await,
+            vec![vec![serde_json::json!(0), serde_json::json!(null)]]
+        );
+
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (n:Person) RETURN DISTINCT n.city ORDER BY n.city DESC"
+            )
+            .await,
+            vec![
+                vec![serde_json::json!("Rome")],
+                vec![serde_json::json!("Oslo")]
+            ]
+        );
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (n:Person) RETURN n.name ORDER BY n.age DESC, n.name SKIP 1 LIMIT 2"
+            )
+            .await,
+            vec![
+                vec![serde_json::json!("Bob")],
+                vec![serde_json::json!("Ann")]
+            ]
+        );
+
+        // WITH chains query parts and filters on aggregates
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (a:Person)-[:KNOWS]->(b) WITH a, count(b) AS friends WHERE friends > 1 \
+                 MATCH (a)-[:KNOWS]->(c) RETURN a.name, friends, count(DISTINCT c.city) AS cities"
+            )
+            .await,
+            vec![vec![
+                serde_json::json!("Ann"),
+                serde_json::json!(2),
+                serde_json::json!(2)
+            ]]
+        );
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (n:Person) WITH n.age AS age ORDER BY age DESC LIMIT 2 RETURN sum(age) * 2 AS total"
+            )
+            .await,
+            vec![vec![serde_json::json!(180)]]
+        );
+
+        for invalid in [
+            "MATCH (n) WITH n.age RETURN n",
+            "MATCH (n) WITH n AS m RETURN n",
+            "MATCH (n) WHERE count(*) > 1 RETURN n",
+            "MATCH (n) RETURN count(count(*))",
+        ] {
+            assert!(
+                handle_cypher_query(
+                    database(),
+                    graph.clone(),
+                    invalid.to_string(),
+                    serde_json::Map::new()
+                )
+                .await
+                .is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_procedures() {
+        let mut graph = GraphStore::new();
+        let mut people = Vec::new();
+        for name in ["Ann", "Bob", "Cid", "Dan"] {
+            people.push(graph.create_node(
+                vec!["Person".to_string()],
+                properties(serde_json::json!({ "name": name })),
+            ));
+        }
+        for (from, to) in [(0, 1), (1, 2), (2, 0), (3, 0)] {
+            graph
+                .create_relationship(
+                    people[from],
+                    people[to],
+                    "KNOWS".to_string(),
+                    Properties::new(),
+                )
+                .unwrap();
+        }
+        let graph = Arc::new(Mutex::new(graph));
+
+        assert_eq!(
+            query(
+                &graph,
+                "CALL algo.pageRank('Person', 'KNOWS', {iterations: 20}) YIELD node, score \
+                 RETURN node.name ORDER BY score DESC LIMIT 1"
+            )
+            .await,
+            vec![vec![serde_json::json!("Ann")]]
+        );
+        assert_eq!(
+            query(
+                &graph,
+                "CALL algo.triangleCount('Person', 'KNOWS') YIELD node, triangleCount AS triangles \
+                 WHERE triangles > 0 RETURN count(*)"
+            )
+            .await,
+            vec![vec![serde_json::json!(3)]]
+        );
+        assert_eq!(
+            query(
+                &graph,
+                "CALL algo.degree('Person', 'KNOWS', {orientation: 'REVERSE'}) YIELD node, score \
+                 MATCH (node)<-[:KNOWS]-(other) RETURN node.name, score, count(other) ORDER BY node.name"
+            )
+            .await,
+            vec![
+                vec![serde_json::json!("Ann"), serde_json::json!(2), serde_json::json!(2)],
+                vec![serde_json::json!("Bob"), serde_json::json!(1), serde_json::json!(1)],
+                vec![serde_json::json!("Cid"), serde_json::json!(1), serde_json::json!(1)],
+            ]
+        );
+
+        // A CALL on its own returns every field
+        let result = handle_cypher_query(
+            database(),
+            graph.clone(),
+            "CALL algo.wcc('Person')".to_string(),
+            serde_json::Map::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.columns, vec!["node", "componentId"]);
+        assert_eq!(result.row_count, 4);
+        assert_eq!(result.rows[3][1], serde_json::json!(0));
+
+        for invalid in [
+            "CALL algo.unknown()",
+            "CALL algo.wcc() YIELD rank",
+            "MATCH (node) CALL algo.wcc() YIELD node RETURN node",
+            "CALL algo.pageRank('Person', 'KNOWS', 20)",
+        ] {
+            assert!(
+                handle_cypher_query(
+                    database(),
+                    graph.clone(),
+                    invalid.to_string(),
+                    serde_json::Map::new()
+                )
+                .await
+                .is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_documents() {
+        let database = database();
+        {
+            let mut database = database.lock().await;
+            database.create_storage("people".to_string()).unwrap();
+            for (key, age) in [("ann", 31), ("bob", 42), ("cid", 27)] {
+                database
+                    .insert_document(
+                        "people".to_string(),
+                        key.to_string(),
+                        serde_json::json!({ "age": age, "address": { "city": "Oslo" } }),
+                    )
+                    .unwrap();
+            }
+        }
+        let graph = Arc::new(Mutex::new(GraphStore::new()));
+        let run = |query: &str| {
+            handle_cypher_query(
+                database.clone(),
+                graph.clone(),
+                query.to_string(),
+                serde_json::Map::new(),
+            )
+        };
+
+        run("CREATE (a:Person {name: 'Ann'})-[:FRIEND]->(b:Person {name: 'Bob'}), \
+             (a)-[:FRIEND]->(c:Person {name: 'Cid'}) \
+             SET doc(a) = ['people', 'ann'], doc(b) = ['people', 'bob'], doc(c) = ['people', 'cid']")
+        .await
+        .unwrap();
+
+        let result = run("MATCH (p:Person {name: 'Ann'})-[:FRIEND]->(f) \
+             RETURN f.name, doc(f).age, doc(f).address.city ORDER BY doc(f).age")
+        .await
+        .unwrap();
+        assert_eq!(
+            result.columns,
+            vec!["f.name", "doc(f).age", "doc(f).address.city"]
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    serde_json::json!("Cid"),
+                    serde_json::json!(27),
+                    serde_json::json!("Oslo")
+                ],
+                vec![
+                    serde_json::json!("Bob"),
+                    serde_json::json!(42),
+                    serde_json::json!("Oslo")
+                ],
+            ]
+        );
+
+        let result = run("MATCH (p:Person) WHERE doc(p).age > 30 RETURN p.name ORDER BY p.name")
+            .await
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![serde_json::json!("Ann")],
+                vec![serde_json::json!("Bob")]
+            ]
+        );
+
+        // Unlinked nodes and deleted documents read as null
+        run("MATCH (p {name: 'Bob'}) REMOVE doc(p)").await.unwrap();
+        database
+            .lock()
+            .await
+            .delete("people".to_string(), "cid".to_string())
+            .unwrap();
+        let result = run("MATCH (p:Person) RETURN p.name, doc(p) ORDER BY p.name")
+            .await
+            .unwrap();
+        assert_eq!(result.rows[0][1]["age"], serde_json::json!(31));
+        assert_eq!(result.rows[1][1], serde_json::Value::Null);
+        assert_eq!(result.rows[2][1], serde_json::Value::Null);
+
+        for invalid in [
+            "MATCH (p {name: 'Ann'}) SET doc(p) = 'people'",
+            "MATCH (p)-[r]->() RETURN doc(r)",
+        ] {
+            assert!(run(invalid).await.is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_indexes() {
+        let graph = Arc::new(Mutex::new(GraphStore::new()));
+        query(
+            &graph,
+            "CREATE (:Person {email: 'ann@x', age: 31}), (:Person {email: 'bob@x', age: 42}), \
+             (:City {email: 'oslo@x', age: 1000})",
+        )
+        .await;
+        query(&graph, "CREATE INDEX ON :Person(email)").await;
+        query(&graph, "CREATE INDEX ON :Person(age) TYPE number").await;
+
+        let explain = |query: &str| {
+            let graph = graph.clone();
+            let query = query.to_string();
+            async move {
+                let steps = explain_cypher_query(database(), graph, query, serde_json::Map::new())
+                    .await
+                    .unwrap();
+                serde_json::from_str::<serde_json::Value>(&format!("[{}]", steps)).unwrap()
+            }
+        };
+        let steps = explain("MATCH (n:Person {email: 'bob@x'}) RETURN n").await;
+        assert_eq!(steps[0]["Index"]["label"], serde_json::json!("Person"));
+        assert_eq!(steps[0]["Index"]["nodes"], serde_json::json!(1));
+        let steps = explain("MATCH (n:Person) WHERE n.age > 30 AND n.name IS NULL RETURN n").await;
+        assert_eq!(steps[0]["Index"]["nodes"], serde_json::json!(2));
+        let steps = explain("MATCH (n:Person) WHERE n.age > 30 OR n.name = 'Ann' RETURN n").await;
+        assert_eq!(steps[0]["Label"]["nodes"], serde_json::json!(2));
+        let steps = explain("MATCH (n {email: 'bob@x'}) RETURN n").await;
+        assert_eq!(steps[0]["AllNodes"]["nodes"], serde_json::json!(3));
+
+        // Writes are not run by explain
+        for write in [
+            "CREATE (:Person {email: 'eve@x'})",
+            "MERGE (:Person {email: 'eve@x'})",
+            "MATCH (n:Person) SET n.age = 1",
+            "MATCH (n:City) DETACH DELETE n",
+            "MATCH (n:Person) WITH n MATCH (m:City) DELETE m",
+            "DROP INDEX ON :Person(age)",
+        ] {
+            assert!(
+                explain_cypher_query(
+                    database(),
+                    graph.clone(),
+                    write.to_string(),
+                    serde_json::Map::new()
+                )
+                .await
+                .is_err(),
+                "{}",
+                write
+            );
+        }
+        assert_eq!(
+            query(&graph, "MATCH (n) RETURN count(n)").await,
+            vec![vec![serde_json::json!(3)]]
+        );
+        let steps = explain("MATCH (n:Person) WHERE n.age > 30 RETURN n").await;
+        assert_eq!(steps[0]["Index"]["nodes"], serde_json::json!(2));
+
+        assert_eq!(
+            query(&graph, "MATCH (n:Person) WHERE 40 > n.age RETURN n.email").await,
+            vec![vec![serde_json::json!("ann@x")]]
+        );
+
+        // Index entries follow SET and are restored when a query fails
+        query(
+            &graph,
+            "MATCH (n:Person {email: 'ann@x'}) SET n.email = 'ann@y'",
+        )
+        .await;
+        assert!(handle_cypher_query(
+            database(),
+            graph.clone(),
+            "MATCH (n:Person {email: 'bob@x'}) SET n.email = 'bob@y' RETURN 1 / 0".to_string(),
+            serde_json::Map::new()
+        )
+        .await
+        .is_err());
+        assert_eq!(
+            query(
+                &graph,
+                "MATCH (a:Person {email: 'ann@y'}), (b:Person {email: 'bob@x'}) RETURN a.age, b.age"
+            )
+            .await,
+            vec![vec![serde_json::json!(31), serde_json::json!(42)]]
+        );
+
+        query(&graph, "DROP INDEX ON :Person(email)").await;
+        let steps = explain("MATCH (n:Person {email: 'bob@x'}) RETURN n").await;
+        assert_eq!(steps[0]["Label"]["label"], serde_json::json!("Person"));
+        assert!(handle_cypher_query(
+            database(),
+            graph.clone(),
+            "DROP INDEX ON :Person(email)".to_string(),
+            serde_json::Map::new()
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parameters() {
+        let graph = Arc::new(Mutex::new(GraphStore::new()));
+        let with_params = |query: &str, params: serde_json::Value| {
+            handle_cypher_query(
+                database(),
+                graph.clone(),
+                query.to_string(),
+                params.as_object().unwrap().clone(),
+            )
+        };
+        with_params(
+            "CREATE (:Person {name: $name, age: $age}), (:Person {name: $other, age: 20})",
+            serde_json::json!({"name": "Ann", "age": 31, "other": "Bob"}),
+        )
+        .await
+        .unwrap();
+
+        let result = with_params(
+            "MATCH (n:Person) WHERE n.age > $min AND n.name IN $names SET n.city = $city \
+             RETURN n.name, n.city SKIP $skip",
+            serde_json::json!({"min": 30, "names": ["Ann", "Bob"], "city": "Oslo", "skip": 0}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![serde_json::json!("Ann"), serde_json::json!("Oslo")]]
+        );
+
+        // A parameter is a value, quotes in it are not query text
+        let result = with_params(
+            "MATCH (n:Person) WHERE n.name = $name RETURN n.name",
+            serde_json::json!({"name": "Ann' OR 1 = 1 OR n.name = '"}),
+        )
+        .await
+        .unwrap();
+        assert!(result.rows.is_empty());
+
+        let error = with_params(
+            "MATCH (n:Person)\nWHERE n.name = $name RETURN n",
+            serde_json::json!({}),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::ParameterError { .. }));
+        assert_eq!(
+            error.into_string(),
+            "Missing parameter $name at line 2, column 16 ('$name')"
+        );
+        let error = with_params(
+            "MATCH (n:Person) WHERE count(n) > 1 RETURN n",
+            serde_json::json!({}),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::QueryError(_)));
+    }
+}
@@ -0,0 +1,501 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+
+use crate::common::FieldType;
+use crate::kv::index::{Index, IndexList};
+use crate::kv::witchvm_kv::IndexPlan;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+pub type NodeId = u64;
+pub type RelationshipId = u64;
+pub type Properties = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: NodeId,
+    pub labels: Vec<String>,
+    pub properties: Properties,
+    // A key value document this node stands for
+    pub document: Option<DocumentRef>,
+}
+
+impl Node {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "id": self.id,
+            "labels": self.labels,
+            "properties": self.properties,
+        });
+        if let Some(document) = &self.document {
+            json["document"] = document.to_json();
+        }
+        json
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentRef {
+    pub storage_name: String,
+    pub key: String,
+}
+
+impl DocumentRef {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "storage": self.storage_name,
+            "key": self.key,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Relationship {
+    pub id: RelationshipId,
+    pub rel_type: String,
+    pub start: NodeId,
+    pub end: NodeId,
+    pub properties: Properties,
+}
+
+impl Relationship {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "type": self.rel_type,
+            "start": self.start,
+            "end": self.end,
+            "properties": self.properties,
+        })
+    }
+}
+
+// Nodes and relationships are kept ordered by id, so results come out in creation order
+pub struct GraphStore {
+    nodes: BTreeMap<NodeId, Node>,
+    relationships: BTreeMap<RelationshipId, Relationship>,
+    outgoing: HashMap<NodeId, Vec<RelationshipId>>,
+    incoming: HashMap<NodeId, Vec<RelationshipId>>,
+    // Ids of the nodes with a label
+    labels: HashMap<String, BTreeSet<NodeId>>,
+    // Property indexes of the nodes with a label, keyed by node id
+    indexes: HashMap<String, IndexList>,
+    next_node_id: NodeId,
+    next_relationship_id: RelationshipId,
+}
+
+impl GraphStore {
+    pub fn new() -> Self {
+        Self {
+            nodes: BTreeMap::new(),
+            relationships: BTreeMap::new(),
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            labels: HashMap::new(),
+            indexes: HashMap::new(),
+            next_node_id: 0,
+            next_relationship_id: 0,
+        }
+    }
+
+    pub fn create_node(&mut self, labels: Vec<String>, properties: Properties) -> NodeId {
+        let id = self.next_node_id;
+        self.next_node_id += 1;
+        let node = Node {
+            id,
+            labels,
+            properties,
+            document: None,
+        };
+        self.index_node(None, Some(&node));
+        self.nodes.insert(id, node);
+        id
+    }
+
+    pub fn create_relationship(
+        &mut self,
+        start: NodeId,
+        end: NodeId,
+        rel_type: String,
+        properties: Properties,
+    ) -> Result<RelationshipId, String> {
+        for node_id in [start, end] {
+            if !self.nodes.contains_key(&node_id) {
+                return Err(format!("Node {} not found", node_id));
+            }
+        }
+
+        let id = self.next_relationship_id;
+        self.next_relationship_id += 1;
+        self.relationships.insert(
+            id,
+            Relationship {
+                id,
+                rel_type,
+                start,
+                end,
+                properties,
+            },
+        );
+        self.outgoing.entry(start).or_default().push(id);
+        self.incoming.entry(end).or_default().push(id);
+        Ok(id)
+    }
+
+    // Removes a node without relationships
+    pub fn delete_node(&mut self, id: NodeId) -> Result<Node, String> {
+        if !self.outgoing(id).is_empty() || !self.incoming(id).is_empty() {
+            return Err(format!(
+                "Node {} still has relationships, use DETACH DELETE",
+                id
+            ));
+        }
+        self.outgoing.remove(&id);
+        self.incoming.remove(&id);
+        let node = self
+            .nodes
+            .remove(&id)
+            .ok_or(format!("Node {} not found", id))?;
+        self.index_node(Some(&node), None);
+        Ok(node)
+    }
+
+    pub fn delete_relationship(&mut self, id: RelationshipId) -> Result<Relationship, String> {
+        let relationship = self
+            .relationships
+            .remove(&id)
+            .ok_or(format!("Relationship {} not found", id))?;
+        if let Some(ids) = self.outgoing.get_mut(&relationship.start) {
+            ids.retain(|other| *other != id);
+        }
+        if let Some(ids) = self.incoming.get_mut(&relationship.end) {
+            ids.retain(|other| *other != id);
+        }
+        Ok(relationship)
+    }
+
+    // Puts back a deleted or changed node under its own id
+    pub fn restore_node(&mut self, node: Node) {
+        let old = self.nodes.get(&node.id).cloned();
+        self.index_node(old.as_ref(), Some(&node));
+        self.nodes.insert(node.id, node);
+    }
+
+    // Replaces an existing node, so its labels and properties are indexed again, returns the old one
+    pub fn update_node(&mut self, node: Node) -> Result<Node, String> {
+        let old = self
+            .nodes
+            .get(&node.id)
+            .cloned()
+            .ok_or(format!("Node {} not found", node.id))?;
+        self.index_node(Some(&old), Some(&node));
+        self.nodes.insert(node.id, node);
+        Ok(old)
+    }
+
+    // Moves a node from its old labels and property values to the new ones.
+    // None is a missing node, as before it is created or after it is deleted.
+    fn index_node(&mut self, old: Option<&Node>, new: Option<&Node>) {
+        let id = old.or(new).map(|node| node.id).unwrap_or_default();
+        for label in old.iter().flat_map(|node| node.labels.iter()) {
+            if let Some(ids) = self.labels.get_mut(label) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.labels.remove(label);
+                }
+            }
+        }
+        for label in new.iter().flat_map(|node| node.labels.iter()) {
+            self.labels.entry(label.clone()).or_default().insert(id);
+        }
+
+        let document = |node: Option<&Node>, label: &String| {
+            node.filter(|node| node.labels.contains(label))
+                .map(|node| serde_json::Value::Object(node.properties.clone()))
+        };
+        for (label, indexes) in self.indexes.iter_mut() {
+            let old_document = document(old, label);
+            let new_document = document(new, label);
+            // Graph indexes are not unique, so moving the entries can not fail
+            let _ = indexes.update_entries(
+                &id.to_string(),
+                old_document.as_ref(),
+                new_document.as_ref(),
+            );
+        }
+    }
+
+    pub fn create_index(
+        &mut self,
+        label: String,
+        property: String,
+        field_type: FieldType,
+    ) -> Result<(), String> {
+        if self
+            .indexes
+            .get(&label)
+            .is_some_and(|indexes| indexes.index_exists(&property))
+        {
+            return Err(format!("Index on :{}({}) already exists", label, property));
+        }
+
+        let documents = self
+            .nodes_with_label(&label)
+            .filter_map(|id| self.node(id))
+            .map(|node| {
+                (
+                    node.id.to_string(),
+                    serde_json::Value::Object(node.properties.clone()),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut index = Index::new(field_type, false, false);
+        index
+            .build(&property, documents.iter().map(|(key, value)| (key, value)))
+            .map_err(|e| e.into_string())?;
+        self.indexes
+            .entry(label)
+            .or_insert_with(IndexList::new)
+            .create_index(property, index);
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, label: &str, property: &String) -> Result<(), String> {
+        self.indexes
+            .get_mut(label)
+            .and_then(|indexes| indexes.drop_index(property))
+            .map(|_| ())
+            .ok_or(format!("Index on :{}({}) not found", label, property))
+    }
+
+    pub fn nodes_with_label(&self, label: &str) -> impl Iterator<Item = NodeId> + '_ {
+        self.labels.get(label).into_iter().flatten().copied()
+    }
+
+    pub fn label_count(&self, label: &str) -> usize {
+        self.labels.get(label).map(|ids| ids.len()).unwrap_or(0)
+    }
+
+    // Nodes with the label that may match the plan, None when its indexes can not answer it
+    pub fn index_search(&self, label: &str, plan: &IndexPlan) -> Option<Vec<NodeId>> {
+        let keys = plan.candidate_keys(self.indexes.get(label)?)?;
+        Some(keys.iter().filter_map(|key| key.parse().ok()).collect())
+    }
+
+    // Puts back a deleted or changed relationship under its own id
+    pub fn restore_relationship(&mut self, relationship: Relationship) {
+        let id = relationship.id;
+        if self
+            .relationships
+            .insert(id, relationship.clone())
+            .is_none()
+        {
+            self.outgoing
+                .entry(relationship.start)
+                .or_default()
+                .push(id);
+            self.incoming.entry(relationship.end).or_default().push(id);
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(&id)
+    }
+
+    pub fn relationship(&self, id: RelationshipId) -> Option<&Relationship> {
+        self.relationships.get(&id)
+    }
+
+    pub fn relationship_mut(&mut self, id: RelationshipId) -> Option<&mut Relationship> {
+        self.relationships.get_mut(&id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub fn relationships(&self) -> impl Iterator<Item = &Relationship> {
+        self.relationships.values()
+    }
+
+    pub fn outgoing(&self, id: NodeId) -> &[RelationshipId] {
+        self.outgoing
+            .get(&id)
+            .map(|ids| ids.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn incoming(&self, id: NodeId) -> &[RelationshipId] {
+        self.incoming
+            .get(&id)
+            .map(|ids| ids.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Documents of the nodes connected by a relationship of the given type,
+    // in either direction, to the nodes that reference the key
+    pub fn document_neighbours(&self, key: &str, rel_type: &str) -> Vec<DocumentRef> {
+        let mut documents: Vec<DocumentRef> = Vec::new();
+        for node in self.nodes() {
+            if node.document.as_ref().map(|document| document.key.as_str()) != Some(key) {
+                continue;
+            }
+            for id in self.outgoing(node.id).iter().chain(self.incoming(node.id)) {
+                let Some(relationship) = self.relationship(*id) else {
+                    continue;
+                };
+                if relationship.rel_type != rel_type {
+                    continue;
+                }
+                let other = if relationship.start == node.id {
+                    relationship.end
+                } else {
+                    relationship.start
+                };
+                if let Some(document) = self.node(other).and_then(|n| n.document.as_ref()) {
+                    if !documents.contains(document) {
+                        documents.push(document.clone());
+                    }
+                }
+            }
+        }
+        documents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relationships_are_linked_both_ways() {
+        let mut graph = GraphStore::new();
+        let a = graph.create_node(vec!["Person".to_string()], Properties::new());
+        let b = graph.create_node(vec!["Person".to_string()], Properties::new());
+        let r = graph
+            .create_relationship(a, b, "KNOWS".to_string(), Properties::new())
+            .unwrap();
+
+        assert_eq!(graph.outgoing(a), &[r]);
+        assert_eq!(graph.incoming(b), &[r]);
+        assert!(graph.outgoing(b).is_empty());
+        assert!(graph
+            .create_relationship(a, 42, "KNOWS".to_string(), Properties::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_delete_and_restore() {
+        let mut graph = GraphStore::new();
+        let a = graph.create_node(Vec::new(), Properties::new());
+        let b = graph.create_node(Vec::new(), Properties::new());
+        let r = graph
+            .create_relationship(a, b, "KNOWS".to_string(), Properties::new())
+            .unwrap();
+
+        assert!(graph.delete_node(a).is_err());
+        let relationship = graph.delete_relationship(r).unwrap();
+        assert!(graph.outgoing(a).is_empty());
+        let node = graph.delete_node(a).unwrap();
+        assert!(graph.node(a).is_none());
+
+        graph.restore_node(node);
+        graph.restore_relationship(relationship);
+        assert_eq!(graph.outgoing(a), &[r]);
+        assert_eq!(graph.incoming(b), &[r]);
+    }
+
+    #[test]
+    fn test_label_and_property_indexes() {
+        let mut graph = GraphStore::new();
+        let person = |age: i64| {
+            serde_json::json!({ "age": age })
+                .as_object()
+                .unwrap()
+                .clone()
+        };
+        let a = graph.create_node(vec!["Person".to_string()], person(31));
+        let b = graph.create_node(vec!["Person".to_string()], person(42));
+        graph.create_node(vec!["City".to_string()], person(42));
+        graph
+            .create_index("Person".to_string(), "age".to_string(), FieldType::Number)
+            .unwrap();
+        assert!(graph
+            .create_index("Person".to_string(), "age".to_string(), FieldType::Number)
+            .is_err());
+
+        let older_than = |n: f64| IndexPlan::Compare {
+            field: "age".to_string(),
+            operator: ">".to_string(),
+            value: crate::common::FieldValue::Number(n),
+        };
+        assert_eq!(graph.nodes_with_label("Person").collect::<Vec<_>>(), [a, b]);
+        assert_eq!(
+            graph.index_search("Person", &older_than(40.0)),
+            Some(vec![b])
+        );
+        assert_eq!(graph.index_search("City", &older_than(40.0)), None);
+
+        // Changed and deleted nodes leave the indexes
+        let mut node = graph.node(a).unwrap().clone();
+        node.properties
+            .insert("age".to_string(), serde_json::json!(50));
+        node.labels.push("Admin".to_string());
+        let old = graph.update_node(node).unwrap();
+        assert_eq!(
+            graph.index_search("Person", &older_than(40.0)),
+            Some(vec![b, a])
+        );
+        assert_eq!(graph.label_count("Admin"), 1);
+        graph.restore_node(old);
+        assert_eq!(graph.label_count("Admin"), 0);
+        graph.delete_node(b).unwrap();
+        assert_eq!(
+            graph.index_search("Person", &older_than(0.0)),
+            Some(vec![a])
+        );
+
+        graph.drop_index("Person", &"age".to_string()).unwrap();
+        assert_eq!(graph.index_search("Person", &older_than(0.0)), None);
+    }
+}
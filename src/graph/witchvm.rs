@@ -0,0 +1,1517 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+
+use crate::common::FieldType;
+use crate::graph::algorithms::Procedure;
+use crate::graph::cypher::{AggregateFunction, BinaryOperator, Direction, Length};
+use crate::graph::store::{
+    DocumentRef, GraphStore, Node, NodeId, Properties, Relationship, RelationshipId,
+};
+use crate::kv::database::Database;
+use crate::kv::witchvm_kv::{compare_json_values, number_to_json, IndexPlan};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use tokio::time::{Duration, Instant};
+
+// Variables bound while matching a pattern
+pub type Row = HashMap<String, Entry>;
+
+pub type Evaluator = Box<dyn Fn(&Context, &Row) -> Result<Entry, String>>;
+
+// What an expression can read: the graph and the documents its nodes reference
+pub struct Context<'a> {
+    pub graph: &'a GraphStore,
+    pub database: &'a Database,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Node(NodeId),
+    Relationship(RelationshipId),
+    Value(serde_json::Value),
+    // A list literal or the relationships of a variable length pattern
+    List(Vec<Entry>),
+    Path {
+        nodes: Vec<NodeId>,
+        relationships: Vec<RelationshipId>,
+    },
+}
+
+impl Entry {
+    pub fn to_json(&self, graph: &GraphStore) -> serde_json::Value {
+        match self {
+            Entry::Node(id) => graph
+                .node(*id)
+                .map(|node| node.to_json())
+                .unwrap_or_default(),
+            Entry::Relationship(id) => graph
+                .relationship(*id)
+                .map(|relationship| relationship.to_json())
+                .unwrap_or_default(),
+            Entry::Value(value) => value.clone(),
+            Entry::List(entries) => entries.iter().map(|entry| entry.to_json(graph)).collect(),
+            Entry::Path {
+                nodes,
+                relationships,
+            } => serde_json::json!({
+                "nodes": nodes
+                    .iter()
+                    .map(|id| Entry::Node(*id).to_json(graph))
+                    .collect::<Vec<_>>(),
+                "relationships": relationships
+                    .iter()
+                    .map(|id| Entry::Relationship(*id).to_json(graph))
+                    .collect::<Vec<_>>(),
+            }),
+        }
+    }
+
+    // Boolean value of a condition, None when it is null
+    pub fn truth(&self) -> Result<Option<bool>, String> {
+        match self {
+            Entry::Value(serde_json::Value::Bool(value)) => Ok(Some(*value)),
+            Entry::Value(serde_json::Value::Null) => Ok(None),
+            entry => Err(format!("Expected a boolean, got {:?}", entry)),
+        }
+    }
+
+    pub fn from_truth(truth: Option<bool>) -> Entry {
+        Entry::Value(truth.map(serde_json::Value::Bool).unwrap_or_default())
+    }
+
+    // Items of a list literal or a JSON array, None when the entry is null
+    fn items(&self) -> Result<Option<Vec<Entry>>, String> {
+        match self {
+            Entry::List(entries) => Ok(Some(entries.clone())),
+            Entry::Value(serde_json::Value::Array(values)) => {
+                Ok(Some(values.iter().cloned().map(Entry::Value).collect()))
+            }
+            Entry::Value(serde_json::Value::Null) => Ok(None),
+            entry => Err(format!("Expected a list, got {:?}", entry)),
+        }
+    }
+
+    // Relationships bound to the entry, one for a relationship and all of them for a list
+    fn relationship_ids(&self) -> Vec<RelationshipId> {
+        match self {
+            Entry::Relationship(id) => vec![*id],
+            Entry::List(entries) => entries.iter().flat_map(Entry::relationship_ids).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Document referenced by a node, null when there is none or it was deleted
+    pub fn document(&self, context: &Context) -> Result<Entry, String> {
+        let id = match self {
+            Entry::Node(id) => *id,
+            Entry::Value(serde_json::Value::Null) => return Ok(Entry::Value(Default::default())),
+            entry => return Err(format!("doc() expects a node, got {:?}", entry)),
+        };
+        let document = context
+            .graph
+            .node(id)
+            .and_then(|node| node.document.as_ref())
+            .and_then(|document| {
+                context
+                    .database
+                    .get_document(document.storage_name.clone(), document.key.clone())
+                    .ok()
+            })
+            .cloned();
+        Ok(Entry::Value(document.unwrap_or_default()))
+    }
+
+    // Property of a node or relationship, null when it is missing
+    pub fn property(&self, graph: &GraphStore, name: &str) -> Result<Entry, String> {
+        let properties = match self {
+            Entry::Node(id) => graph.node(*id).map(|node| &node.properties),
+            Entry::Relationship(id) => graph
+                .relationship(*id)
+                .map(|relationship| &relationship.properties),
+            Entry::Value(serde_json::Value::Null) => None,
+            Entry::Value(value) => {
+                return match value.get(name) {
+                    Some(value) => Ok(Entry::Value(value.clone())),
+                    None if value.is_object() => Ok(Entry::Value(serde_json::Value::Null)),
+                    None => Err(format!("Can not read property '{}' of {}", name, value)),
+                };
+            }
+            Entry::List(_) | Entry::Path { .. } => {
+                return Err(format!(
+                    "Can not read property '{}' of a list or path",
+                    name
+                ));
+            }
+        };
+        Ok(Entry::Value(
+            properties
+                .and_then(|properties| properties.get(name))
+                .cloned()
+                .unwrap_or_default(),
+        ))
+    }
+}
+
+// Counts of the changes made by a query, returned when it has no RETURN
+#[derive(Debug, Default)]
+pub struct Statistics {
+    nodes_created: u64,
+    nodes_deleted: u64,
+    relationships_created: u64,
+    relationships_deleted: u64,
+    properties_set: u64,
+    labels_added: u64,
+    labels_removed: u64,
+}
+
+impl Statistics {
+    pub const COLUMNS: [&'static str; 7] = [
+        "nodes_created",
+        "nodes_deleted",
+        "relationships_created",
+        "relationships_deleted",
+        "properties_set",
+        "labels_added",
+        "labels_removed",
+    ];
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "nodes_created": self.nodes_created,
+            "nodes_deleted": self.nodes_deleted,
+            "relationships_created": self.relationships_created,
+            "relationships_deleted": self.relationships_deleted,
+            "properties_set": self.properties_set,
+            "labels_added": self.labels_added,
+            "labels_removed": self.labels_removed,
+        })
+    }
+}
+
+pub struct WitchVMGraph {
+    rows: Vec<Row>,
+    output: Vec<serde_json::Value>,
+    statistics: Statistics,
+    // Writes done by the current execution, undone if a later instruction fails
+    undo_log: Vec<UndoEntry>,
+    explain: Vec<NodeScan>,
+}
+
+enum UndoEntry {
+    NodeCreated(NodeId),
+    RelationshipCreated(RelationshipId),
+    // State of a node or relationship before it was changed or deleted
+    NodeChanged(Node),
+    RelationshipChanged(Relationship),
+}
+
+impl WitchVMGraph {
+    pub fn new() -> Self {
+        Self {
+            // Matching starts from a single row with nothing bound
+            rows: vec![Row::new()],
+            output: Vec::new(),
+            statistics: Statistics::default(),
+            undo_log: Vec::new(),
+            explain: Vec::new(),
+        }
+    }
+
+    pub fn into_output(self) -> Vec<serde_json::Value> {
+        self.output
+    }
+
+    pub fn execute(
+        &mut self,
+        graph: &mut GraphStore,
+        database: &Database,
+        instructions: Vec<Instruction>,
+    ) -> Result<Vec<NodeScan>, String> {
+        if let Err(e) = self.execute_all(graph, database, &instructions) {
+            return Err(match self.rollback(graph) {
+                Ok(()) => e,
+                Err(rollback_error) => format!("{}, rollback is incomplete: {}", e, rollback_error),
+            });
+        }
+        self.undo_log.clear();
+        Ok(std::mem::take(&mut self.explain))
+    }
+
+    // Undoes every entry, even after one fails, and reports the ones that failed
+    fn rollback(&mut self, graph: &mut GraphStore) -> Result<(), String> {
+        let mut errors = Vec::new();
+        while let Some(entry) = self.undo_log.pop() {
+            match entry {
+                UndoEntry::NodeCreated(id) => {
+                    if let Err(e) = graph.delete_node(id) {
+                        errors.push(format!("node {} was not removed: {}", id, e));
+                    }
+                }
+                UndoEntry::RelationshipCreated(id) => {
+                    if let Err(e) = graph.delete_relationship(id) {
+                        errors.push(format!("relationship {} was not removed: {}", id, e));
+                    }
+                }
+                UndoEntry::NodeChanged(node) => graph.restore_node(node),
+                UndoEntry::RelationshipChanged(relationship) => {
+                    graph.restore_relationship(relationship)
+                }
+            }
+        }
+        self.statistics = Statistics::default();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    fn execute_all(
+        &mut self,
+        graph: &mut GraphStore,
+        database: &Database,
+        instructions: &[Instruction],
+    ) -> Result<(), String> {
+        for instruction in instructions {
+            self.execute_instruction(graph, database, instruction)?;
+        }
+        Ok(())
+    }
+
+    fn execute_instruction(
+        &mut self,
+        graph: &mut GraphStore,
+        database: &Database,
+        instruction: &Instruction,
+    ) -> Result<(), String> {
+        match instruction {
+            Instruction::MatchNode {
+                variable,
+                labels,
+                properties,
+                index_plan,
+            } => {
+                // Nodes are looked up once, and only if a row does not bind the variable yet
+                let mut candidates: Option<Vec<NodeId>> = None;
+                let mut rows = Vec::new();
+                for row in std::mem::take(&mut self.rows) {
+                    // A variable bound by an earlier pattern is checked, not matched again
+                    if let Some(entry) = row.get(variable) {
+                        let Entry::Node(id) = entry else {
+                            return Err(format!("Variable '{}' is not a node", variable));
+                        };
+                        if node_matches(graph, *id, labels, properties) {
+                            rows.push(row);
+                        }
+                        continue;
+                    }
+
+                    let candidates = candidates.get_or_insert_with(|| {
+                        let (ids, step) = scan_nodes(graph, variable, labels, index_plan.as_ref());
+                        self.explain.push(step);
+                        ids
+                    });
+                    for id in candidates.iter() {
+                        if node_matches(graph, *id, labels, properties) {
+                            let mut row = row.clone();
+                            row.insert(variable.clone(), Entry::Node(*id));
+                            rows.push(row);
+                        }
+                    }
+                }
+                self.rows = rows;
+            }
+            Instruction::Expand {
+                from,
+                variable,
+                filter,
+                direction,
+                length,
+                reversed,
+                to,
+                distinct_from,
+            } => {
+                let mut rows = Vec::new();
+                for row in std::mem::take(&mut self.rows) {
+                    let Some(Entry::Node(from_id)) = row.get(from) else {
+                        return Err(format!("Variable '{}' is not a node", from));
+                    };
+                    // One relationship is not used twice in the same MATCH
+                    let used = used_relationships(&row, distinct_from);
+
+                    let expansions: Vec<(Entry, NodeId)> = match length {
+                        None => neighbours(graph, *from_id, *direction)
+                            .into_iter()
+                            .filter(|(relationship_id, _)| {
+                                !used.contains(relationship_id)
+                                    && filter.matches(graph, *relationship_id)
+                            })
+                            .map(|(relationship_id, other_id)| {
+                                (Entry::Relationship(relationship_id), other_id)
+                            })
+                            .collect(),
+                        Some(length) => {
+                            let mut paths = Vec::new();
+                            let mut path = Vec::new();
+                            expand_paths(
+                                graph, *from_id, filter, *direction, *length, &used, &mut path,
+                                &mut paths,
+                            );
+                            paths
+                                .into_iter()
+                                .map(|(mut path, other_id)| {
+                                    // The list follows the pattern from left to right
+                                    if *reversed {
+                                        path.reverse();
+                                    }
+                                    let path = path.into_iter().map(Entry::Relationship).collect();
+                                    (Entry::List(path), other_id)
+                                })
+                                .collect()
+                        }
+                    };
+
+                    for (entry, other_id) in expansions {
+                        if row.get(variable).is_some_and(|bound| *bound != entry) {
+                            continue;
+                        }
+                        match row.get(to) {
+                            Some(Entry::Node(to_id)) if *to_id != other_id => continue,
+                            Some(Entry::Node(_)) | None => {}
+                            Some(_) => return Err(format!("Variable '{}' is not a node", to)),
+                        }
+
+                        let mut row = row.clone();
+                        row.insert(variable.clone(), entry);
+                        row.insert(to.clone(), Entry::Node(other_id));
+                        rows.push(row);
+                    }
+                }
+                self.rows = rows;
+            }
+            Instruction::ShortestPath {
+                from,
+                variable,
+                filter,
+                direction,
+                max_length,
+                to,
+                distinct_from,
+            } => {
+                let mut rows = Vec::new();
+                for mut row in std::mem::take(&mut self.rows) {
+                    let (Some(Entry::Node(from_id)), Some(Entry::Node(to_id))) =
+                        (row.get(from), row.get(to))
+                    else {
+                        return Err(format!("Variables '{}' and '{}' are not nodes", from, to));
+                    };
+                    let used = used_relationships(&row, distinct_from);
+                    if let Some(path) = shortest_path(
+                        graph,
+                        *from_id,
+                        *to_id,
+                        filter,
+                        *direction,
+                        *max_length,
+                        &used,
+                    ) {
+                        let path = path.into_iter().map(Entry::Relationship).collect();
+                        row.insert(variable.clone(), Entry::List(path));
+                        rows.push(row);
+                    }
+                }
+                self.rows = rows;
+            }
+            Instruction::BindPath {
+                variable,
+                start,
+                relationships,
+            } => {
+                for row in self.rows.iter_mut() {
+                    let Some(Entry::Node(start_id)) = row.get(start) else {
+                        return Err(format!("Variable '{}' is not a node", start));
+                    };
+                    let mut nodes = vec![*start_id];
+                    let mut relationship_ids = Vec::new();
+                    for name in relationships.iter() {
+                        let entry = row
+                            .get(name)
+                            .ok_or(format!("Variable '{}' is not defined", name))?;
+                        for relationship_id in entry.relationship_ids() {
+                            let relationship = graph
+                                .relationship(relationship_id)
+                                .ok_or(format!("Relationship {} not found", relationship_id))?;
+                            let current = nodes[nodes.len() - 1];
+                            nodes.push(if relationship.start == current {
+                                relationship.end
+                            } else {
+                                relationship.start
+                            });
+                            relationship_ids.push(relationship_id);
+                        }
+                    }
+                    row.insert(
+                        variable.clone(),
+                        Entry::Path {
+                            nodes,
+                            relationships: relationship_ids,
+                        },
+                    );
+                }
+            }
+            Instruction::Aggregate { keys, aggregations } => {
+                let context = Context { graph, database };
+                // First row of every group with its key values, and the arguments of each aggregation
+                let mut groups: Vec<(Row, Vec<Vec<Entry>>)> = Vec::new();
+                let mut group_index: HashMap<String, usize> = HashMap::new();
+                for row in self.rows.iter() {
+                    let key_entries = keys
+                        .iter()
+                        .map(|(_, key)| key(&context, row))
+                        .collect::<Result<Vec<Entry>, String>>()?;
+                    let group_key = format!("{:?}", key_entries);
+                    let index = match group_index.get(&group_key) {
+                        Some(index) => *index,
+                        None => {
+                            let mut first = row.clone();
+                            for ((name, _), entry) in keys.iter().zip(key_entries) {
+                                first.insert(name.clone(), entry);
+                            }
+                            groups.push((first, vec![Vec::new(); aggregations.len()]));
+                            group_index.insert(group_key, groups.len() - 1);
+                            groups.len() - 1
+                        }
+                    };
+                    for (aggregation, values) in aggregations.iter().zip(groups[index].1.iter_mut())
+                    {
+                        values.push(match &aggregation.argument {
+                            Some(argument) => argument(&context, row)?,
+                            // count(*) counts rows
+                            None => Entry::Value(serde_json::Value::Bool(true)),
+                        });
+                    }
+                }
+                // Aggregating nothing without grouping keys still gives one row, e.g. count(*) = 0
+                if groups.is_empty() && keys.is_empty() {
+                    groups.push((Row::new(), vec![Vec::new(); aggregations.len()]));
+                }
+
+                let mut rows = Vec::new();
+                for (mut row, values) in groups {
+                    for (aggregation, values) in aggregations.iter().zip(values) {
+                        row.insert(
+                            aggregation.variable.clone(),
+                            aggregation.aggregate(graph, values)?,
+                        );
+                    }
+                    rows.push(row);
+                }
+                self.rows = rows;
+            }
+            Instruction::Project { items } => {
+                let context = Context { graph, database };
+                for row in self.rows.iter_mut() {
+                    let entries = items
+                        .iter()
+                        .map(|(_, evaluator)| evaluator(&context, row))
+                        .collect::<Result<Vec<Entry>, String>>()?;
+                    for ((name, _), entry) in items.iter().zip(entries) {
+                        row.insert(name.clone(), entry);
+                    }
+                }
+            }
+            Instruction::Distinct { columns } => {
+                let mut seen = HashSet::new();
+                self.rows.retain(|row| {
+                    let entries = columns
+                        .iter()
+                        .map(|column| row.get(column))
+                        .collect::<Vec<_>>();
+                    seen.insert(format!("{:?}", entries))
+                });
+            }
+            Instruction::Sort { keys } => {
+                let context = Context { graph, database };
+                let mut sorted = Vec::new();
+                for row in std::mem::take(&mut self.rows) {
+                    let entries = keys
+                        .iter()
+                        .map(|(key, _)| key(&context, &row))
+                        .collect::<Result<Vec<Entry>, String>>()?;
+                    sorted.push((entries, row));
+                }
+                sorted.sort_by(|(a, _), (b, _)| {
+                    for ((a, b), (_, descending)) in a.iter().zip(b.iter()).zip(keys.iter()) {
+                        let ordering = compare_entries(graph, a, b);
+                        if ordering != Ordering::Equal {
+                            return if *descending {
+                                ordering.reverse()
+                            } else {
+                                ordering
+                            };
+                        }
+                    }
+                    Ordering::Equal
+                });
+                self.rows = sorted.into_iter().map(|(_, row)| row).collect();
+            }
+            Instruction::Slice { skip, limit } => {
+                let rows = std::mem::take(&mut self.rows).into_iter().skip(*skip);
+                self.rows = match limit {
+                    Some(limit) => rows.take(*limit).collect(),
+                    None => rows.collect(),
+                };
+            }
+            Instruction::Scope { columns } => {
+                for row in self.rows.iter_mut() {
+                    row.retain(|name, _| columns.contains(name));
+                }
+            }
+            Instruction::Return { columns } => {
+                let mut output = Vec::new();
+                for row in self.rows.iter() {
+                    let mut result = serde_json::Map::new();
+                    for column in columns.iter() {
+                        let value = row.get(column).map(|entry| entry.to_json(graph));
+                        result.insert(column.clone(), value.unwrap_or_default());
+                    }
+                    output.push(serde_json::Value::Object(result));
+                }
+                self.output = output;
+            }
+            Instruction::Call {
+                procedure,
+                arguments,
+                yields,
+            } => {
+                let context = Context { graph, database };
+                let mut rows = Vec::new();
+                for row in std::mem::take(&mut self.rows) {
+                    let values = arguments
+                        .iter()
+                        .map(|argument| Ok(argument(&context, &row)?.to_json(graph)))
+                        .collect::<Result<Vec<serde_json::Value>, String>>()?;
+                    for result in procedure.call(graph, values)? {
+                        let mut row = row.clone();
+                        for (index, variable) in yields.iter() {
+                            row.insert(variable.clone(), result[*index].clone());
+                        }
+                        rows.push(row);
+                    }
+                }
+                self.rows = rows;
+            }
+            Instruction::Filter { predicate } => {
+                let context = Context { graph, database };
+                let mut rows = Vec::new();
+                for row in std::mem::take(&mut self.rows) {
+                    // Rows where the condition is false or null are dropped
+                    if predicate(&context, &row)?.truth()? == Some(true) {
+                        rows.push(row);
+                    }
+                }
+                self.rows = rows;
+            }
+            Instruction::ReturnStatistics => {
+                self.output = vec![self.statistics.to_json()];
+            }
+            Instruction::CreateNode {
+                variable,
+                labels,
+                properties,
+            } => {
+                for row in self.rows.iter_mut() {
+                    let id = graph.create_node(labels.clone(), properties.clone());
+                    self.undo_log.push(UndoEntry::NodeCreated(id));
+                    self.statistics.nodes_created += 1;
+                    self.statistics.labels_added += labels.len() as u64;
+                    self.statistics.properties_set += properties.len() as u64;
+                    row.insert(variable.clone(), Entry::Node(id));
+                }
+            }
+            Instruction::CreateRelationship {
+                variable,
+                rel_type,
+                properties,
+                start,
+                end,
+            } => {
+                for row in self.rows.iter_mut() {
+                    let id = graph.create_relationship(
+                        node_id(row, start)?,
+                        node_id(row, end)?,
+                        rel_type.clone(),
+                        properties.clone(),
+                    )?;
+                    self.undo_log.push(UndoEntry::RelationshipCreated(id));
+                    self.statistics.relationships_created += 1;
+                    self.statistics.properties_set += properties.len() as u64;
+                    row.insert(variable.clone(), Entry::Relationship(id));
+                }
+            }
+            Instruction::Merge {
+                match_instructions,
+                create_instructions,
+                on_match,
+                on_create,
+            } => {
+                let mut rows = Vec::new();
+                for row in std::mem::take(&mut self.rows) {
+                    self.rows = vec![row.clone()];
+                    self.execute_all(graph, database, match_instructions)?;
+                    let actions = if self.rows.is_empty() {
+                        self.rows = vec![row];
+                        self.execute_all(graph, database, create_instructions)?;
+                        on_create
+                    } else {
+                        on_match
+                    };
+                    self.execute_all(graph, database, actions)?;
+                    rows.append(&mut self.rows);
+                }
+                self.rows = rows;
+            }
+            Instruction::SetProperty {
+                variable,
+                property,
+                value,
+            } => {
+                for row in self.rows.iter() {
+                    let value = match value(&Context { graph, database }, row)? {
+                        Entry::Value(value) => value,
+                        _ => {
+                            return Err(format!(
+                                "Property '{}' can only be set to a value",
+                                property
+                            ))
+                        }
+                    };
+                    match row.get(variable) {
+                        // Nodes are replaced as a whole, so their indexes follow the change
+                        Some(Entry::Node(id)) => {
+                            let Some(mut node) = graph.node(*id).cloned() else {
+                                continue;
+                            };
+                            set_property(&mut node.properties, property, value);
+                            let old = graph.update_node(node)?;
+                            self.undo_log.push(UndoEntry::NodeChanged(old));
+                        }
+                        Some(Entry::Relationship(id)) => {
+                            let Some(relationship) = graph.relationship_mut(*id) else {
+                                continue;
+                            };
+                            self.undo_log
+                                .push(UndoEntry::RelationshipChanged(relationship.clone()));
+                            set_property(&mut relationship.properties, property, value);
+                        }
+                        Some(Entry::Value(serde_json::Value::Null)) => continue,
+                        _ => {
+                            return Err(format!(
+                                "Variable '{}' is not a node or relationship",
+                                variable
+                            ))
+                        }
+                    }
+                    self.statistics.properties_set += 1;
+                }
+            }
+            Instruction::CreateIndex {
+                label,
+                property,
+                field_type,
+            } => {
+                graph.create_index(label.clone(), property.clone(), field_type.clone())?;
+            }
+            Instruction::DropIndex { label, property } => {
+                graph.drop_index(label, property)?;
+            }
+            Instruction::SetDocument { variable, document } => {
+                for row in self.rows.iter() {
+                    let document = match document {
+                        Some(document) => {
+                            document_ref(document(&Context { graph, database }, row)?)?
+                        }
+                        None => None,
+                    };
+                    let Some(mut node) = bound_node(graph, row, variable)? else {
+                        continue;
+                    };
+                    node.document = document;
+                    let old = graph.update_node(node)?;
+                    self.undo_log.push(UndoEntry::NodeChanged(old));
+                }
+            }
+            Instruction::SetLabels {
+                variable,
+                labels,
+                remove,
+            } => {
+                for row in self.rows.iter() {
+                    let Some(mut node) = bound_node(graph, row, variable)? else {
+                        continue;
+                    };
+                    for label in labels.iter() {
+                        let position = node.labels.iter().position(|other| other == label);
+                        match (position, remove) {
+                            (None, false) => {
+                                node.labels.push(label.clone());
+                                self.statistics.labels_added += 1;
+                            }
+                            (Some(position), true) => {
+                                node.labels.remove(position);
+                                self.statistics.labels_removed += 1;
+                            }
+                            _ => {}
+                        }
+                    }
+                    let old = graph.update_node(node)?;
+                    self.undo_log.push(UndoEntry::NodeChanged(old));
+                }
+            }
+            Instruction::Delete { variables, detach } => {
+                let mut node_ids = Vec::new();
+                let mut relationship_ids = Vec::new();
+                for row in self.rows.iter() {
+                    for variable in variables.iter() {
+                        match row.get(variable) {
+                            Some(Entry::Node(id)) => node_ids.push(*id),
+                            Some(Entry::Path {
+                                nodes,
+                                relationships,
+                            }) => {
+                                node_ids.extend(nodes);
+                                relationship_ids.extend(relationships);
+                            }
+                            Some(Entry::Value(serde_json::Value::Null)) => {}
+                            Some(entry @ (Entry::Relationship(_) | Entry::List(_))) => {
+                                relationship_ids.extend(entry.relationship_ids())
+                            }
+                            Some(Entry::Value(value)) => {
+                                return Err(format!("Can not delete {}", value))
+                            }
+                            None => return Err(format!("Variable '{}' is not defined", variable)),
+                        }
+                    }
+                }
+                if *detach {
+                    for id in node_ids.iter() {
+                        relationship_ids.extend(graph.outgoing(*id));
+                        relationship_ids.extend(graph.incoming(*id));
+                    }
+                }
+
+                // Relationships go first, so nodes deleted together with them are free
+                for id in relationship_ids {
+                    // A relationship found by several rows is deleted once
+                    if graph.relationship(id).is_none() {
+                        continue;
+                    }
+                    let relationship = graph.delete_relationship(id)?;
+                    self.undo_log
+                        .push(UndoEntry::RelationshipChanged(relationship));
+                    self.statistics.relationships_deleted += 1;
+                }
+                for id in node_ids {
+                    if graph.node(id).is_none() {
+                        continue;
+                    }
+                    let node = graph.delete_node(id)?;
+                    self.undo_log.push(UndoEntry::NodeChanged(node));
+                    self.statistics.nodes_deleted += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Operators follow the Cypher null rules: a null operand gives null,
+// except for AND / OR when the other side decides the result
+pub fn evaluate_binary(
+    operator: BinaryOperator,
+    left: Entry,
+    right: Entry,
+) -> Result<Entry, String> {
+    use serde_json::Value;
+
+    match operator {
+        BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor => {
+            let (left, right) = (left.truth()?, right.truth()?);
+            return Ok(Entry::from_truth(match (operator, left, right) {
+                (BinaryOperator::And, Some(false), _) | (BinaryOperator::And, _, Some(false)) => {
+                    Some(false)
+                }
+                (BinaryOperator::Or, Some(true), _) | (BinaryOperator::Or, _, Some(true)) => {
+                    Some(true)
+                }
+                (BinaryOperator::And, Some(true), Some(true)) => Some(true),
+                (BinaryOperator::Or, Some(false), Some(false)) => Some(false),
+                (BinaryOperator::Xor, Some(left), Some(right)) => Some(left != right),
+                _ => None,
+            }));
+        }
+        BinaryOperator::Equal => return Ok(Entry::from_truth(entries_equal(&left, &right))),
+        BinaryOperator::NotEqual => {
+            return Ok(Entry::from_truth(
+                entries_equal(&left, &right).map(|equal| !equal),
+            ))
+        }
+        BinaryOperator::In => {
+            let Some(items) = right.items()? else {
+                return Ok(Entry::from_truth(None));
+            };
+            // Not found with a null in the list is unknown
+            let mut result = Some(false);
+            for item in items.iter() {
+                match entries_equal(&left, item) {
+                    Some(true) => return Ok(Entry::from_truth(Some(true))),
+                    Some(false) => {}
+                    None => result = None,
+                }
+            }
+            return Ok(Entry::from_truth(result));
+        }
+        // Lists are concatenated
+        BinaryOperator::Add
+            if matches!(left, Entry::List(_)) || matches!(right, Entry::List(_)) =>
+        {
+            let (Some(left), Some(right)) = (left.items()?, right.items()?) else {
+                return Ok(Entry::Value(serde_json::Value::Null));
+            };
+            return Ok(Entry::List([left, right].concat()));
+        }
+        _ => {}
+    }
+
+    let (Entry::Value(left), Entry::Value(right)) = (&left, &right) else {
+        return Err(format!(
+            "Operator {:?} can not be applied to {:?} and {:?}",
+            operator, left, right
+        ));
+    };
+    if left.is_null() || right.is_null() {
+        return Ok(Entry::Value(Value::Null));
+    }
+
+    let result = match (operator, left, right) {
+        (
+            BinaryOperator::Less
+            | BinaryOperator::LessEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterEqual,
+            _,
+            _,
+        ) => {
+            // Only values of the same type have an order
+            let comparable = matches!(
+                (left, right),
+                (Value::Number(_), Value::Number(_))
+                    | (Value::String(_), Value::String(_))
+                    | (Value::Bool(_), Value::Bool(_))
+            );
+            if !comparable {
+                return Ok(Entry::Value(Value::Null));
+            }
+            let ordering = compare_json_values(left, right);
+            Value::Bool(match operator {
+                BinaryOperator::Less => ordering == Ordering::Less,
+                BinaryOperator::LessEqual => ordering != Ordering::Greater,
+                BinaryOperator::Greater => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            })
+        }
+        (BinaryOperator::StartsWith, Value::String(a), Value::String(b)) => {
+            Value::Bool(a.starts_with(b.as_str()))
+        }
+        (BinaryOperator::EndsWith, Value::String(a), Value::String(b)) => {
+            Value::Bool(a.ends_with(b.as_str()))
+        }
+        (BinaryOperator::Contains, Value::String(a), Value::String(b)) => {
+            Value::Bool(a.contains(b.as_str()))
+        }
+        (
+            BinaryOperator::StartsWith | BinaryOperator::EndsWith | BinaryOperator::Contains,
+            _,
+            _,
+        ) => Value::Null,
+        (BinaryOperator::Add, Value::String(a), Value::String(b)) => {
+            Value::String(format!("{}{}", a, b))
+        }
+        (BinaryOperator::Add, Value::String(a), Value::Number(b)) => {
+            Value::String(format!("{}{}", a, b))
+        }
+        (BinaryOperator::Add, Value::Number(a), Value::String(b)) => {
+            Value::String(format!("{}{}", a, b))
+        }
+        (_, Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (
+                a.as_f64().unwrap_or_default(),
+                b.as_f64().unwrap_or_default(),
+            );
+            number_to_json(match operator {
+                BinaryOperator::Add => a + b,
+                BinaryOperator::Subtract => a - b,
+                BinaryOperator::Multiply => a * b,
+                _ if b == 0.0 => return Err("Division by zero".to_string()),
+                BinaryOperator::Divide => a / b,
+                _ => a % b,
+            })
+        }
+        _ => {
+            return Err(format!(
+                "Operator {:?} can not be applied to {} and {}",
+                operator, left, right
+            ))
+        }
+    };
+    Ok(Entry::Value(result))
+}
+
+// How the nodes of a pattern were found, the graph version of the SQL explain steps
+#[derive(Debug, Serialize)]
+pub enum NodeScan {
+    AllNodes {
+        variable: String,
+        nodes: usize,
+        time: Duration,
+    },
+    Label {
+        variable: String,
+        label: String,
+        nodes: usize,
+        time: Duration,
+    },
+    Index {
+        variable: String,
+        label: String,
+        nodes: usize,
+        time: Duration,
+    },
+}
+
+// Candidate nodes of a pattern: a property index of one of its labels,
+// else the label with the fewest nodes, else every node
+fn scan_nodes(
+    graph: &GraphStore,
+    variable: &str,
+    labels: &[String],
+    index_plan: Option<&IndexPlan>,
+) -> (Vec<NodeId>, NodeScan) {
+    let start = Instant::now();
+    let variable = variable.to_string();
+    if let Some(plan) = index_plan {
+        for label in labels.iter() {
+            if let Some(ids) = graph.index_search(label, plan) {
+                return (
+                    ids.clone(),
+                    NodeScan::Index {
+                        variable,
+                        label: label.clone(),
+                        nodes: ids.len(),
+                        time: start.elapsed(),
+                    },
+                );
+            }
+        }
+    }
+    if let Some(label) = labels.iter().min_by_key(|label| graph.label_count(label)) {
+        let ids = graph.nodes_with_label(label).collect::<Vec<NodeId>>();
+        return (
+            ids.clone(),
+            NodeScan::Label {
+                variable,
+                label: label.clone(),
+                nodes: ids.len(),
+                time: start.elapsed(),
+            },
+        );
+    }
+    let ids = graph.nodes().map(|node| node.id).collect::<Vec<NodeId>>();
+    (
+        ids.clone(),
+        NodeScan::AllNodes {
+            variable,
+            nodes: ids.len(),
+            time: start.elapsed(),
+        },
+    )
+}
+
+// Copy of the node bound to the variable, None when it is null or was deleted
+fn bound_node(graph: &GraphStore, row: &Row, variable: &String) -> Result<Option<Node>, String> {
+    match row.get(variable) {
+        Some(Entry::Node(id)) => Ok(graph.node(*id).cloned()),
+        Some(Entry::Value(serde_json::Value::Null)) => Ok(None),
+        _ => Err(format!("Variable '{}' is not a node", variable)),
+    }
+}
+
+// Setting a property to null removes it
+fn set_property(properties: &mut Properties, name: &str, value: serde_json::Value) {
+    if value.is_null() {
+        properties.remove(name);
+    } else {
+        properties.insert(name.to_string(), value);
+    }
+}
+
+// ['storage', 'key'] as a document reference, null unlinks
+fn document_ref(entry: Entry) -> Result<Option<DocumentRef>, String> {
+    let Some(items) = entry.items()? else {
+        return Ok(None);
+    };
+    match items.as_slice() {
+        [Entry::Value(serde_json::Value::String(storage_name)), Entry::Value(serde_json::Value::String(key))] => {
+            Ok(Some(DocumentRef {
+                storage_name: storage_name.clone(),
+                key: key.clone(),
+            }))
+        }
+        _ => Err("A document is referenced as ['storage', 'key']".to_string()),
+    }
+}
+
+// Nodes and relationships are ordered by id, values like in SQL ORDER BY
+fn compare_entries(graph: &GraphStore, left: &Entry, right: &Entry) -> Ordering {
+    match (left, right) {
+        (Entry::Node(left), Entry::Node(right)) => left.cmp(right),
+        (Entry::Relationship(left), Entry::Relationship(right)) => left.cmp(right),
+        (Entry::Value(left), Entry::Value(right)) => compare_json_values(left, right),
+        _ => compare_json_values(&left.to_json(graph), &right.to_json(graph)),
+    }
+}
+
+// Equality of two entries, None when a null makes it unknown
+fn entries_equal(left: &Entry, right: &Entry) -> Option<bool> {
+    match (left, right) {
+        (Entry::Value(serde_json::Value::Null), _) | (_, Entry::Value(serde_json::Value::Null)) => {
+            None
+        }
+        (Entry::Value(left), Entry::Value(right)) => {
+            Some(compare_json_values(left, right) == Ordering::Equal)
+        }
+        (Entry::List(_), _) | (_, Entry::List(_)) => {
+            let (Ok(Some(left)), Ok(Some(right))) = (left.items(), right.items()) else {
+                return Some(false);
+            };
+            if left.len() != right.len() {
+                return Some(false);
+            }
+            let mut result = Some(true);
+            for (left, right) in left.iter().zip(right.iter()) {
+                match entries_equal(left, right) {
+                    Some(false) => return Some(false),
+                    None => result = None,
+                    Some(true) => {}
+                }
+            }
+            result
+        }
+        _ => Some(left == right),
+    }
+}
+
+fn node_id(row: &Row, variable: &str) -> Result<NodeId, String> {
+    match row.get(variable) {
+        Some(Entry::Node(id)) => Ok(*id),
+        _ => Err(format!("Variable '{}' is not a node", variable)),
+    }
+}
+
+fn node_matches(
+    graph: &GraphStore,
+    id: NodeId,
+    labels: &[String],
+    properties: &Properties,
+) -> bool {
+    let Some(node) = graph.node(id) else {
+        return false;
+    };
+    labels.iter().all(|label| node.labels.contains(label))
+        && properties
+            .iter()
+            .all(|(key, value)| values_equal(node.properties.get(key), value))
+}
+
+fn used_relationships(row: &Row, distinct_from: &[String]) -> HashSet<RelationshipId> {
+    distinct_from
+        .iter()
+        .filter_map(|name| row.get(name))
+        .flat_map(Entry::relationship_ids)
+        .collect()
+}
+
+// Every path of the length from a node, a relationship is used once per path
+#[allow(clippy::too_many_arguments)]
+fn expand_paths(
+    graph: &GraphStore,
+    id: NodeId,
+    filter: &RelationshipFilter,
+    direction: Direction,
+    length: Length,
+    used: &HashSet<RelationshipId>,
+    path: &mut Vec<RelationshipId>,
+    paths: &mut Vec<(Vec<RelationshipId>, NodeId)>,
+) {
+    if path.len() >= length.min {
+        paths.push((path.clone(), id));
+    }
+    if length.max.is_some_and(|max| path.len() >= max) {
+        return;
+    }
+    for (relationship_id, other_id) in neighbours(graph, id, direction) {
+        if used.contains(&relationship_id)
+            || path.contains(&relationship_id)
+            || !filter.matches(graph, relationship_id)
+        {
+            continue;
+        }
+        path.push(relationship_id);
+        expand_paths(
+            graph, other_id, filter, direction, length, used, path, paths,
+        );
+        path.pop();
+    }
+}
+
+// Breadth first search for the relationships of one of the shortest paths between two nodes
+fn shortest_path(
+    graph: &GraphStore,
+    from: NodeId,
+    to: NodeId,
+    filter: &RelationshipFilter,
+    direction: Direction,
+    max_length: Option<usize>,
+    used: &HashSet<RelationshipId>,
+) -> Option<Vec<RelationshipId>> {
+    // Node -> relationship it was reached by and the node before it
+    let mut parents: HashMap<NodeId, (RelationshipId, NodeId)> = HashMap::new();
+    let mut queue = VecDeque::from([(from, 0)]);
+    while let Some((id, depth)) = queue.pop_front() {
+        if max_length.is_some_and(|max| depth >= max) {
+            continue;
+        }
+        for (relationship_id, other_id) in neighbours(graph, id, direction) {
+            if other_id == from
+                || parents.contains_key(&other_id)
+                || used.contains(&relationship_id)
+                || !filter.matches(graph, relationship_id)
+            {
+                continue;
+            }
+            parents.insert(other_id, (relationship_id, id));
+            if other_id == to {
+                let mut path = Vec::new();
+                let mut current = to;
+                while current != from {
+                    let (relationship_id, previous) = parents[&current];
+                    path.push(relationship_id);
+                    current = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            queue.push_back((other_id, depth + 1));
+        }
+    }
+    None
+}
+
+// Relationships of a node in the direction, with the node on their other end
+fn neighbours(
+    graph: &GraphStore,
+    id: NodeId,
+    direction: Direction,
+) -> Vec<(RelationshipId, NodeId)> {
+    let mut result = Vec::new();
+    if matches!(direction, Direction::Outgoing | Direction::Both) {
+        for relationship_id in graph.outgoing(id) {
+            if let Some(relationship) = graph.relationship(*relationship_id) {
+                result.push((relationship.id, relationship.end));
+            }
+        }
+    }
+    if matches!(direction, Direction::Incoming | Direction::Both) {
+        for relationship_id in graph.incoming(id) {
+            if let Some(relationship) = graph.relationship(*relationship_id) {
+                // A loop is found once when both directions are followed
+                if direction == Direction::Both && relationship.start == relationship.end {
+                    continue;
+                }
+                result.push((relationship.id, relationship.start));
+            }
+        }
+    }
+    result
+}
+
+// 30 and 30.0 are the same number
+fn values_equal(property: Option<&serde_json::Value>, value: &serde_json::Value) -> bool {
+    property.is_some_and(|property| compare_json_values(property, value) == Ordering::Equal)
+}
+
+// An aggregate function of a RETURN or WITH item, bound to the variable per group
+pub struct Aggregation {
+    pub variable: String,
+    pub function: AggregateFunction,
+    pub distinct: bool,
+    // None for count(*)
+    pub argument: Option<Evaluator>,
+}
+
+impl Aggregation {
+    // Nulls are skipped by every function
+    fn aggregate(&self, graph: &GraphStore, values: Vec<Entry>) -> Result<Entry, String> {
+        if self.argument.is_none() {
+            return Ok(Entry::Value(serde_json::json!(values.len())));
+        }
+
+        let mut values: Vec<Entry> = values
+            .into_iter()
+            .filter(|value| *value != Entry::Value(serde_json::Value::Null))
+            .collect();
+        if self.distinct {
+            let mut seen = HashSet::new();
+            values.retain(|value| seen.insert(format!("{:?}", value)));
+        }
+
+        let numbers = || {
+            values
+                .iter()
+                .map(|value| match value {
+                    Entry::Value(serde_json::Value::Number(n)) => {
+                        Ok(n.as_f64().unwrap_or_default())
+                    }
+                    value => Err(format!(
+                        "{} needs numbers, got {:?}",
+                        self.function.name(),
+                        value
+                    )),
+                })
+                .collect::<Result<Vec<f64>, String>>()
+        };
+
+        Ok(match self.function {
+            AggregateFunction::Count => Entry::Value(serde_json::json!(values.len())),
+            AggregateFunction::Collect => Entry::List(values),
+            AggregateFunction::Sum => Entry::Value(number_to_json(numbers()?.iter().sum())),
+            AggregateFunction::Avg => {
+                let numbers = numbers()?;
+                if numbers.is_empty() {
+                    Entry::Value(serde_json::Value::Null)
+                } else {
+                    Entry::Value(number_to_json(
+                        numbers.iter().sum::<f64>() / numbers.len() as f64,
+                    ))
+                }
+            }
+            AggregateFunction::Min | AggregateFunction::Max => {
+                let wanted = if self.function == AggregateFunction::Min {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+                values
+                    .into_iter()
+                    .reduce(|best, value| {
+                        if compare_entries(graph, &value, &best) == wanted {
+                            value
+                        } else {
+                            best
+                        }
+                    })
+                    .unwrap_or(Entry::Value(serde_json::Value::Null))
+            }
+        })
+    }
+}
+
+// Types and properties a relationship must have
+pub struct RelationshipFilter {
+    pub types: Vec<String>,
+    pub properties: Properties,
+}
+
+impl RelationshipFilter {
+    fn matches(&self, graph: &GraphStore, id: RelationshipId) -> bool {
+        let Some(relationship) = graph.relationship(id) else {
+            return false;
+        };
+        (self.types.is_empty() || self.types.contains(&relationship.rel_type))
+            && self
+                .properties
+                .iter()
+                .all(|(key, value)| values_equal(relationship.properties.get(key), value))
+    }
+}
+
+pub enum Instruction {
+    MatchNode {
+        variable: String,
+        labels: Vec<String>,
+        properties: Properties,
+        // Comparisons a property index may answer, the nodes are still checked
+        index_plan: Option<IndexPlan>,
+    },
+    // Follows relationships from a bound node and binds them and the node on the other end
+    Expand {
+        from: String,
+        variable: String,
+        filter: RelationshipFilter,
+        direction: Direction,
+        // Variable length relationships bind a list of relationships
+        length: Option<Length>,
+        // Expanding from the right end of the pattern to the left
+        reversed: bool,
+        to: String,
+        // Relationship variables bound earlier in the same MATCH
+        distinct_from: Vec<String>,
+    },
+    // Binds the relationships of a shortest path between two bound nodes
+    ShortestPath {
+        from: String,
+        variable: String,
+        filter: RelationshipFilter,
+        direction: Direction,
+        max_length: Option<usize>,
+        to: String,
+        distinct_from: Vec<String>,
+    },
+    // Binds a path walking the relationship variables from the start node
+    BindPath {
+        variable: String,
+        start: String,
+        relationships: Vec<String>,
+    },
+    // Runs the procedure for every row and binds the yielded fields by index
+    Call {
+        procedure: Procedure,
+        arguments: Vec<Evaluator>,
+        yields: Vec<(usize, String)>,
+    },
+    // Keeps the rows where the predicate is true
+    Filter {
+        predicate: Evaluator,
+    },
+    // Groups the rows by the keys and binds the aggregations of every group
+    Aggregate {
+        keys: Vec<(String, Evaluator)>,
+        aggregations: Vec<Aggregation>,
+    },
+    // Binds the items in every row, the other variables stay for ORDER BY
+    Project {
+        items: Vec<(String, Evaluator)>,
+    },
+    Distinct {
+        columns: Vec<String>,
+    },
+    // The flag sorts in descending order
+    Sort {
+        keys: Vec<(Evaluator, bool)>,
+    },
+    Slice {
+        skip: usize,
+        limit: Option<usize>,
+    },
+    // Unbinds everything a WITH does not pass on
+    Scope {
+        columns: Vec<String>,
+    },
+    Return {
+        columns: Vec<String>,
+    },
+    // Outputs the change counts as a single row
+    ReturnStatistics,
+    CreateNode {
+        variable: String,
+        labels: Vec<String>,
+        properties: Properties,
+    },
+    CreateRelationship {
+        variable: String,
+        rel_type: String,
+        properties: Properties,
+        start: String,
+        end: String,
+    },
+    // Binds the pattern for each row if it matches, creates it otherwise
+    Merge {
+        match_instructions: Vec<Instruction>,
+        create_instructions: Vec<Instruction>,
+        on_match: Vec<Instruction>,
+        on_create: Vec<Instruction>,
+    },
+    // A null value removes the property
+    SetProperty {
+        variable: String,
+        property: String,
+        value: Evaluator,
+    },
+    CreateIndex {
+        label: String,
+        property: String,
+        field_type: FieldType,
+    },
+    DropIndex {
+        label: String,
+        property: String,
+    },
+    // Links a node to a key value document, or unlinks it when there is no value
+    SetDocument {
+        variable: String,
+        document: Option<Evaluator>,
+    },
+    SetLabels {
+        variable: String,
+        labels: Vec<String>,
+        remove: bool,
+    },
+    Delete {
+        variables: Vec<String>,
+        // Deletes the relationships of the nodes too
+        detach: bool,
+    },
+}
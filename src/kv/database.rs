@@ -45,28 +45,162 @@
 
 use super::error::Error;
 use super::index::{Index, IndexList};
+use super::persistence::{self, PersistenceConfig, Snapshot, Wal, WalRecord};
 use crate::common::FieldType;
 use serde_json;
 use std::collections::HashMap;
 
 pub struct Storage {
     pub name: String,
-    pub data: HashMap<String, String>,
+    // Values are kept parsed, so scans do not parse documents again
+    pub data: HashMap<String, serde_json::Value>,
     pub indexes: IndexList,
 }
 
 pub struct Database {
     pub storages: Vec<Storage>,
+    // None keeps the database in memory only
+    wal: Option<Wal>,
 }
 
 impl Database {
     pub fn new() -> Self {
         Self {
             storages: Vec::new(),
+            wal: None,
+        }
+    }
+
+    // Loads the last snapshot, replays the WAL on top of it and keeps logging to it
+    pub fn open(config: PersistenceConfig) -> Result<Self, Error> {
+        let mut database = Database::new();
+
+        let mut sequence = 0;
+        if let Some(snapshot) = persistence::read_snapshot(&config)? {
+            sequence = snapshot.sequence;
+            database.restore(snapshot)?;
+        }
+
+        let entries = persistence::read_wal(&config)?;
+        let mut replayed = 0;
+        // Every record in the WAL was applied once, one that fails now means the state is lost
+        for (number, entry) in entries.into_iter().enumerate() {
+            // Left behind by a crash after the snapshot was written but before the WAL was emptied
+            if entry.sequence <= sequence {
+                continue;
+            }
+            database.apply(entry.record).map_err(|e| {
+                Error::PersistenceError(format!(
+                    "Error replaying WAL record {}: {}",
+                    number + 1,
+                    e.into_string()
+                ))
+            })?;
+            sequence = entry.sequence;
+            replayed += 1;
+        }
+
+        database.wal = Some(Wal::open(config, replayed, sequence)?);
+        if database
+            .wal
+            .as_ref()
+            .is_some_and(|wal| wal.needs_snapshot())
+        {
+            database.snapshot()?;
+        }
+        Ok(database)
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        for storage in snapshot.storages {
+            let name = storage.name.into_owned();
+            self.create_storage(name.clone())?;
+            for (key, value) in storage.data.into_owned() {
+                self.insert_document(name.clone(), key, value)?;
+            }
+            for index in storage.indexes {
+                self.create_index(
+                    name.clone(),
+                    index.field_name,
+                    index.field_type,
+                    index.unique,
+                    index.ordered,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, record: WalRecord) -> Result<(), Error> {
+        match record {
+            WalRecord::CreateStorage { storage } => self.create_storage(storage),
+            WalRecord::DeleteStorage { storage } => self.delete_storage(storage),
+            WalRecord::Insert {
+                storage,
+                key,
+                value,
+            } => self.insert_document(storage, key, value),
+            WalRecord::Update {
+                storage,
+                key,
+                value,
+            } => self.update_document(storage, key, value),
+            WalRecord::Delete { storage, key } => self.delete(storage, key),
+            WalRecord::CreateIndex {
+                storage,
+                field,
+                field_type,
+                unique,
+                ordered,
+            } => self.create_index(storage, field, field_type, unique, ordered),
+            WalRecord::DropIndex { storage, field } => self.drop_index(storage, field),
+        }
+    }
+
+    // Records are built only when there is a WAL to write them to
+    fn wal_record(&self, record: impl FnOnce() -> WalRecord) -> Option<WalRecord> {
+        self.wal.as_ref().map(|_| record())
+    }
+
+    // Logs a change already made in memory, `undo` reverts it when the record can not be written.
+    // Once the record is in the WAL the write stands, a failed snapshot is tried again later
+    fn log(
+        &mut self,
+        record: Option<WalRecord>,
+        undo: impl FnOnce(&mut Self),
+    ) -> Result<(), Error> {
+        let (Some(wal), Some(record)) = (self.wal.as_mut(), record) else {
+            return Ok(());
+        };
+        if let Err(e) = wal.append(&record) {
+            undo(self);
+            return Err(e);
+        }
+        if wal.needs_snapshot() {
+            if let Err(e) = self.snapshot() {
+                println!("Error writing snapshot: {}", e.into_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn storage_mut(&mut self, storage_name: &str) -> Option<&mut Storage> {
+        self.storages
+            .iter_mut()
+            .find(|s| s.name.as_str() == storage_name)
+    }
+
+    pub fn snapshot(&mut self) -> Result<(), Error> {
+        match self.wal.as_mut() {
+            Some(wal) => wal.write_snapshot(&self.storages),
+            None => Ok(()),
         }
     }
 
     pub fn create_storage(&mut self, name: String) -> Result<(), Error> {
+        let record = self.wal_record(|| WalRecord::CreateStorage {
+            storage: name.clone(),
+        });
         if self.storages.iter().any(|s| s.name.as_str() == name) {
             return Err(Error::StorageError(format!(
                 "Storage with name '{}' already exists",
@@ -78,7 +212,9 @@ impl Database {
             data: HashMap::new(),
             indexes: IndexList::new(),
         });
-        Ok(())
+        self.log(record, |database| {
+            database.storages.pop();
+        })
     }
 
     pub fn get_storage(&self, name: String) -> Result<&Storage, Error> {
@@ -92,11 +228,27 @@ impl Database {
     }
 
     pub fn delete_storage(&mut self, storage_name: String) -> Result<(), Error> {
-        self.storages.retain(|s| s.name != storage_name);
-        Ok(())
+        let Some(position) = self.storages.iter().position(|s| s.name == storage_name) else {
+            return Ok(());
+        };
+        let storage = self.storages.remove(position);
+        let record = self.wal_record(|| WalRecord::DeleteStorage {
+            storage: storage_name,
+        });
+        self.log(record, |database| {
+            database.storages.insert(position, storage)
+        })
     }
 
     pub fn get(&self, storage_name: String, key: String) -> Result<String, Error> {
+        Ok(document_to_string(self.get_document(storage_name, key)?))
+    }
+
+    pub fn get_document(
+        &self,
+        storage_name: String,
+        key: String,
+    ) -> Result<&serde_json::Value, Error> {
         let storage = self
             .storages
             .iter()
@@ -106,15 +258,10 @@ impl Database {
                 storage_name
             )))?;
 
-        let value = storage
-            .data
-            .get(&key)
-            .ok_or(Error::KeyNotFound(format!(
-                "Key '{}' not found in storage '{}'",
-                key, storage_name
-            )))?
-            .clone();
-        Ok(value)
+        storage.data.get(&key).ok_or(Error::KeyNotFound(format!(
+            "Key '{}' not found in storage '{}'",
+            key, storage_name
+        )))
     }
 
     pub fn insert(
@@ -123,6 +270,20 @@ impl Database {
         key: String,
         value: String,
     ) -> Result<(), Error> {
+        self.insert_document(storage_name, key, parse_document(value))
+    }
+
+    pub fn insert_document(
+        &mut self,
+        storage_name: String,
+        key: String,
+        value: serde_json::Value,
+    ) -> Result<(), Error> {
+        let record = self.wal_record(|| WalRecord::Insert {
+            storage: storage_name.clone(),
+            key: key.clone(),
+            value: value.clone(),
+        });
         let storage = self
             .storages
             .iter_mut()
@@ -139,59 +300,22 @@ impl Database {
             )));
         }
 
-        let indexes = &mut storage.indexes;
-
-        // Parse the value as JSON
-        if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&value) {
-            // Check if it's an object
-            if let Some(obj) = json_value.as_object() {
-                // Iterate through all fields in the JSON object
-                for (field_name, field_value) in obj {
-                    // Process each field here
-                    if let Some(index) = indexes.get_index_mut(field_name) {
-                        match index {
-                            Index::HashUnique(hashmap) => {
-                                if let Some(field_str) = field_value.as_str() {
-                                    if hashmap.contains_key(&field_str.to_string()) {
-                                        return Err(Error::IndexError(format!(
-                                            "Unique constraint violation: '{}' = '{}' already exists",
-                                            field_name, field_str
-                                        )));
-                                    }
-                                    hashmap.insert(field_str.to_string(), key.clone());
-                                }
-                            }
-                            Index::Hash(hashmap) => {
-                                if let Some(field_str) = field_value.as_str() {
-                                    hashmap
-                                        .entry(field_str.to_string())
-                                        .or_insert_with(Vec::new)
-                                        .push(key.clone());
-                                }
-                            }
-                            Index::BTreeUnique(btreemap) => {
-                                // btreemap.insert(field_value.as_i64().unwrap(), key.clone());
-                                if let Some(field_num) = field_value.as_i64() {
-                                    if btreemap.contains_key(&field_num) {
-                                        return Err(Error::IndexError(format!(
-                                            "Unique constraint violation: '{}' = '{}' already exists",
-                                            field_name, field_num
-                                        )));
-                                    }
-                                    btreemap.insert(field_num, key.clone());
-                                }
-                            }
-                        }
-                    }
+        storage.indexes.update_entries(&key, None, Some(&value))?;
+        storage.data.insert(key.clone(), value);
+        self.log(record, |database| {
+            if let Some(storage) = database.storage_mut(&storage_name) {
+                if let Some(value) = storage.data.remove(&key) {
+                    let _ = storage.indexes.update_entries(&key, Some(&value), None);
                 }
             }
-        }
-
-        storage.data.insert(key, value);
-        Ok(())
+        })
     }
 
     pub fn delete(&mut self, storage_name: String, key: String) -> Result<(), Error> {
+        let record = self.wal_record(|| WalRecord::Delete {
+            storage: storage_name.clone(),
+            key: key.clone(),
+        });
         let storage = self
             .storages
             .iter_mut()
@@ -205,45 +329,14 @@ impl Database {
             "Key '{}' not found in storage '{}'",
             key, storage_name
         )))?;
-
-        let indexes = &mut storage.indexes;
-
-        // Parse the value as JSON
-        if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&value) {
-            // Check if it's an object
-            if let Some(obj) = json_value.as_object() {
-                // Iterate through all fields in the JSON object
-                for (field_name, field_value) in obj {
-                    // Process each field here
-                    if let Some(index) = indexes.get_index_mut(field_name) {
-                        match index {
-                            Index::HashUnique(hashmap) => {
-                                if let Some(field_str) = field_value.as_str() {
-                                    hashmap.remove(&field_str.to_string());
-                                }
-                            }
-                            Index::Hash(hashmap) => {
-                                if let Some(field_str) = field_value.as_str() {
-                                    if let Some(keys) = hashmap.get_mut(&field_str.to_string()) {
-                                        if let Some(i) = keys.iter().position(|k| *k == key) {
-                                            keys.remove(i);
-                                        }
-                                    }
-                                }
-                            }
-                            Index::BTreeUnique(btreemap) => {
-                                if let Some(field_num) = field_value.as_i64() {
-                                    btreemap.remove(&field_num);
-                                }
-                            }
-                        }
-                    }
-                }
+        storage.indexes.update_entries(&key, Some(value), None)?;
+        let value = storage.data.remove(&key);
+        self.log(record, |database| {
+            if let (Some(storage), Some(value)) = (database.storage_mut(&storage_name), value) {
+                let _ = storage.indexes.update_entries(&key, None, Some(&value));
+                storage.data.insert(key, value);
             }
-        }
-
-        storage.data.remove(&key);
-        Ok(())
+        })
     }
 
     pub fn update(
@@ -252,6 +345,20 @@ impl Database {
         key: String,
         new_value: String,
     ) -> Result<(), Error> {
+        self.update_document(storage_name, key, parse_document(new_value))
+    }
+
+    pub fn update_document(
+        &mut self,
+        storage_name: String,
+        key: String,
+        new_value: serde_json::Value,
+    ) -> Result<(), Error> {
+        let record = self.wal_record(|| WalRecord::Update {
+            storage: storage_name.clone(),
+            key: key.clone(),
+            value: new_value.clone(),
+        });
         let storage = self
             .storages
             .iter_mut()
@@ -261,61 +368,26 @@ impl Database {
                 storage_name
             )))?;
 
-        storage.data.get(&key).ok_or(Error::KeyNotFound(format!(
+        let old_value = storage.data.get(&key).ok_or(Error::KeyNotFound(format!(
             "Key '{}' not found in storage '{}'",
             key, storage_name
         )))?;
-
-        let indexes = &mut storage.indexes;
-
-        // Parse the value as JSON
-        if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&new_value) {
-            // Check if it's an object
-            if let Some(obj) = json_value.as_object() {
-                // Iterate through all fields in the JSON object
-                for (field_name, field_value) in obj {
-                    // Process each field here
-                    if let Some(index) = indexes.get_index_mut(field_name) {
-                        match index {
-                            Index::HashUnique(hashmap) => {
-                                if let Some(field_str) = field_value.as_str() {
-                                    if hashmap.contains_key(&field_str.to_string()) {
-                                        return Err(Error::IndexError(format!(
-                                            "Unique constraint violation: '{}' = '{}' already exists",
-                                            field_name, field_str
-                                        )));
-                                    }
-                                    hashmap.insert(field_str.to_string(), key.clone());
-                                }
-                            }
-                            Index::Hash(hashmap) => {
-                                if let Some(field_str) = field_value.as_str() {
-                                    hashmap
-                                        .entry(field_str.to_string())
-                                        .or_insert_with(Vec::new)
-                                        .push(key.clone());
-                                }
-                            }
-                            Index::BTreeUnique(btreemap) => {
-                                // btreemap.insert(field_value.as_i64().unwrap(), key.clone());
-                                if let Some(field_num) = field_value.as_i64() {
-                                    if btreemap.contains_key(&field_num) {
-                                        return Err(Error::IndexError(format!(
-                                            "Unique constraint violation: '{}' = '{}' already exists",
-                                            field_name, field_num
-                                        )));
-                                    }
-                                    btreemap.insert(field_num, key.clone());
-                                }
-                            }
-                        }
-                    }
-                }
+        // Unique indexes accept the document's own current values
+        storage
+            .indexes
+            .update_entries(&key, Some(old_value), Some(&new_value))?;
+        let old_value = storage.data.insert(key.clone(), new_value);
+        self.log(record, |database| {
+            if let (Some(storage), Some(old_value)) =
+                (database.storage_mut(&storage_name), old_value)
+            {
+                let new_value = storage.data.get(&key);
+                let _ = storage
+                    .indexes
+                    .update_entries(&key, new_value, Some(&old_value));
+                storage.data.insert(key, old_value);
             }
-        }
-
-        storage.data.insert(key, new_value);
-        Ok(())
+        })
     }
 
     pub fn create_index(
@@ -324,7 +396,15 @@ impl Database {
         field_name: String,
         field_type: FieldType,
         unique: bool,
+        ordered: bool,
     ) -> Result<(), Error> {
+        let record = self.wal_record(|| WalRecord::CreateIndex {
+            storage: storage_name.clone(),
+            field: field_name.clone(),
+            field_type: field_type.clone(),
+            unique,
+            ordered,
+        });
         let storage = self
             .storages
             .iter_mut()
@@ -333,121 +413,247 @@ impl Database {
                 "Storage with name '{}' not found",
                 storage_name
             )))?;
+        // Replacing an index here would leave the undo nothing to put back
+        if storage.indexes.index_exists(&field_name) {
+            return Err(Error::IndexError(format!(
+                "Index on '{}' already exists in storage '{}'",
+                field_name, storage_name
+            )));
+        }
 
-        let mut index = match field_type {
-            FieldType::String => {
-                if unique {
-                    Index::new_unique_hashmap()
-                } else {
-                    Index::new_hashmap()
-                }
-            }
-            FieldType::Number => {
-                if unique {
-                    Index::new_unique_btreemap()
-                } else {
-                    return Err(Error::IndexError(
-                        "Non unique indexes are not supported for numbers".to_string(),
-                    ));
-                }
-            }
-        };
+        let mut index = Index::new(field_type, unique, ordered);
 
-        // Iterate over all key-value pairs in the storage
-        for (key, value) in &storage.data {
-            // Try to parse the value as JSON
-            if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&value) {
-                // If the field exists in the JSON, add it to the index
-                if let Some(field_value) = json_value.get(&field_name) {
-                    match index {
-                        Index::HashUnique(_) => {
-                            if let Some(field_str) = field_value.as_str() {
-                                index
-                                    .add_string_unique(key.clone(), field_str.to_string())
-                                    .map_err(|e| {
-                                        Error::IndexError(format!("Error creating index: {:?}", e))
-                                    })?;
-                            }
-                        }
-                        Index::Hash(_) => {
-                            if let Some(field_str) = field_value.as_str() {
-                                index
-                                    .add_string(key.clone(), field_str.to_string())
-                                    .map_err(|e| {
-                                        Error::IndexError(format!("Error creating index: {:?}", e))
-                                    })?;
-                            }
-                        }
-                        Index::BTreeUnique(_) => {
-                            if let Some(field_num) = field_value.as_i64() {
-                                index
-                                    .add_number_unique(key.clone(), field_num)
-                                    .map_err(|e| {
-                                        Error::IndexError(format!("Error creating index: {:?}", e))
-                                    })?;
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        index
+            .build(&field_name, storage.data.iter())
+            .map_err(|e| Error::IndexError(format!("Error creating index: {}", e.into_string())))?;
 
-        storage.indexes.create_index(field_name, index);
-        Ok(())
+        storage.indexes.create_index(field_name.clone(), index);
+        self.log(record, |database| {
+            if let Some(storage) = database.storage_mut(&storage_name) {
+                storage.indexes.drop_index(&field_name);
+            }
+        })
     }
 
-    pub fn string_index_search(
-        &self,
-        storage_name: String,
-        index: &Index,
-        predicate: &Box<dyn Fn(String) -> bool>,
-    ) -> Result<Vec<String>, Error> {
+    pub fn drop_index(&mut self, storage_name: String, field_name: String) -> Result<(), Error> {
+        let record = self.wal_record(|| WalRecord::DropIndex {
+            storage: storage_name.clone(),
+            field: field_name.clone(),
+        });
         let storage = self
             .storages
-            .iter()
+            .iter_mut()
             .find(|s| s.name.as_str() == storage_name)
             .ok_or(Error::StorageError(format!(
                 "Storage with name '{}' not found",
                 storage_name
             )))?;
 
-        let result = match index {
-            Index::HashUnique(hashmap) => {
-                let keys = hashmap
-                    .iter()
-                    .filter(|(field, _)| predicate((*field).clone()))
-                    .map(|(_, key)| key.clone())
-                    .collect::<Vec<String>>();
-
-                // println!("Keys: {:?}", keys);
-
-                keys.iter()
-                    .map(|x| storage.data.get(x))
-                    .filter(|x| x.is_some())
-                    .map(|x| x.unwrap_or(&"{}".to_string()).clone())
-                    .collect::<Vec<String>>()
-            }
-            Index::Hash(hashmap) => {
-                let keys = hashmap
-                    .iter()
-                    .filter(|(field, _)| predicate((*field).clone()))
-                    .map(|(_, key)| key.clone())
-                    .flatten()
-                    .collect::<Vec<String>>();
-
-                keys.iter()
-                    .map(|x| storage.data.get(x))
-                    .filter(|x| x.is_some())
-                    .map(|x| x.unwrap_or(&"{}".to_string()).clone())
-                    .collect::<Vec<String>>()
-            }
-            _ => {
-                return Err(Error::IndexError(
-                    "Index is not a unique string index".to_string(),
-                ))
+        let index = storage
+            .indexes
+            .drop_index(&field_name)
+            .ok_or(Error::IndexError(format!(
+                "Index on '{}' not found in storage '{}'",
+                field_name, storage_name
+            )))?;
+        self.log(record, |database| {
+            if let Some(storage) = database.storage_mut(&storage_name) {
+                storage.indexes.create_index(field_name, index);
             }
+        })
+    }
+}
+
+// Values that are not JSON are kept as JSON strings. A JSON string keeps its quotes,
+// so '"abc"' and 'abc' are both read back as they were written
+pub fn parse_document(value: String) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(&value) {
+        Ok(serde_json::Value::String(_)) | Err(_) => serde_json::Value::String(value),
+        Ok(document) => document,
+    }
+}
+
+pub fn document_to_string(document: &serde_json::Value) -> String {
+    match document {
+        serde_json::Value::String(value) => value.clone(),
+        _ => document.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::FieldValue;
+    use crate::kv::persistence::{FsyncPolicy, WAL_FILE};
+
+    #[test]
+    fn test_create_existing_index() {
+        let mut database = Database::new();
+        database.create_storage("users".to_string()).unwrap();
+        database
+            .insert(
+                "users".to_string(),
+                "a".to_string(),
+                "{\"email\":\"a@x\"}".to_string(),
+            )
+            .unwrap();
+        for unique in [true, false] {
+            let result = database.create_index(
+                "users".to_string(),
+                "email".to_string(),
+                FieldType::String,
+                unique,
+                false,
+            );
+            assert_eq!(result.is_ok(), unique);
+        }
+
+        // The first index is kept and still unique
+        let storage = database.get_storage("users".to_string()).unwrap();
+        assert!(storage
+            .indexes
+            .get_index(&"email".to_string())
+            .unwrap()
+            .is_unique());
+    }
+
+    #[test]
+    fn test_document_round_trip() {
+        for text in [
+            "plain text",
+            "\"quoted\"",
+            "",
+            "42",
+            "true",
+            "null",
+            "[1,\"a\"]",
+            "{\"age\":30,\"name\":\"Ann\"}",
+        ] {
+            assert_eq!(document_to_string(&parse_document(text.to_string())), text);
+        }
+        assert_eq!(
+            parse_document("plain text".to_string()),
+            serde_json::json!("plain text")
+        );
+        assert_eq!(parse_document("{\"a\": [1]}".to_string())["a"][0], 1);
+
+        let mut database = Database::new();
+        database.create_storage("main".to_string()).unwrap();
+        for (key, text) in [("a", "plain text"), ("b", "\"quoted\""), ("c", "{\"n\":1}")] {
+            database
+                .insert("main".to_string(), key.to_string(), text.to_string())
+                .unwrap();
+            assert_eq!(
+                database.get("main".to_string(), key.to_string()).unwrap(),
+                text
+            );
+        }
+        database
+            .update(
+                "main".to_string(),
+                "a".to_string(),
+                "other text".to_string(),
+            )
+            .unwrap();
+        assert_eq!(
+            database.get("main".to_string(), "a".to_string()).unwrap(),
+            "other text"
+        );
+    }
+
+    // Every write to /dev/full fails, like a full disk
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_failed_log_undoes_the_write() {
+        let data_dir = std::env::temp_dir().join(format!("dark_witch_full_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::os::unix::fs::symlink("/dev/full", data_dir.join(WAL_FILE)).unwrap();
+        let config = PersistenceConfig {
+            data_dir: data_dir.clone(),
+            fsync: FsyncPolicy::Off,
+            snapshot_every: 0,
+        };
+
+        let mut database = Database::new();
+        database.create_storage("users".to_string()).unwrap();
+        database
+            .create_index(
+                "users".to_string(),
+                "email".to_string(),
+                FieldType::String,
+                true,
+                false,
+            )
+            .unwrap();
+        database
+            .insert(
+                "users".to_string(),
+                "a".to_string(),
+                "{\"email\":\"a@x\"}".to_string(),
+            )
+            .unwrap();
+        database.wal = Some(Wal::open(config, 0, 0).unwrap());
+
+        let email_keys = |database: &Database, email: &str| {
+            database
+                .get_storage("users".to_string())
+                .unwrap()
+                .indexes
+                .get_index(&"email".to_string())
+                .unwrap()
+                .search("=", &FieldValue::String(email.to_string()))
+                .unwrap()
         };
 
-        Ok(result)
+        assert!(database
+            .insert(
+                "users".to_string(),
+                "b".to_string(),
+                "{\"email\":\"b@x\"}".to_string()
+            )
+            .is_err());
+        assert!(database.get("users".to_string(), "b".to_string()).is_err());
+        assert!(email_keys(&database, "b@x").is_empty());
+
+        assert!(database
+            .update(
+                "users".to_string(),
+                "a".to_string(),
+                "{\"email\":\"a@y\"}".to_string()
+            )
+            .is_err());
+        assert_eq!(email_keys(&database, "a@x"), vec!["a"]);
+        assert!(email_keys(&database, "a@y").is_empty());
+
+        assert!(database
+            .delete("users".to_string(), "a".to_string())
+            .is_err());
+        assert_eq!(
+            database.get("users".to_string(), "a".to_string()).unwrap(),
+            "{\"email\":\"a@x\"}"
+        );
+        assert_eq!(email_keys(&database, "a@x"), vec!["a"]);
+
+        assert!(database
+            .drop_index("users".to_string(), "email".to_string())
+            .is_err());
+        assert!(database
+            .create_index(
+                "users".to_string(),
+                "age".to_string(),
+                FieldType::Number,
+                false,
+                false
+            )
+            .is_err());
+        assert!(database.create_storage("other".to_string()).is_err());
+        assert!(database.delete_storage("users".to_string()).is_err());
+        let storage = database.get_storage("users".to_string()).unwrap();
+        assert!(storage.indexes.index_exists(&"email".to_string()));
+        assert!(!storage.indexes.index_exists(&"age".to_string()));
+        assert!(database.get_storage("other".to_string()).is_err());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
     }
 }
@@ -45,6 +45,7 @@
 
 use serde::{Deserialize, Serialize};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Error {
     SyntaxError(String),
@@ -56,6 +57,7 @@ pub enum Error {
     KeyAlreadyExists(String),
     ExecutionError(String),
     IndexError(String),
+    PersistenceError(String),
 }
 
 impl Error {
@@ -70,6 +72,7 @@ impl Error {
             Error::KeyAlreadyExists(s) => s,
             Error::ExecutionError(s) => s,
             Error::IndexError(s) => s,
+            Error::PersistenceError(s) => s,
         }
     }
 
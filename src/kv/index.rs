@@ -43,26 +43,79 @@
 // MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
 // MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
 
+use crate::common::{self, FieldType};
 use crate::kv::error::Error;
+use crate::kv::json_path::column_value;
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
 
 pub type FieldName = String;
 pub type FieldValue = String;
 pub type Key = String;
 
+// Numbers as BTree keys, ordered by f64::total_cmp
+#[derive(Debug, Clone, Copy)]
+pub struct NumberKey(f64);
+
+impl NumberKey {
+    pub fn new(n: f64) -> Self {
+        // -0.0 and 0.0 are the same value in a query
+        Self(if n == 0.0 { 0.0 } else { n })
+    }
+}
+
+impl PartialEq for NumberKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for NumberKey {}
+
+impl PartialOrd for NumberKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NumberKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
 #[derive(Debug)]
 pub enum Index {
+    // Unique Index for numbers
+    BTreeUnique(BTreeMap<NumberKey, Key>),
     // Index for numbers
-    // key - key
+    // key - number
     // value - list of ids
-    BTreeUnique(BTreeMap<i64, Key>),
+    BTree(BTreeMap<NumberKey, Vec<Key>>),
     // Uniqe Index for strings
     HashUnique(HashMap<FieldValue, Key>),
     // Index for strings
     Hash(HashMap<FieldValue, Vec<Key>>),
+    // Unique ordered index for strings, answers ranges and prefixes
+    BTreeStringUnique(BTreeMap<FieldValue, Key>),
+    // Ordered index for strings
+    BTreeString(BTreeMap<FieldValue, Vec<Key>>),
 }
 
 impl Index {
+    // Strings go to hash maps unless `ordered`, numbers always to BTrees so they can answer ranges
+    pub fn new(field_type: FieldType, unique: bool, ordered: bool) -> Self {
+        match (field_type, unique, ordered) {
+            (FieldType::String, true, false) => Self::new_unique_hashmap(),
+            (FieldType::String, false, false) => Self::new_hashmap(),
+            (FieldType::String, true, true) => Self::BTreeStringUnique(BTreeMap::new()),
+            (FieldType::String, false, true) => Self::BTreeString(BTreeMap::new()),
+            (FieldType::Number, true, _) => Self::new_unique_btreemap(),
+            (FieldType::Number, false, _) => Self::new_btreemap(),
+        }
+    }
+
     pub fn new_unique_hashmap() -> Self {
         Self::HashUnique(HashMap::new())
     }
@@ -71,53 +124,241 @@ impl Index {
         Self::BTreeUnique(BTreeMap::new())
     }
 
+    pub fn new_btreemap() -> Self {
+        Self::BTree(BTreeMap::new())
+    }
+
     pub fn new_hashmap() -> Self {
         Self::Hash(HashMap::new())
     }
 
-    pub fn add_string_unique(&mut self, key: Key, field_value: FieldValue) -> Result<(), Error> {
-        if let Self::HashUnique(hashmap) = self {
-            if hashmap.contains_key(&key) {
-                return Err(Error::IndexError("Duplicate key".to_string()));
+    // Fails if another key already has this value in a unique index
+    fn check_unique(
+        &self,
+        field_name: &FieldName,
+        field_value: &serde_json::Value,
+        key: &Key,
+    ) -> Result<(), Error> {
+        let existing = match self {
+            Self::HashUnique(hashmap) => field_value.as_str().and_then(|s| hashmap.get(s)),
+            Self::BTreeStringUnique(btreemap) => field_value.as_str().and_then(|s| btreemap.get(s)),
+            Self::BTreeUnique(btreemap) => field_value
+                .as_f64()
+                .and_then(|n| btreemap.get(&NumberKey::new(n))),
+            Self::Hash(_) | Self::BTree(_) | Self::BTreeString(_) => None,
+        };
+        match existing {
+            Some(existing) if existing != key => Err(Error::IndexError(format!(
+                "Unique constraint violation: '{}' = '{}' already exists",
+                field_name, field_value
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // Values of the wrong type for the index are not indexed
+    fn insert_entry(&mut self, field_value: &serde_json::Value, key: Key) {
+        match self {
+            Self::HashUnique(hashmap) => {
+                if let Some(s) = field_value.as_str() {
+                    hashmap.insert(s.to_string(), key);
+                }
+            }
+            Self::Hash(hashmap) => {
+                if let Some(s) = field_value.as_str() {
+                    hashmap.entry(s.to_string()).or_default().push(key);
+                }
+            }
+            Self::BTreeStringUnique(btreemap) => {
+                if let Some(s) = field_value.as_str() {
+                    btreemap.insert(s.to_string(), key);
+                }
+            }
+            Self::BTreeString(btreemap) => {
+                if let Some(s) = field_value.as_str() {
+                    btreemap.entry(s.to_string()).or_default().push(key);
+                }
+            }
+            Self::BTreeUnique(btreemap) => {
+                if let Some(n) = field_value.as_f64() {
+                    btreemap.insert(NumberKey::new(n), key);
+                }
+            }
+            Self::BTree(btreemap) => {
+                if let Some(n) = field_value.as_f64() {
+                    btreemap.entry(NumberKey::new(n)).or_default().push(key);
+                }
+            }
+        }
+    }
+
+    fn remove_entry(&mut self, field_value: &serde_json::Value, key: &Key) {
+        match self {
+            Self::HashUnique(hashmap) => {
+                if let Some(s) = field_value.as_str() {
+                    if hashmap.get(s) == Some(key) {
+                        hashmap.remove(s);
+                    }
+                }
+            }
+            Self::Hash(hashmap) => {
+                if let Some(s) = field_value.as_str() {
+                    if let Some(keys) = hashmap.get_mut(s) {
+                        keys.retain(|k| k != key);
+                        if keys.is_empty() {
+                            hashmap.remove(s);
+                        }
+                    }
+                }
+            }
+            Self::BTreeStringUnique(btreemap) => {
+                if let Some(s) = field_value.as_str() {
+                    if btreemap.get(s) == Some(key) {
+                        btreemap.remove(s);
+                    }
+                }
+            }
+            Self::BTreeString(btreemap) => {
+                if let Some(s) = field_value.as_str() {
+                    if let Some(keys) = btreemap.get_mut(s) {
+                        keys.retain(|k| k != key);
+                        if keys.is_empty() {
+                            btreemap.remove(s);
+                        }
+                    }
+                }
+            }
+            Self::BTreeUnique(btreemap) => {
+                if let Some(n) = field_value.as_f64() {
+                    if btreemap.get(&NumberKey::new(n)) == Some(key) {
+                        btreemap.remove(&NumberKey::new(n));
+                    }
+                }
+            }
+            Self::BTree(btreemap) => {
+                if let Some(n) = field_value.as_f64() {
+                    if let Some(keys) = btreemap.get_mut(&NumberKey::new(n)) {
+                        keys.retain(|k| k != key);
+                        if keys.is_empty() {
+                            btreemap.remove(&NumberKey::new(n));
+                        }
+                    }
+                }
             }
-            hashmap.insert(field_value, key);
         }
-        Ok(())
     }
 
-    pub fn add_number_unique(&mut self, key: Key, num_value: i64) -> Result<(), Error> {
-        if let Self::BTreeUnique(btreemap) = self {
-            if btreemap.contains_key(&num_value) {
-                return Err(Error::IndexError("Duplicate key".to_string()));
+    // Indexes every document that has the field, fails on duplicates in unique indexes
+    pub fn build<'a>(
+        &mut self,
+        field_name: &FieldName,
+        documents: impl Iterator<Item = (&'a Key, &'a serde_json::Value)>,
+    ) -> Result<(), Error> {
+        for (key, document) in documents {
+            if let Some(field_value) = column_value(document, field_name) {
+                self.check_unique(field_name, field_value, key)?;
+                self.insert_entry(field_value, key.clone());
             }
-            btreemap.insert(num_value, key);
         }
         Ok(())
     }
 
-    pub fn add_string(&mut self, key: Key, field_value: FieldValue) -> Result<(), Error> {
-        if let Self::Hash(hashmap) = self {
-            hashmap
-                .entry(field_value)
-                .or_insert_with(Vec::new)
-                .push(key);
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            Self::BTreeUnique(_) | Self::BTree(_) => FieldType::Number,
+            Self::HashUnique(_)
+            | Self::Hash(_)
+            | Self::BTreeStringUnique(_)
+            | Self::BTreeString(_) => FieldType::String,
         }
-        Ok(())
     }
 
+    pub fn is_unique(&self) -> bool {
+        match self {
+            Self::BTreeUnique(_) | Self::HashUnique(_) | Self::BTreeStringUnique(_) => true,
+            Self::BTree(_) | Self::Hash(_) | Self::BTreeString(_) => false,
+        }
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        !matches!(self, Self::HashUnique(_) | Self::Hash(_))
+    }
+
+    // Keys of the rows where `field <operator> value` holds,
+    // None if this index can not answer the comparison
+    pub fn search(&self, operator: &str, value: &common::FieldValue) -> Option<Vec<Key>> {
+        match (self, value) {
+            (Self::HashUnique(hashmap), common::FieldValue::String(s)) if operator == "=" => {
+                Some(hashmap.get(s).cloned().into_iter().collect())
+            }
+            (Self::Hash(hashmap), common::FieldValue::String(s)) if operator == "=" => {
+                Some(hashmap.get(s).cloned().unwrap_or_default())
+            }
+            (Self::BTreeStringUnique(btreemap), common::FieldValue::String(s)) => Some(
+                btreemap
+                    .range::<str, _>(range_bounds(operator, s.as_str())?)
+                    .map(|(_, key)| key.clone())
+                    .collect(),
+            ),
+            (Self::BTreeString(btreemap), common::FieldValue::String(s)) => Some(
+                btreemap
+                    .range::<str, _>(range_bounds(operator, s.as_str())?)
+                    .flat_map(|(_, keys)| keys.iter().cloned())
+                    .collect(),
+            ),
+            (Self::BTreeUnique(btreemap), common::FieldValue::Number(n)) => Some(
+                btreemap
+                    .range(range_bounds(operator, NumberKey::new(*n))?)
+                    .map(|(_, key)| key.clone())
+                    .collect(),
+            ),
+            (Self::BTree(btreemap), common::FieldValue::Number(n)) => Some(
+                btreemap
+                    .range(range_bounds(operator, NumberKey::new(*n))?)
+                    .flat_map(|(_, keys)| keys.iter().cloned())
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    // Keys of the rows whose string starts with `prefix`, only ordered string indexes can answer
+    pub fn search_prefix(&self, prefix: &str) -> Option<Vec<Key>> {
+        let starts = (Bound::Included(prefix), Bound::Unbounded);
+        match self {
+            Self::BTreeStringUnique(btreemap) => Some(
+                btreemap
+                    .range::<str, _>(starts)
+                    .take_while(|(value, _)| value.starts_with(prefix))
+                    .map(|(_, key)| key.clone())
+                    .collect(),
+            ),
+            Self::BTreeString(btreemap) => Some(
+                btreemap
+                    .range::<str, _>(starts)
+                    .take_while(|(value, _)| value.starts_with(prefix))
+                    .flat_map(|(_, keys)| keys.iter().cloned())
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
     pub fn get_unique_hash_key(&self, field_value: FieldValue) -> Option<&Key> {
         match self {
             Self::HashUnique(hashmap) => hashmap.get(&field_value),
-            Self::BTreeUnique(_) => None,
-            Self::Hash(_) => None,
+            Self::BTreeUnique(_) | Self::BTree(_) => None,
+            Self::Hash(_) | Self::BTreeStringUnique(_) | Self::BTreeString(_) => None,
         }
     }
 
+    #[allow(dead_code)]
     pub fn get_hash_keys(&self, field_value: FieldValue) -> Option<&Vec<Key>> {
         match self {
             Self::Hash(hashmap) => hashmap.get(&field_value),
-            Self::HashUnique(_) => None,
-            Self::BTreeUnique(_) => None,
+            Self::HashUnique(_) | Self::BTreeStringUnique(_) | Self::BTreeString(_) => None,
+            Self::BTreeUnique(_) | Self::BTree(_) => None,
         }
     }
 }
@@ -141,11 +382,60 @@ impl IndexList {
         self.list.get(field_name)
     }
 
-    pub fn get_index_mut(&mut self, field_name: &FieldName) -> Option<&mut Index> {
-        self.list.get_mut(field_name)
+    pub fn drop_index(&mut self, field_name: &FieldName) -> Option<Index> {
+        self.list.remove(field_name)
+    }
+
+    // Moves the entries of `key` from the old document to the new one.
+    // None is a missing document, as before an insert or after a delete.
+    // All unique constraints are checked before anything changes.
+    pub fn update_entries(
+        &mut self,
+        key: &Key,
+        old_document: Option<&serde_json::Value>,
+        new_document: Option<&serde_json::Value>,
+    ) -> Result<(), Error> {
+        let changed = |field_name: &FieldName| {
+            let old_value = old_document.and_then(|d| column_value(d, field_name));
+            let new_value = new_document.and_then(|d| column_value(d, field_name));
+            (old_value != new_value).then_some((old_value, new_value))
+        };
+
+        for (field_name, index) in self.list.iter() {
+            if let Some((_, Some(new_value))) = changed(field_name) {
+                index.check_unique(field_name, new_value, key)?;
+            }
+        }
+
+        for (field_name, index) in self.list.iter_mut() {
+            if let Some((old_value, new_value)) = changed(field_name) {
+                if let Some(old_value) = old_value {
+                    index.remove_entry(old_value, key);
+                }
+                if let Some(new_value) = new_value {
+                    index.insert_entry(new_value, key.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&FieldName, &Index)> {
+        self.list.iter()
     }
 
     pub fn index_exists(&self, field_name: &FieldName) -> bool {
         self.list.contains_key(field_name)
     }
 }
+
+fn range_bounds<T: Copy>(operator: &str, n: T) -> Option<(Bound<T>, Bound<T>)> {
+    match operator {
+        "=" => Some((Bound::Included(n), Bound::Included(n))),
+        ">" => Some((Bound::Excluded(n), Bound::Unbounded)),
+        ">=" => Some((Bound::Included(n), Bound::Unbounded)),
+        "<" => Some((Bound::Unbounded, Bound::Excluded(n))),
+        "<=" => Some((Bound::Unbounded, Bound::Included(n))),
+        _ => None,
+    }
+}
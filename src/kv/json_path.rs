@@ -0,0 +1,114 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+
+use crate::kv::error::Error;
+use serde_json::{Map, Value};
+
+// Columns name fields of nested objects and array elements with paths like
+// profile.address.city or tags[0]. A key that is the whole name wins over the path,
+// so rows of joins, {"u": {...}}, and aggregates like COUNT(*) are found the same way
+pub fn column_value<'a>(json: &'a Value, name: &str) -> Option<&'a Value> {
+    if let Some(value) = json.get(name) {
+        return Some(value);
+    }
+    let split = name.find(['.', '[']).filter(|split| *split > 0)?;
+    let (head, rest) = name.split_at(split);
+    element_value(json.get(head)?, rest)
+}
+
+// The value at the rest of a path, which starts with . or [
+fn element_value<'a>(json: &'a Value, rest: &str) -> Option<&'a Value> {
+    if let Some(rest) = rest.strip_prefix('.') {
+        return column_value(json, rest);
+    }
+    let (position, rest) = rest.strip_prefix('[')?.split_once(']')?;
+    let element = json.get(position.parse::<usize>().ok()?)?;
+    if rest.is_empty() {
+        Some(element)
+    } else {
+        element_value(element, rest)
+    }
+}
+
+// Sets the value at a path, creating the missing objects on the way.
+// Array elements are only replaced, never added
+pub fn set_column_value(json: &mut Value, name: &str, value: Value) -> Result<(), Error> {
+    set_path(json, name, value).ok_or_else(|| {
+        Error::ExecutionError(format!(
+            "Can not set '{}', the path goes through a value that is not an object or a missing array element",
+            name
+        ))
+    })
+}
+
+fn set_path(json: &mut Value, name: &str, value: Value) -> Option<()> {
+    if json.is_null() {
+        *json = Value::Object(Map::new());
+    }
+    let document = json.as_object_mut()?;
+    let split = name
+        .find(['.', '['])
+        .filter(|split| *split > 0 && !document.contains_key(name));
+    let Some(split) = split else {
+        document.insert(name.to_string(), value);
+        return Some(());
+    };
+    let (head, rest) = name.split_at(split);
+    set_element(document.entry(head).or_insert(Value::Null), rest, value)
+}
+
+fn set_element(json: &mut Value, rest: &str, value: Value) -> Option<()> {
+    if let Some(rest) = rest.strip_prefix('.') {
+        return set_path(json, rest, value);
+    }
+    let (position, rest) = rest.strip_prefix('[')?.split_once(']')?;
+    let element = json.get_mut(position.parse::<usize>().ok()?)?;
+    if rest.is_empty() {
+        *element = value;
+        Some(())
+    } else {
+        set_element(element, rest, value)
+    }
+}
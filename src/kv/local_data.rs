@@ -50,6 +50,16 @@ use tokio::sync::Mutex;
 
 // Only for testing purposes, used in "local" feature. Will be removed in future.
 pub async fn fill_database(database: Arc<Mutex<Database>>) -> Arc<Mutex<Database>> {
+    // Already restored from the data directory
+    if database
+        .lock()
+        .await
+        .get_storage("main".to_string())
+        .is_ok()
+    {
+        return database;
+    }
+
     if let Err(e) = database.lock().await.create_storage("main".to_string()) {
         println!("Error creating storage: {:?}", e);
         panic!("Failed to create storage");
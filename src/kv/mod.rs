@@ -46,6 +46,8 @@
 pub mod database;
 pub mod error;
 pub mod index;
+pub mod json_path;
+pub mod persistence;
 pub mod query_handler;
 pub mod sql;
 pub mod witchvm_kv;
@@ -0,0 +1,566 @@
+// MIT License
+//
+// Copyright (c) 2025
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
+// MMMMMMMMMMMMds+:--------:+sdNMMMMMMMMMMM
+// MMMMMMMMms:-+sdNMMMMMMMMNdy+--omMMMMMMMM
+// MMMMMMh:` /mMMMMMMMMMMMMMMMMm+ `-yMMMMMM
+// MMMMd--hN``--sNMMMMMMMMMMNy:..`md:.hMMMM
+// MMM+`yMMMy hd+./hMMMMMMh/.+dd sMMMh`/MMM
+// MM:.mMMMMM:.NMMh/.+dd+./hMMM--MMMMMm--NM
+// M+`mMMMMMMN`+MMMMm-  .dMMMMo mMMMMMMN.:M
+// d yMMMMMMMMy dNy:.omNs--sNm oMMMMMMMMh h
+// /`MMMMMMMMMM.`.+dMMMMMMm+.``NMMMMMMMMM-:
+// .:MMMMMMMd+./`oMMMMMMMMMMs /.+dMMMMMMM/`
+// .:MMMMmo.:yNMs dMMMMMMMMm`oMNy:.omMMMM/`
+// /`MNy:.omMMMMM--MMMMMMMM:.MMMMMNs--sNM.:
+// d -` :++++++++: /++++++/ :++++++++:  : h
+// M+ yddddddddddd+ yddddy /dddddddddddy`/M
+// MM/.mMMMMMMMMMMM.-MMMM/.NMMMMMMMMMMm.:NM
+// MMMo`sMMMMMMMMMMd sMMy hMMMMMMMMMMy`+MMM
+// MMMMd--hMMMMMMMMM+`mN`/MMMMMMMMMh--hMMMM
+// MMMMMMh:.omMMMMMMN.:/`NMMMMMMms.:hMMMMMM
+// MMMMMMMMNs:./shmMMh  yMMNds/.:smMMMMMMMM
+// MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
+
+use super::database::Storage;
+use super::error::Error;
+use crate::common::FieldType;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+pub const WAL_FILE: &str = "wal.log";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const DEFAULT_DATA_DIR: &str = "witch_data";
+const DEFAULT_SNAPSHOT_EVERY: u64 = 10_000;
+// Records written between two fsyncs with the batch policy
+const FSYNC_BATCH_SIZE: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy {
+    // fsync after every record
+    Always,
+    // fsync every FSYNC_BATCH_SIZE records and before snapshots
+    Batch,
+    // Leave it to the OS
+    Off,
+}
+
+impl FsyncPolicy {
+    fn parse(value: &str) -> Result<Self, Error> {
+        match value.to_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "batch" => Ok(FsyncPolicy::Batch),
+            "off" => Ok(FsyncPolicy::Off),
+            _ => Err(Error::PersistenceError(format!(
+                "Unknown fsync policy '{}', expected always, batch or off",
+                value
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PersistenceConfig {
+    pub data_dir: PathBuf,
+    pub fsync: FsyncPolicy,
+    // WAL records after which a snapshot is taken, 0 never takes one
+    pub snapshot_every: u64,
+}
+
+impl PersistenceConfig {
+    // DARK_WITCH_DATA_DIR, DARK_WITCH_FSYNC and DARK_WITCH_SNAPSHOT_EVERY
+    pub fn from_env() -> Result<Self, Error> {
+        let data_dir = std::env::var("DARK_WITCH_DATA_DIR")
+            .unwrap_or(DEFAULT_DATA_DIR.to_string())
+            .into();
+
+        let fsync = match std::env::var("DARK_WITCH_FSYNC") {
+            Ok(value) => FsyncPolicy::parse(&value)?,
+            Err(_) => FsyncPolicy::Batch,
+        };
+
+        let snapshot_every = match std::env::var("DARK_WITCH_SNAPSHOT_EVERY") {
+            Ok(value) => value.parse::<u64>().map_err(|_| {
+                Error::PersistenceError(format!(
+                    "DARK_WITCH_SNAPSHOT_EVERY must be a number, got '{}'",
+                    value
+                ))
+            })?,
+            Err(_) => DEFAULT_SNAPSHOT_EVERY,
+        };
+
+        Ok(Self {
+            data_dir,
+            fsync,
+            snapshot_every,
+        })
+    }
+}
+
+// One line of the WAL, written after the operation succeeded in memory
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalRecord {
+    CreateStorage {
+        storage: String,
+    },
+    DeleteStorage {
+        storage: String,
+    },
+    Insert {
+        storage: String,
+        key: String,
+        value: serde_json::Value,
+    },
+    Update {
+        storage: String,
+        key: String,
+        value: serde_json::Value,
+    },
+    Delete {
+        storage: String,
+        key: String,
+    },
+    CreateIndex {
+        storage: String,
+        field: String,
+        field_type: FieldType,
+        unique: bool,
+        #[serde(default)]
+        ordered: bool,
+    },
+    DropIndex {
+        storage: String,
+        field: String,
+    },
+}
+
+// Records are numbered across snapshots, so replay can tell which ones a snapshot already holds
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalEntry<R> {
+    pub sequence: u64,
+    #[serde(flatten)]
+    pub record: R,
+}
+
+// Indexes are stored as definitions and rebuilt from the data on load
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub field_name: String,
+    pub field_type: FieldType,
+    pub unique: bool,
+    #[serde(default)]
+    pub ordered: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageSnapshot<'a> {
+    pub name: Cow<'a, str>,
+    pub data: Cow<'a, HashMap<String, serde_json::Value>>,
+    pub indexes: Vec<IndexDefinition>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot<'a> {
+    // Sequence number of the last WAL record contained in the snapshot
+    pub sequence: u64,
+    pub storages: Vec<StorageSnapshot<'a>>,
+}
+
+impl<'a> Snapshot<'a> {
+    fn from_storages(storages: &'a [Storage], sequence: u64) -> Self {
+        Self {
+            sequence,
+            storages: storages
+                .iter()
+                .map(|storage| StorageSnapshot {
+                    name: Cow::Borrowed(storage.name.as_str()),
+                    data: Cow::Borrowed(&storage.data),
+                    indexes: storage
+                        .indexes
+                        .iter()
+                        .map(|(field_name, index)| IndexDefinition {
+                            field_name: field_name.clone(),
+                            field_type: index.field_type(),
+                            unique: index.is_unique(),
+                            ordered: index.is_ordered(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub struct Wal {
+    config: PersistenceConfig,
+    file: File,
+    unsynced: u64,
+    records_since_snapshot: u64,
+    sequence: u64,
+}
+
+impl Wal {
+    pub fn open(
+        config: PersistenceConfig,
+        records_since_snapshot: u64,
+        sequence: u64,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(&config.data_dir).map_err(io_error)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.data_dir.join(WAL_FILE))
+            .map_err(io_error)?;
+        Ok(Self {
+            config,
+            file,
+            unsynced: 0,
+            records_since_snapshot,
+            sequence,
+        })
+    }
+
+    // A record that can not be written is cut off again,
+    // so the next one does not follow half a line and the caller can undo its change
+    pub fn append(&mut self, record: &WalRecord) -> Result<(), Error> {
+        let entry = WalEntry {
+            sequence: self.sequence + 1,
+            record,
+        };
+        let mut line =
+            serde_json::to_string(&entry).map_err(|e| Error::JsonError(e.to_string()))?;
+        line.push('\n');
+        let len = self.file.metadata().map_err(io_error)?.len();
+
+        let sync = match self.config.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Batch => self.unsynced + 1 >= FSYNC_BATCH_SIZE,
+            FsyncPolicy::Off => false,
+        };
+        let written = self.file.write_all(line.as_bytes()).and_then(|_| {
+            if sync {
+                self.file.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = written {
+            let _ = self.file.set_len(len);
+            return Err(io_error(e));
+        }
+
+        self.sequence = entry.sequence;
+        self.records_since_snapshot += 1;
+        self.unsynced = if sync { 0 } else { self.unsynced + 1 };
+        Ok(())
+    }
+
+    pub fn needs_snapshot(&self) -> bool {
+        self.config.snapshot_every > 0 && self.records_since_snapshot >= self.config.snapshot_every
+    }
+
+    // The snapshot replaces the old one atomically, only then the WAL is emptied.
+    // Records left in the WAL by a crash in between are skipped by their sequence number
+    pub fn write_snapshot(&mut self, storages: &[Storage]) -> Result<(), Error> {
+        if self.config.fsync != FsyncPolicy::Off {
+            self.sync()?;
+        }
+
+        let path = self.config.data_dir.join(SNAPSHOT_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        let file = File::create(&tmp_path).map_err(io_error)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(
+            &mut writer,
+            &Snapshot::from_storages(storages, self.sequence),
+        )
+        .map_err(|e| Error::JsonError(e.to_string()))?;
+        let file = writer.into_inner().map_err(|e| io_error(e.into_error()))?;
+        file.sync_all().map_err(io_error)?;
+        fs::rename(&tmp_path, &path).map_err(io_error)?;
+        sync_dir(&self.config.data_dir)?;
+
+        self.file.set_len(0).map_err(io_error)?;
+        self.file.sync_all().map_err(io_error)?;
+        self.unsynced = 0;
+        self.records_since_snapshot = 0;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data().map_err(io_error)?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+pub fn read_snapshot(config: &PersistenceConfig) -> Result<Option<Snapshot<'static>>, Error> {
+    let content = match fs::read(config.data_dir.join(SNAPSHOT_FILE)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(io_error(e)),
+    };
+    let snapshot = serde_json::from_slice(&content)
+        .map_err(|e| Error::PersistenceError(format!("Snapshot is not readable: {}", e)))?;
+    Ok(Some(snapshot))
+}
+
+// A record cut off by a crash can only be the last line, it is removed so new records
+// follow the last good one. A broken line before the end is corruption and fails the read
+pub fn read_wal(config: &PersistenceConfig) -> Result<Vec<WalEntry<WalRecord>>, Error> {
+    let path = config.data_dir.join(WAL_FILE);
+    let content = match fs::read(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(io_error(e)),
+    };
+
+    let mut records = Vec::new();
+    let mut valid_len = 0;
+    let mut lines = content
+        .split_inclusive(|b| *b == b'\n')
+        .enumerate()
+        .peekable();
+    while let Some((number, line)) = lines.next() {
+        let record = serde_json::from_slice::<WalEntry<WalRecord>>(line);
+        if lines.peek().is_none() && (record.is_err() || !line.ends_with(b"\n")) {
+            break;
+        }
+        records.push(record.map_err(|e| {
+            Error::PersistenceError(format!("WAL record {} is not readable: {}", number + 1, e))
+        })?);
+        valid_len += line.len();
+    }
+
+    if valid_len < content.len() {
+        println!(
+            "Dropping {} bytes of unfinished records at the end of the WAL",
+            content.len() - valid_len
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_len(valid_len as u64))
+            .map_err(io_error)?;
+    }
+
+    Ok(records)
+}
+
+// The rename of the snapshot is only durable once the directory itself is synced
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), Error> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(io_error)
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::PersistenceError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::database::Database;
+
+    fn test_config(name: &str, snapshot_every: u64) -> PersistenceConfig {
+        let data_dir =
+            std::env::temp_dir().join(format!("dark_witch_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        PersistenceConfig {
+            data_dir,
+            fsync: FsyncPolicy::Always,
+            snapshot_every,
+        }
+    }
+
+    fn fill(database: &mut Database) {
+        database.create_storage("users".to_string()).unwrap();
+        database
+            .create_index(
+                "users".to_string(),
+                "email".to_string(),
+                FieldType::String,
+                true,
+                false,
+            )
+            .unwrap();
+        for (key, value) in [
+            ("a", "{\"email\":\"a@x\",\"age\":30}"),
+            ("b", "{\"email\":\"b@x\",\"age\":25}"),
+            ("c", "plain text"),
+        ] {
+            database
+                .insert("users".to_string(), key.to_string(), value.to_string())
+                .unwrap();
+        }
+        database
+            .update(
+                "users".to_string(),
+                "a".to_string(),
+                "{\"email\":\"a@y\",\"age\":31}".to_string(),
+            )
+            .unwrap();
+        database
+            .delete("users".to_string(), "b".to_string())
+            .unwrap();
+    }
+
+    fn assert_restored(database: &Database) {
+        let storage = database.get_storage("users".to_string()).unwrap();
+        assert_eq!(storage.data.len(), 2);
+        assert_eq!(storage.data["a"]["age"], 31);
+        assert!(storage.indexes.index_exists(&"email".to_string()));
+        assert_eq!(
+            database.get("users".to_string(), "c".to_string()).unwrap(),
+            "plain text"
+        );
+        assert!(database.get("users".to_string(), "b".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_replay_wal() {
+        let config = test_config("replay_wal", 0);
+        fill(&mut Database::open(config.clone()).unwrap());
+
+        let mut database = Database::open(config.clone()).unwrap();
+        assert_restored(&database);
+        // The rebuilt unique index still rejects duplicates
+        assert!(database
+            .insert(
+                "users".to_string(),
+                "d".to_string(),
+                "{\"email\":\"a@y\"}".to_string(),
+            )
+            .is_err());
+        fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_and_unfinished_record() {
+        let config = test_config("snapshot", 4);
+        fill(&mut Database::open(config.clone()).unwrap());
+        assert!(config.data_dir.join(SNAPSHOT_FILE).exists());
+
+        // A crash in the middle of a write leaves half a record behind
+        let mut wal = OpenOptions::new()
+            .append(true)
+            .open(config.data_dir.join(WAL_FILE))
+            .unwrap();
+        wal.write_all(b"{\"op\":\"insert\",\"sto").unwrap();
+
+        assert_restored(&Database::open(config.clone()).unwrap());
+        assert_restored(&Database::open(config.clone()).unwrap());
+        fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_record_in_the_middle() {
+        let config = test_config("corrupt_wal", 0);
+        fill(&mut Database::open(config.clone()).unwrap());
+
+        // Only the last line can be a torn write, a broken one before it must not truncate the rest
+        let path = config.data_dir.join(WAL_FILE);
+        let content = fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<&str> = content.lines().collect();
+        lines[1] = "{\"broken\"";
+        let corrupted = lines.join("\n") + "\n";
+        fs::write(&path, &corrupted).unwrap();
+
+        assert!(matches!(
+            Database::open(config.clone()),
+            Err(Error::PersistenceError(e)) if e.contains("record 2")
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), corrupted);
+        fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_failing_record_stops_open() {
+        let config = test_config("failing_record", 0);
+        fill(&mut Database::open(config.clone()).unwrap());
+
+        // A record that can not be applied, like a second storage with the same name
+        let mut wal = OpenOptions::new()
+            .append(true)
+            .open(config.data_dir.join(WAL_FILE))
+            .unwrap();
+        let record = serde_json::to_string(&WalEntry {
+            sequence: 8,
+            record: WalRecord::CreateStorage {
+                storage: "users".to_string(),
+            },
+        })
+        .unwrap();
+        wal.write_all(format!("{}\n", record).as_bytes()).unwrap();
+
+        assert!(matches!(
+            Database::open(config.clone()),
+            Err(Error::PersistenceError(e)) if e.contains("record 8") && e.contains("already exists")
+        ));
+        fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_crash_before_wal_is_emptied() {
+        let config = test_config("snapshot_crash", 0);
+        let mut database = Database::open(config.clone()).unwrap();
+        fill(&mut database);
+
+        // The snapshot is in place but the WAL still holds the records it contains
+        let path = config.data_dir.join(WAL_FILE);
+        let records = fs::read(&path).unwrap();
+        database.snapshot().unwrap();
+        drop(database);
+        fs::write(&path, &records).unwrap();
+
+        let mut database = Database::open(config.clone()).unwrap();
+        assert_restored(&database);
+        database
+            .insert(
+                "users".to_string(),
+                "d".to_string(),
+                "{\"email\":\"d@x\"}".to_string(),
+            )
+            .unwrap();
+        drop(database);
+
+        // Records written after the snapshot are still replayed
+        let database = Database::open(config.clone()).unwrap();
+        let storage = database.get_storage("users".to_string()).unwrap();
+        assert_eq!(storage.data.len(), 3);
+        assert_eq!(storage.data["d"]["email"], "d@x");
+        fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+}
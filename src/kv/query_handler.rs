@@ -43,48 +43,358 @@
 // MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
 // MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
 
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
 
-use crate::kv::database::Database;
+use crate::graph::store::GraphStore;
+use crate::kv::database::{document_to_string, Database};
 use crate::kv::error::Error;
 use crate::kv::sql;
 use crate::kv::witchvm_kv::WitchVMKV;
 
-pub async fn handle_query(database: Arc<Mutex<Database>>, query: String) -> Result<String, Error> {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    // One array per row, values in the order of columns
+    pub rows: Vec<Vec<serde_json::Value>>,
+    pub row_count: usize,
+    pub elapsed_ms: f64,
+    // Rows as produced by the VM, for the raw response format
+    #[serde(skip)]
+    raw_rows: Vec<String>,
+}
+
+impl QueryResult {
+    pub fn new(
+        columns: Option<Vec<String>>,
+        documents: Vec<serde_json::Value>,
+        elapsed: Duration,
+    ) -> Self {
+        let raw_rows = documents.iter().map(document_to_string).collect();
+
+        // SELECT * takes columns from the documents, in order of first appearance
+        let columns = columns.unwrap_or_else(|| {
+            let mut columns: Vec<String> = Vec::new();
+            for document in documents.iter() {
+                match document.as_object() {
+                    Some(object) => {
+                        for name in object.keys() {
+                            if !columns.contains(name) {
+                                columns.push(name.clone());
+                            }
+                        }
+                    }
+                    None => {
+                        if !columns.iter().any(|c| c == VALUE_COLUMN) {
+                            columns.push(VALUE_COLUMN.to_string());
+                        }
+                    }
+                }
+            }
+            columns
+        });
+
+        let rows = documents
+            .iter()
+            .map(|document| match document.as_object() {
+                Some(object) => columns
+                    .iter()
+                    .map(|name| object.get(name).cloned().unwrap_or_default())
+                    .collect(),
+                // Values that are not JSON objects go to the value column
+                None => columns
+                    .iter()
+                    .map(|name| {
+                        if name == VALUE_COLUMN {
+                            document.clone()
+                        } else {
+                            serde_json::Value::Null
+                        }
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<Vec<serde_json::Value>>>();
+
+        Self {
+            columns,
+            row_count: rows.len(),
+            rows,
+            elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+            raw_rows,
+        }
+    }
+
+    pub fn try_to_string(&self) -> Result<String, Error> {
+        serde_json::to_string(self).map_err(|e| Error::JsonError(e.to_string()))
+    }
+
+    // Comma separated rows, the format /kv/sql used to return
+    pub fn into_raw_string(self) -> String {
+        self.raw_rows.join(",")
+    }
+}
+
+const VALUE_COLUMN: &str = "value";
+
+// A script returns the result of its last statement.
+// The graph is read by table functions like neighbors()
+pub async fn handle_query(
+    database: Arc<Mutex<Database>>,
+    graph: Arc<Mutex<GraphStore>>,
+    query: String,
+) -> Result<QueryResult, Error> {
     let mut database = database.lock().await;
+    let graph = graph.lock().await;
+    let start = Instant::now();
     let mut lexer = sql::Lexer::new(&query);
     let tokens = lexer.tokenize();
     let mut parser = sql::Parser::new(tokens);
-    let ast = parser.parse()?;
+    let statements = parser.parse_script()?;
     #[cfg(feature = "local")]
     {
-        println!("{:?}", ast);
+        println!("{:?}", statements);
     }
-    let mut generator = sql::CodeGenerator::new();
-    generator.generate(&ast)?;
-    let mut vm: WitchVMKV = WitchVMKV::new();
-    vm.execute(&mut database, generator.instructions)?;
-    Ok(vm.into_output().join(","))
+    let mut columns = Some(Vec::new());
+    let mut output = Vec::new();
+    // Every statement is atomic on its own, a script stops at the first failing one
+    for ast in statements.iter() {
+        let mut generator = sql::CodeGenerator::new();
+        generator.generate(ast)?;
+        let mut vm: WitchVMKV = WitchVMKV::new();
+        vm.execute(&mut database, &graph, generator.instructions)?;
+        columns = generator.columns;
+        output = vm.into_output();
+    }
+    Ok(QueryResult::new(columns, output, start.elapsed()))
 }
 
-pub async fn explain_query(database: Arc<Mutex<Database>>, query: String) -> Result<String, Error> {
+pub async fn explain_query(
+    database: Arc<Mutex<Database>>,
+    graph: Arc<Mutex<GraphStore>>,
+    query: String,
+) -> Result<String, Error> {
     let mut database = database.lock().await;
+    let graph = graph.lock().await;
     let mut lexer = sql::Lexer::new(&query);
     let tokens = lexer.tokenize();
     let mut parser = sql::Parser::new(tokens);
-    let ast = parser.parse()?;
+    let statements = parser.parse_script()?;
     #[cfg(feature = "local")]
     {
-        println!("{:?}", ast);
+        println!("{:?}", statements);
+    }
+    // Explain runs the statements for real, so only reads can be explained
+    if statements
+        .iter()
+        .any(|ast| !matches!(ast, sql::AstNode::Select { .. }))
+    {
+        return Err(Error::QueryError(
+            "Only SELECT statements can be explained".to_string(),
+        ));
     }
-    let mut generator = sql::CodeGenerator::new();
-    generator.generate(&ast)?;
     let mut vm: WitchVMKV = WitchVMKV::new();
-    let results = vm.execute(&mut database, generator.instructions)?;
+    let mut results = Vec::new();
+    for ast in statements.iter() {
+        let mut generator = sql::CodeGenerator::new();
+        generator.generate(ast)?;
+        results.extend(vm.execute(&mut database, &graph, generator.instructions)?);
+    }
     Ok(results
         .into_iter()
         .map(|x| serde_json::to_string(&x).unwrap_or("{}".to_string()))
         .collect::<Vec<String>>()
         .join(","))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::store::DocumentRef;
+
+    #[tokio::test]
+    async fn test_handle_query_result() {
+        let database = Arc::new(Mutex::new(Database::new()));
+        let graph = Arc::new(Mutex::new(GraphStore::new()));
+        database
+            .lock()
+            .await
+            .create_storage("main".to_string())
+            .unwrap();
+
+        let result = handle_query(
+            database.clone(),
+            graph.clone(),
+            "INSERT INTO main (KEY, name, age) VALUES ('a', 'Ann', 31), ('b', 'Bob', 42)"
+                .to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.columns, vec!["rows_affected"]);
+        assert_eq!(result.rows, vec![vec![serde_json::json!(2)]]);
+
+        let result = handle_query(
+            database.clone(),
+            graph.clone(),
+            "SELECT name, age FROM main ORDER BY age".to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.columns, vec!["name", "age"]);
+        assert_eq!(result.row_count, 2);
+        assert_eq!(
+            result.rows[0],
+            vec![serde_json::json!("Ann"), serde_json::json!(31)]
+        );
+
+        let result = handle_query(
+            database.clone(),
+            graph.clone(),
+            "SELECT * FROM main WHERE age > 100".to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.row_count, 0);
+        assert!(result.columns.is_empty());
+        assert_eq!(result.into_raw_string(), "");
+    }
+
+    #[tokio::test]
+    async fn test_neighbors_table_function() {
+        let database = Arc::new(Mutex::new(Database::new()));
+        let graph = Arc::new(Mutex::new(GraphStore::new()));
+        handle_query(
+            database.clone(),
+            graph.clone(),
+            "CREATE TABLE people; INSERT INTO people (KEY, name, age) \
+             VALUES ('person1', 'Ann', 31), ('person2', 'Bob', 42), ('person3', 'Cid', 27)"
+                .to_string(),
+        )
+        .await
+        .unwrap();
+        {
+            let mut graph = graph.lock().await;
+            let mut ids = Vec::new();
+            for key in ["person1", "person2", "person3"] {
+                let id = graph.create_node(Vec::new(), serde_json::Map::new());
+                let mut node = graph.node(id).unwrap().clone();
+                node.document = Some(DocumentRef {
+                    storage_name: "people".to_string(),
+                    key: key.to_string(),
+                });
+                graph.update_node(node).unwrap();
+                ids.push(id);
+            }
+            for (start, end, rel_type) in [(0, 1, "FRIEND"), (2, 0, "FRIEND"), (0, 2, "BLOCKED")] {
+                graph
+                    .create_relationship(
+                        ids[start],
+                        ids[end],
+                        rel_type.to_string(),
+                        serde_json::Map::new(),
+                    )
+                    .unwrap();
+            }
+        }
+
+        let result = handle_query(
+            database.clone(),
+            graph.clone(),
+            "SELECT name FROM neighbors('person1', 'FRIEND') ORDER BY name".to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![serde_json::json!("Bob")],
+                vec![serde_json::json!("Cid")]
+            ]
+        );
+
+        let result = handle_query(
+            database.clone(),
+            graph.clone(),
+            "SELECT * FROM neighbors('person1', 'FRIEND') WHERE age > 30".to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.columns, vec!["age", "name"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![serde_json::json!(42), serde_json::json!("Bob")]]
+        );
+
+        assert!(handle_query(
+            database.clone(),
+            graph.clone(),
+            "SELECT * FROM friends('person1')".to_string(),
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn test_columns_of_select_all() {
+        let result = QueryResult::new(
+            None,
+            vec![
+                serde_json::json!({ "name": "Ann" }),
+                serde_json::json!({ "age": 3, "name": "Bob" }),
+            ],
+            Duration::from_millis(1),
+        );
+        assert_eq!(result.columns, vec!["name", "age"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![serde_json::json!("Ann"), serde_json::Value::Null],
+                vec![serde_json::json!("Bob"), serde_json::json!(3)],
+            ]
+        );
+        assert_eq!(
+            result.into_raw_string(),
+            "{\"name\":\"Ann\"},{\"age\":3,\"name\":\"Bob\"}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explain_only_reads() {
+        let database = Arc::new(Mutex::new(Database::new()));
+        let graph = Arc::new(Mutex::new(GraphStore::new()));
+        database
+            .lock()
+            .await
+            .create_storage("main".to_string())
+            .unwrap();
+
+        for query in [
+            "INSERT INTO main (KEY, name) VALUES ('a', 'Ann')",
+            "SELECT * FROM main; DELETE FROM main",
+            "CREATE TABLE other",
+            "CREATE INDEX ON main (name)",
+        ] {
+            assert!(
+                explain_query(database.clone(), graph.clone(), query.to_string())
+                    .await
+                    .is_err(),
+                "{}",
+                query
+            );
+        }
+        {
+            let database = database.lock().await;
+            assert!(database.get_storage("other".to_string()).is_err());
+            let storage = database.get_storage("main".to_string()).unwrap();
+            assert!(storage.data.is_empty());
+            assert!(!storage.indexes.index_exists(&"name".to_string()));
+        }
+
+        assert!(
+            explain_query(database, graph, "SELECT * FROM main".to_string())
+                .await
+                .is_ok()
+        );
+    }
+}
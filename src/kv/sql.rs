@@ -43,10 +43,12 @@
 // MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
 // MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
 
+use crate::common::{FieldType, FieldValue};
 use crate::kv::error::Error;
-use crate::kv::witchvm_kv::Instruction;
+use crate::kv::json_path::{column_value, set_column_value};
+use crate::kv::witchvm_kv::{number_to_json, Aggregate, AggregateFunction, Instruction, JoinKind};
 
-use super::witchvm_kv::Filter;
+use super::witchvm_kv::{Filter, IndexPlan, Predicate};
 
 // Lexer: Converts raw SQL into tokens
 #[derive(Debug, Clone, PartialEq)]
@@ -61,10 +63,27 @@ pub enum Token {
     By,
     Limit,
     Offset,
+    Group,
+    Having,
+    As,
+    Insert,
+    Into,
+    Values,
+    Update,
+    Set,
+    Delete,
+    Create,
+    Drop,
 
     // Symbols
     Asterisk,
+    Plus,
+    Minus,
+    Slash,
     Comma,
+    Semicolon,
+    LeftParen,
+    RightParen,
     GreaterThan,
     LessThan,
     Equal,
@@ -80,6 +99,9 @@ pub enum Token {
     Number(f64),
     String(String),
 
+    // Text the lexer can not read, with the reason and where it is
+    Invalid(String),
+
     // End of input
     Eof,
 }
@@ -114,10 +136,29 @@ impl Lexer {
         }
     }
 
+    // Qualified names like u.name and paths like tags[0].name are one identifier
     fn read_identifier(&mut self) -> String {
         let start = self.position;
         while let Some(c) = self.peek() {
-            if !(c.is_alphanumeric() || c == '_') {
+            if c == '[' {
+                let digits = self.input[self.position + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count();
+                if digits == 0 || self.input.get(self.position + 1 + digits) != Some(&']') {
+                    break;
+                }
+                for _ in 0..digits + 2 {
+                    self.advance();
+                }
+                continue;
+            }
+            let qualifier = c == '.'
+                && self
+                    .input
+                    .get(self.position + 1)
+                    .is_some_and(|next| next.is_alphabetic() || *next == '_');
+            if !(c.is_alphanumeric() || c == '_' || qualifier) {
                 break;
             }
             self.advance();
@@ -131,7 +172,7 @@ impl Lexer {
         let mut has_decimal = false;
 
         while let Some(c) = self.peek() {
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 self.advance();
             } else if c == '.' && !has_decimal {
                 has_decimal = true;
@@ -145,26 +186,43 @@ impl Lexer {
         num_str.parse().unwrap_or(0.0)
     }
 
-    fn read_string(&mut self) -> String {
+    // None if the input ends before the closing quote
+    fn read_string(&mut self) -> Option<String> {
         // Skip the opening quote
         self.advance();
 
-        let start = self.position;
+        let mut string_value = String::new();
         while let Some(c) = self.peek() {
             if c == '\'' {
+                // Two quotes in a row are an escaped quote
+                if self.input.get(self.position + 1) == Some(&'\'') {
+                    string_value.push('\'');
+                    self.advance();
+                    self.advance();
+                    continue;
+                }
                 break;
             }
+            string_value.push(c);
             self.advance();
         }
 
-        let string_value = self.input[start..self.position].iter().collect();
-
         // Skip the closing quote
-        if self.peek() == Some('\'') {
-            self.advance();
-        }
+        self.peek()?;
+        self.advance();
+
+        Some(string_value)
+    }
 
-        string_value
+    // Line and column of a character, both counted from 1
+    fn location(&self, position: usize) -> String {
+        let before = &self.input[..position];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let line_start = before
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |newline| newline + 1);
+        format!("line {}, column {}", line, position - line_start + 1)
     }
 
     fn next_token(&mut self) -> Token {
@@ -176,10 +234,34 @@ impl Lexer {
                     self.advance();
                     Token::Asterisk
                 }
+                '+' => {
+                    self.advance();
+                    Token::Plus
+                }
+                '-' => {
+                    self.advance();
+                    Token::Minus
+                }
+                '/' => {
+                    self.advance();
+                    Token::Slash
+                }
                 ',' => {
                     self.advance();
                     Token::Comma
                 }
+                ';' => {
+                    self.advance();
+                    Token::Semicolon
+                }
+                '(' => {
+                    self.advance();
+                    Token::LeftParen
+                }
+                ')' => {
+                    self.advance();
+                    Token::RightParen
+                }
                 '>' => {
                     self.advance();
                     if self.peek() == Some('=') {
@@ -219,8 +301,14 @@ impl Lexer {
                     Token::Number(value)
                 }
                 '\'' => {
-                    let string_value = self.read_string();
-                    Token::String(string_value)
+                    let start = self.position;
+                    match self.read_string() {
+                        Some(string_value) => Token::String(string_value),
+                        None => Token::Invalid(format!(
+                            "Unterminated string at {}",
+                            self.location(start)
+                        )),
+                    }
                 }
                 'a'..='z' | 'A'..='Z' | '_' => {
                     let identifier = self.read_identifier();
@@ -234,12 +322,25 @@ impl Lexer {
                         "BY" => Token::By,
                         "LIMIT" => Token::Limit,
                         "OFFSET" => Token::Offset,
+                        "GROUP" => Token::Group,
+                        "HAVING" => Token::Having,
+                        "AS" => Token::As,
+                        "INSERT" => Token::Insert,
+                        "INTO" => Token::Into,
+                        "VALUES" => Token::Values,
+                        "UPDATE" => Token::Update,
+                        "SET" => Token::Set,
+                        "DELETE" => Token::Delete,
+                        "CREATE" => Token::Create,
+                        "DROP" => Token::Drop,
+                        "NOT" => Token::Not,
                         _ => Token::Identifier(identifier),
                     }
                 }
-                _ => {
+                c => {
+                    let location = self.location(self.position);
                     self.advance();
-                    Token::Eof
+                    Token::Invalid(format!("Unexpected character '{}' at {}", c, location))
                 }
             }
         } else {
@@ -262,34 +363,109 @@ impl Lexer {
 }
 
 // Parser: Constructs AST from tokens
-#[derive(Debug)]
-#[allow(private_interfaces)]
+#[derive(Debug, Clone)]
 pub enum AstNode {
     Select {
         fields: Vec<FieldExpression>,
-        from: String,
+        from: TableSource,
+        alias: Option<String>,
+        joins: Vec<Join>,
         where_clause: Option<Box<AstNode>>,
+        group_by: Vec<String>,
+        having: Option<Box<AstNode>>,
         order_by: Option<String>,
         limit: Option<i64>,
         offset: Option<i64>,
     },
+    Insert {
+        into: String,
+        columns: Vec<String>,
+        rows: Vec<Vec<LiteralValue>>,
+    },
+    Update {
+        table: String,
+        assignments: Vec<(String, AstNode)>,
+        where_clause: Option<Box<AstNode>>,
+    },
+    Delete {
+        from: String,
+        where_clause: Option<Box<AstNode>>,
+    },
+    CreateTable {
+        name: String,
+    },
+    DropTable {
+        name: String,
+        if_exists: bool,
+    },
+    CreateIndex {
+        table: String,
+        field: String,
+        field_type: FieldType,
+        unique: bool,
+        ordered: bool,
+    },
+    DropIndex {
+        table: String,
+        field: String,
+    },
     BinaryOp {
         left: Box<AstNode>,
         operator: String,
         right: Box<AstNode>,
     },
+    Not(Box<AstNode>),
+    // column IN (values), NOT IN is Not(In)
+    In {
+        left: Box<AstNode>,
+        values: Vec<LiteralValue>,
+    },
+    // column LIKE 'pattern', % is any number of characters and _ exactly one
+    Like {
+        left: Box<AstNode>,
+        pattern: String,
+    },
+    // Missing fields and JSON null, IS NOT NULL is Not(IsNull)
+    IsNull(Box<AstNode>),
     Column(String),
+    Aggregate {
+        function: AggregateFunction,
+        field: Option<String>,
+    },
     Literal(LiteralValue),
 }
 
 #[derive(Debug, Clone)]
-enum FieldExpression {
+pub enum TableSource {
+    Storage(String),
+    // neighbors('key', 'TYPE'): documents linked in the graph to the document with that key
+    Neighbours { key: String, rel_type: String },
+}
+
+// JOIN table alias ON left = right, right is a column of the joined table
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: String,
+    pub alias: String,
+    pub left: String,
+    pub right: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum FieldExpression {
     AllColumns,
     Field(String),
+    Aggregate {
+        function: AggregateFunction,
+        // None for COUNT(*)
+        field: Option<String>,
+        alias: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone)]
-enum LiteralValue {
+pub enum LiteralValue {
     Number(f64),
     String(String),
 }
@@ -338,50 +514,24 @@ impl Parser {
             fields.push(FieldExpression::AllColumns);
             self.advance();
         } else {
-            // Parse first field
-            match self.peek() {
-                Some(Token::Identifier(name)) => {
-                    fields.push(FieldExpression::Field(name.clone()));
-                    self.advance();
-                }
-                _ => {
-                    return Err(Error::SyntaxError(
-                        "Expected identifier in SELECT".to_string(),
-                    ))
-                }
-            }
+            fields.push(self.parse_select_item()?);
 
             // Parse additional fields after commas
             while self.peek() == Some(&Token::Comma) {
                 self.advance(); // consume comma
-                match self.peek() {
-                    Some(Token::Identifier(name)) => {
-                        fields.push(FieldExpression::Field(name.clone()));
-                        self.advance();
-                    }
-                    _ => {
-                        return Err(Error::SyntaxError(
-                            "Expected identifier after comma".to_string(),
-                        ))
-                    }
-                }
+                fields.push(self.parse_select_item()?);
             }
         }
 
         // Parse FROM clause
         self.expect(Token::From)?;
-        let table_name = match self.peek() {
-            Some(Token::Identifier(name)) => {
-                let name = name.clone();
-                self.advance();
-                name
-            }
-            _ => {
-                return Err(Error::SyntaxError(
-                    "Expected table name after FROM".to_string(),
-                ))
-            }
-        };
+        let table_name = self.parse_table_source()?;
+        let alias = self.parse_alias()?;
+
+        let mut joins = Vec::new();
+        while let Some(kind) = self.parse_join_kind()? {
+            joins.push(self.parse_join(kind)?);
+        }
 
         // Parse WHERE clause (if present)
         let where_clause = if self.peek() == Some(&Token::Where) {
@@ -392,6 +542,26 @@ impl Parser {
             None
         };
 
+        // Parse GROUP BY clause (if present)
+        let mut group_by = Vec::new();
+        if self.peek() == Some(&Token::Group) {
+            self.advance();
+            self.expect(Token::By)?;
+            group_by.push(self.parse_identifier("column name after GROUP BY")?);
+            while self.peek() == Some(&Token::Comma) {
+                self.advance();
+                group_by.push(self.parse_identifier("column name after comma")?);
+            }
+        }
+
+        // Parse HAVING clause (if present)
+        let having = if self.peek() == Some(&Token::Having) {
+            self.advance();
+            Some(Box::new(self.parse_expression()?))
+        } else {
+            None
+        };
+
         // Parse ORDER BY clause (if present)
         let order_by = if self.peek() == Some(&Token::Order) {
             self.advance();
@@ -457,131 +627,810 @@ impl Parser {
         Ok(AstNode::Select {
             fields,
             from: table_name,
+            alias,
+            joins,
             where_clause,
+            group_by,
+            having,
             order_by,
             limit,
             offset,
         })
     }
 
-    fn parse_expression(&mut self) -> Result<AstNode, Error> {
-        let mut left = self.parse_comparison()?;
-
-        while let Some(token) = self.peek() {
-            match token {
-                Token::And | Token::Or => {
-                    let operator = match token {
-                        Token::And => "AND".to_string(),
-                        Token::Or => "OR".to_string(),
-                        _ => {
-                            return Err(Error::SyntaxError(format!(
-                                "Expected operator AND and OR, got {:?}",
-                                token
-                            )))
-                        }
-                    };
-                    self.advance();
-                    let right = self.parse_comparison()?;
-                    left = AstNode::BinaryOp {
-                        left: Box::new(left),
-                        operator,
-                        right: Box::new(right),
-                    };
-                }
-                _ => break,
-            }
-        }
-
-        Ok(left)
-    }
-
-    // New method to handle basic comparisons
-    fn parse_comparison(&mut self) -> Result<AstNode, Error> {
-        let left = match self.peek() {
+    // A table or a table function, e.g. neighbors('person1', 'FRIEND')
+    fn parse_table_source(&mut self) -> Result<TableSource, Error> {
+        let name = match self.peek() {
             Some(Token::Identifier(name)) => {
                 let name = name.clone();
                 self.advance();
-                AstNode::Column(name)
+                name
             }
             _ => {
                 return Err(Error::SyntaxError(
-                    "Expected identifier in WHERE clause".to_string(),
+                    "Expected table name after FROM".to_string(),
                 ))
             }
         };
+        if self.peek() != Some(&Token::LeftParen) {
+            return Ok(TableSource::Storage(name));
+        }
+        if !name.eq_ignore_ascii_case("neighbors") {
+            return Err(Error::SyntaxError(format!(
+                "Unknown table function '{}'",
+                name
+            )));
+        }
 
-        let operator = match self.peek() {
-            Some(Token::GreaterThan) => {
+        self.advance();
+        let key = self.parse_string_argument()?;
+        self.expect(Token::Comma)?;
+        let rel_type = self.parse_string_argument()?;
+        self.expect(Token::RightParen)?;
+        Ok(TableSource::Neighbours { key, rel_type })
+    }
+
+    // users u or users AS u
+    fn parse_alias(&mut self) -> Result<Option<String>, Error> {
+        if self.peek() == Some(&Token::As) {
+            self.advance();
+            return Ok(Some(self.parse_identifier("alias after AS")?));
+        }
+        if ["JOIN", "INNER", "LEFT", "ON"]
+            .iter()
+            .any(|word| self.peek_word(word))
+        {
+            return Ok(None);
+        }
+        match self.peek() {
+            Some(Token::Identifier(alias)) => {
+                let alias = alias.clone();
                 self.advance();
-                ">".to_string()
+                Ok(Some(alias))
             }
-            Some(Token::GreaterThanEqual) => {
+            _ => Ok(None),
+        }
+    }
+
+    // JOIN, INNER JOIN, LEFT JOIN or LEFT OUTER JOIN
+    fn parse_join_kind(&mut self) -> Result<Option<JoinKind>, Error> {
+        let kind = if self.peek_word("LEFT") {
+            self.advance();
+            if self.peek_word("OUTER") {
                 self.advance();
-                ">=".to_string()
             }
-            Some(Token::LessThan) => {
-                self.advance();
-                "<".to_string()
+            JoinKind::Left
+        } else if self.peek_word("INNER") {
+            self.advance();
+            JoinKind::Inner
+        } else if self.peek_word("JOIN") {
+            JoinKind::Inner
+        } else {
+            return Ok(None);
+        };
+        self.expect_word("JOIN")?;
+        Ok(Some(kind))
+    }
+
+    fn parse_join(&mut self, kind: JoinKind) -> Result<Join, Error> {
+        let table = self.parse_identifier("table name after JOIN")?;
+        let alias = self.parse_alias()?.unwrap_or(table.clone());
+        self.expect_word("ON")?;
+        let left = self.parse_identifier("column name after ON")?;
+        self.expect(Token::Equal)?;
+        let right = self.parse_identifier("column name after =")?;
+
+        // ON u.KEY = o.user_id is the same as ON o.user_id = u.KEY
+        let joined = |column: &String| {
+            column
+                .split_once('.')
+                .is_some_and(|(table, _)| *table == alias)
+        };
+        let (left, right) = match (joined(&left), joined(&right)) {
+            (false, true) => (left, right),
+            (true, false) => (right, left),
+            _ => {
+                return Err(Error::SyntaxError(format!(
+                    "JOIN condition must compare a column of '{}' with a column of another table",
+                    alias
+                )))
             }
-            Some(Token::LessThanEqual) => {
+        };
+        Ok(Join {
+            kind,
+            table,
+            right: right[alias.len() + 1..].to_string(),
+            alias,
+            left,
+        })
+    }
+
+    fn parse_string_argument(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some(Token::String(value)) => {
+                let value = value.clone();
                 self.advance();
-                "<=".to_string()
+                Ok(value)
             }
-            Some(Token::Equal) => {
+            token => Err(Error::SyntaxError(format!(
+                "Expected string argument, got {:?}",
+                token
+            ))),
+        }
+    }
+
+    fn parse_select_item(&mut self) -> Result<FieldExpression, Error> {
+        if let Some(function) = self.peek_aggregate_function() {
+            let field = self.parse_aggregate_call(function)?;
+            let alias = if self.peek() == Some(&Token::As) {
                 self.advance();
-                "=".to_string()
+                Some(self.parse_identifier("alias after AS")?)
+            } else {
+                None
+            };
+            return Ok(FieldExpression::Aggregate {
+                function,
+                field,
+                alias,
+            });
+        }
+
+        Ok(FieldExpression::Field(
+            self.parse_identifier("identifier in SELECT")?,
+        ))
+    }
+
+    // COUNT, SUM, ... are only functions when followed by a parenthesis
+    fn peek_aggregate_function(&self) -> Option<AggregateFunction> {
+        let Some(Token::Identifier(name)) = self.peek() else {
+            return None;
+        };
+        if self.tokens.get(self.position + 1) != Some(&Token::LeftParen) {
+            return None;
+        }
+        match name.to_uppercase().as_str() {
+            "COUNT" => Some(AggregateFunction::Count),
+            "SUM" => Some(AggregateFunction::Sum),
+            "AVG" => Some(AggregateFunction::Avg),
+            "MIN" => Some(AggregateFunction::Min),
+            "MAX" => Some(AggregateFunction::Max),
+            _ => None,
+        }
+    }
+
+    fn parse_aggregate_call(
+        &mut self,
+        function: AggregateFunction,
+    ) -> Result<Option<String>, Error> {
+        self.advance(); // function name
+        self.expect(Token::LeftParen)?;
+        let field = if self.peek() == Some(&Token::Asterisk) {
+            if function != AggregateFunction::Count {
+                return Err(Error::SyntaxError(format!(
+                    "{}(*) is not supported, only COUNT(*)",
+                    function.name()
+                )));
             }
-            Some(Token::NotEqual) => {
+            self.advance();
+            None
+        } else {
+            Some(self.parse_identifier("column name in aggregate function")?)
+        };
+        self.expect(Token::RightParen)?;
+        Ok(field)
+    }
+
+    fn parse_insert(&mut self) -> Result<AstNode, Error> {
+        self.expect(Token::Insert)?;
+        self.expect(Token::Into)?;
+
+        let table_name = match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
                 self.advance();
-                "!=".to_string()
+                name
             }
-            val => {
-                return Err(Error::SyntaxError(format!(
-                    "Expected comparison operator, got {:?}",
-                    val
-                )))
+            _ => {
+                return Err(Error::SyntaxError(
+                    "Expected table name after INTO".to_string(),
+                ))
             }
         };
 
-        let right = match self.peek() {
+        // Parse column list
+        self.expect(Token::LeftParen)?;
+        let mut columns = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Identifier(name)) => {
+                    columns.push(name.clone());
+                    self.advance();
+                }
+                _ => {
+                    return Err(Error::SyntaxError(
+                        "Expected column name in INSERT".to_string(),
+                    ))
+                }
+            }
+
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(Token::RightParen)?;
+
+        // Parse one or more value tuples
+        self.expect(Token::Values)?;
+        let mut rows = Vec::new();
+        loop {
+            self.expect(Token::LeftParen)?;
+            let mut row = Vec::new();
+            loop {
+                row.push(self.parse_literal()?);
+
+                if self.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+            self.expect(Token::RightParen)?;
+            rows.push(row);
+
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+
+        Ok(AstNode::Insert {
+            into: table_name,
+            columns,
+            rows,
+        })
+    }
+
+    fn parse_literal(&mut self) -> Result<LiteralValue, Error> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.advance();
+                match self.peek() {
+                    Some(Token::Number(n)) => {
+                        let value = -*n;
+                        self.advance();
+                        Ok(LiteralValue::Number(value))
+                    }
+                    val => Err(Error::SyntaxError(format!(
+                        "Expected number after '-', got {:?}",
+                        val
+                    ))),
+                }
+            }
             Some(Token::Number(n)) => {
                 let value = *n;
                 self.advance();
-                AstNode::Literal(LiteralValue::Number(value))
+                Ok(LiteralValue::Number(value))
             }
             Some(Token::String(s)) => {
                 let value = s.clone();
                 self.advance();
-                AstNode::Literal(LiteralValue::String(value))
+                Ok(LiteralValue::String(value))
+            }
+            val => Err(Error::SyntaxError(format!(
+                "Expected literal value, got {:?}",
+                val
+            ))),
+        }
+    }
+
+    fn parse_update(&mut self) -> Result<AstNode, Error> {
+        self.expect(Token::Update)?;
+
+        let table_name = match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                name
             }
             _ => {
                 return Err(Error::SyntaxError(
-                    "Expected literal value after operator".to_string(),
+                    "Expected table name after UPDATE".to_string(),
                 ))
             }
         };
 
-        Ok(AstNode::BinaryOp {
-            left: Box::new(left),
-            operator,
-            right: Box::new(right),
-        })
-    }
+        self.expect(Token::Set)?;
+        let mut assignments = Vec::new();
+        loop {
+            let column = match self.peek() {
+                Some(Token::Identifier(name)) => {
+                    let name = name.clone();
+                    self.advance();
+                    name
+                }
+                _ => {
+                    return Err(Error::SyntaxError(
+                        "Expected column name in SET".to_string(),
+                    ))
+                }
+            };
+            self.expect(Token::Equal)?;
+            assignments.push((column, self.parse_arithmetic()?));
 
-    pub fn parse(&mut self) -> Result<AstNode, Error> {
-        self.parse_select()
-    }
-}
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+
+        let where_clause = if self.peek() == Some(&Token::Where) {
+            self.advance();
+            Some(Box::new(self.parse_expression()?))
+        } else {
+            None
+        };
+
+        Ok(AstNode::Update {
+            table: table_name,
+            assignments,
+            where_clause,
+        })
+    }
+
+    // Words like TABLE or TYPE are only keywords inside DDL, so they stay usable as field names
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(name)) if name.eq_ignore_ascii_case(word))
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), Error> {
+        if self.peek_word(word) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(Error::SyntaxError(format!(
+                "Expected {}, got {:?}",
+                word,
+                self.peek()
+            )))
+        }
+    }
+
+    fn parse_identifier(&mut self, context: &str) -> Result<String, Error> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(Error::SyntaxError(format!("Expected {}", context))),
+        }
+    }
+
+    fn parse_create(&mut self) -> Result<AstNode, Error> {
+        self.expect(Token::Create)?;
+
+        if self.peek_word("TABLE") {
+            self.advance();
+            let name = self.parse_identifier("table name after CREATE TABLE")?;
+            return Ok(AstNode::CreateTable { name });
+        }
+
+        let unique = self.peek_word("UNIQUE");
+        if unique {
+            self.advance();
+        }
+        self.expect_word("INDEX")?;
+        let (table, field) = self.parse_index_target()?;
+
+        let field_type = if self.peek_word("TYPE") {
+            self.advance();
+            let type_name = self.parse_identifier("index type after TYPE")?;
+            match type_name.to_lowercase().as_str() {
+                "string" => FieldType::String,
+                "number" => FieldType::Number,
+                _ => {
+                    return Err(Error::SyntaxError(format!(
+                        "Unknown index type '{}', expected string or number",
+                        type_name
+                    )))
+                }
+            }
+        } else {
+            FieldType::String
+        };
+
+        // Number indexes are always ordered, string indexes are hashed unless USING BTREE
+        let ordered = if self.peek_word("USING") {
+            self.advance();
+            let method = self.parse_identifier("index method after USING")?;
+            match (method.to_lowercase().as_str(), &field_type) {
+                ("btree", _) => true,
+                ("hash", FieldType::String) => false,
+                _ => {
+                    return Err(Error::SyntaxError(format!(
+                        "Unknown index method '{}' for {:?} index, expected BTREE or HASH",
+                        method, field_type
+                    )))
+                }
+            }
+        } else {
+            field_type == FieldType::Number
+        };
+
+        Ok(AstNode::CreateIndex {
+            table,
+            field,
+            field_type,
+            unique,
+            ordered,
+        })
+    }
+
+    fn parse_drop(&mut self) -> Result<AstNode, Error> {
+        self.expect(Token::Drop)?;
+
+        if self.peek_word("INDEX") {
+            self.advance();
+            let (table, field) = self.parse_index_target()?;
+            return Ok(AstNode::DropIndex { table, field });
+        }
+
+        self.expect_word("TABLE")?;
+        let if_exists = self.peek_word("IF");
+        if if_exists {
+            self.advance();
+            self.expect_word("EXISTS")?;
+        }
+        let name = self.parse_identifier("table name after DROP TABLE")?;
+
+        Ok(AstNode::DropTable { name, if_exists })
+    }
+
+    // ON table (field)
+    fn parse_index_target(&mut self) -> Result<(String, String), Error> {
+        self.expect_word("ON")?;
+        let table = self.parse_identifier("table name after ON")?;
+        self.expect(Token::LeftParen)?;
+        let field = self.parse_identifier("indexed field name")?;
+        self.expect(Token::RightParen)?;
+        Ok((table, field))
+    }
+
+    fn parse_delete(&mut self) -> Result<AstNode, Error> {
+        self.expect(Token::Delete)?;
+        self.expect(Token::From)?;
+
+        let table_name = match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                name
+            }
+            _ => {
+                return Err(Error::SyntaxError(
+                    "Expected table name after FROM".to_string(),
+                ))
+            }
+        };
+
+        let where_clause = if self.peek() == Some(&Token::Where) {
+            self.advance();
+            Some(Box::new(self.parse_expression()?))
+        } else {
+            None
+        };
+
+        Ok(AstNode::Delete {
+            from: table_name,
+            where_clause,
+        })
+    }
+
+    // Value expressions: terms joined by + and -
+    fn parse_arithmetic(&mut self) -> Result<AstNode, Error> {
+        let mut left = self.parse_term()?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Plus) => "+",
+                Some(Token::Minus) => "-",
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_term()?;
+            left = AstNode::BinaryOp {
+                left: Box::new(left),
+                operator: operator.to_string(),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    // Factors joined by * and /
+    fn parse_term(&mut self) -> Result<AstNode, Error> {
+        let mut left = self.parse_factor()?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Asterisk) => "*",
+                Some(Token::Slash) => "/",
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_factor()?;
+            left = AstNode::BinaryOp {
+                left: Box::new(left),
+                operator: operator.to_string(),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_factor(&mut self) -> Result<AstNode, Error> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                Ok(AstNode::Column(name))
+            }
+            Some(Token::LeftParen) => {
+                self.advance();
+                let expression = self.parse_arithmetic()?;
+                self.expect(Token::RightParen)?;
+                Ok(expression)
+            }
+            _ => Ok(AstNode::Literal(self.parse_literal()?)),
+        }
+    }
+
+    // Boolean expressions by precedence climbing, OR binds weaker than AND
+    fn parse_expression(&mut self) -> Result<AstNode, Error> {
+        self.parse_boolean(0)
+    }
+
+    fn parse_boolean(&mut self, min_precedence: u8) -> Result<AstNode, Error> {
+        let mut left = self.parse_boolean_operand()?;
+
+        while let Some((precedence, operator)) = self.peek().and_then(boolean_operator) {
+            if precedence < min_precedence {
+                break;
+            }
+            self.advance();
+            // Operators of the same precedence group to the left
+            let right = self.parse_boolean(precedence + 1)?;
+            left = AstNode::BinaryOp {
+                left: Box::new(left),
+                operator: operator.to_string(),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    // NOT, a parenthesized expression or a comparison
+    fn parse_boolean_operand(&mut self) -> Result<AstNode, Error> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.advance();
+                Ok(AstNode::Not(Box::new(self.parse_boolean_operand()?)))
+            }
+            Some(Token::LeftParen) => {
+                self.advance();
+                let expression = self.parse_expression()?;
+                self.expect(Token::RightParen)?;
+                Ok(expression)
+            }
+            _ => self.parse_comparison(),
+        }
+    }
+
+    // New method to handle basic comparisons
+    fn parse_comparison(&mut self) -> Result<AstNode, Error> {
+        let left = match self.peek() {
+            Some(Token::Identifier(_)) if self.peek_aggregate_function().is_some() => {
+                let function = self
+                    .peek_aggregate_function()
+                    .unwrap_or(AggregateFunction::Count);
+                let field = self.parse_aggregate_call(function)?;
+                AstNode::Aggregate { function, field }
+            }
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                AstNode::Column(name)
+            }
+            _ => {
+                return Err(Error::SyntaxError(
+                    "Expected identifier in WHERE clause".to_string(),
+                ))
+            }
+        };
+
+        if self.peek_word("IS") {
+            self.advance();
+            let negated = self.peek() == Some(&Token::Not);
+            if negated {
+                self.advance();
+            }
+            self.expect_word("NULL")?;
+            return Ok(negate(AstNode::IsNull(Box::new(left)), negated));
+        }
+
+        // IN, BETWEEN and LIKE may follow NOT, = and the other operators may not
+        let negated = self.peek() == Some(&Token::Not);
+        if negated {
+            self.advance();
+        }
+        if self.peek_word("IN") {
+            self.advance();
+            self.expect(Token::LeftParen)?;
+            let mut values = vec![self.parse_literal()?];
+            while self.peek() == Some(&Token::Comma) {
+                self.advance();
+                values.push(self.parse_literal()?);
+            }
+            self.expect(Token::RightParen)?;
+            let node = AstNode::In {
+                left: Box::new(left),
+                values,
+            };
+            return Ok(negate(node, negated));
+        }
+        if self.peek_word("BETWEEN") {
+            self.advance();
+            let low = self.parse_literal()?;
+            self.expect(Token::And)?;
+            let high = self.parse_literal()?;
+            let bound = |operator: &str, value: LiteralValue| AstNode::BinaryOp {
+                left: Box::new(left.clone()),
+                operator: operator.to_string(),
+                right: Box::new(AstNode::Literal(value)),
+            };
+            let node = AstNode::BinaryOp {
+                left: Box::new(bound(">=", low)),
+                operator: "AND".to_string(),
+                right: Box::new(bound("<=", high)),
+            };
+            return Ok(negate(node, negated));
+        }
+        if self.peek_word("LIKE") {
+            self.advance();
+            let LiteralValue::String(pattern) = self.parse_literal()? else {
+                return Err(Error::SyntaxError(
+                    "Expected string pattern after LIKE".to_string(),
+                ));
+            };
+            let node = AstNode::Like {
+                left: Box::new(left),
+                pattern,
+            };
+            return Ok(negate(node, negated));
+        }
+        if negated {
+            return Err(Error::SyntaxError(format!(
+                "Expected IN, BETWEEN or LIKE after NOT, got {:?}",
+                self.peek()
+            )));
+        }
+
+        let operator = match self.peek() {
+            Some(Token::GreaterThan) => {
+                self.advance();
+                ">".to_string()
+            }
+            Some(Token::GreaterThanEqual) => {
+                self.advance();
+                ">=".to_string()
+            }
+            Some(Token::LessThan) => {
+                self.advance();
+                "<".to_string()
+            }
+            Some(Token::LessThanEqual) => {
+                self.advance();
+                "<=".to_string()
+            }
+            Some(Token::Equal) => {
+                self.advance();
+                "=".to_string()
+            }
+            Some(Token::NotEqual) => {
+                self.advance();
+                "!=".to_string()
+            }
+            val => {
+                return Err(Error::SyntaxError(format!(
+                    "Expected comparison operator, got {:?}",
+                    val
+                )))
+            }
+        };
+
+        let right = match self.peek() {
+            Some(Token::Number(_)) | Some(Token::String(_)) | Some(Token::Minus) => {
+                AstNode::Literal(self.parse_literal()?)
+            }
+            _ => {
+                return Err(Error::SyntaxError(
+                    "Expected literal value after operator".to_string(),
+                ))
+            }
+        };
+
+        Ok(AstNode::BinaryOp {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        })
+    }
+
+    // Text the lexer could not read fails the query instead of being skipped
+    fn check_tokens(&self) -> Result<(), Error> {
+        match self.tokens.iter().find_map(|token| match token {
+            Token::Invalid(reason) => Some(reason),
+            _ => None,
+        }) {
+            Some(reason) => Err(Error::SyntaxError(reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<AstNode, Error> {
+        match self.peek() {
+            Some(Token::Insert) => self.parse_insert(),
+            Some(Token::Update) => self.parse_update(),
+            Some(Token::Delete) => self.parse_delete(),
+            Some(Token::Create) => self.parse_create(),
+            Some(Token::Drop) => self.parse_drop(),
+            _ => self.parse_select(),
+        }
+    }
+
+    // Statements separated by semicolons, so whole files can be executed at once
+    pub fn parse_script(&mut self) -> Result<Vec<AstNode>, Error> {
+        self.check_tokens()?;
+        let mut statements = Vec::new();
+        loop {
+            while self.peek() == Some(&Token::Semicolon) {
+                self.advance();
+            }
+            if matches!(self.peek(), None | Some(Token::Eof)) {
+                break;
+            }
+
+            statements.push(self.parse()?);
+
+            match self.peek() {
+                Some(Token::Semicolon) | Some(Token::Eof) | None => {}
+                val => {
+                    return Err(Error::SyntaxError(format!(
+                        "Expected end of statement, got {:?}",
+                        val
+                    )))
+                }
+            }
+        }
+        Ok(statements)
+    }
+}
 
 // // Code Generator: Transforms AST into WitchVM instructions
 pub struct CodeGenerator {
     pub instructions: Vec<Instruction>,
+    // Result columns in SELECT order, None when they depend on the documents (SELECT *)
+    pub columns: Option<Vec<String>>,
 }
 
 impl CodeGenerator {
     pub fn new() -> Self {
         CodeGenerator {
             instructions: Vec::new(),
+            columns: Some(Vec::new()),
         }
     }
 
@@ -594,166 +1443,485 @@ impl CodeGenerator {
             AstNode::Select {
                 fields,
                 from,
+                alias,
+                joins,
                 where_clause,
+                group_by,
+                having,
                 order_by,
                 limit,
                 offset,
             } => {
-                // Load the table
-                self.emit(Instruction::UseStorage { name: from.clone() });
-
-                // Handle WHERE clause if present
-                let full_scan_predicate = match where_clause {
-                    Some(condition) => self.generate_full_scan_condition(condition)?,
-                    None => Box::new(|_: String| true),
-                };
-
-                let index_scan_predicate = match where_clause {
-                    Some(condition) => self.generate_index_scan_condition(condition)?,
-                    None => Box::new(|_: String| true),
-                };
+                if fields.is_empty() {
+                    return Err(Error::SyntaxError(
+                        "Syntax error: No fields in SELECT".to_string(),
+                    ));
+                }
+                if fields.len() > 1
+                    && fields
+                        .iter()
+                        .any(|f| matches!(f, FieldExpression::AllColumns))
+                {
+                    return Err(Error::SyntaxError(
+                        "Syntax error: Multiple fields not allowed in SELECT with *".to_string(),
+                    ));
+                }
+                if let Some(condition) = where_clause {
+                    if contains_aggregate(condition) {
+                        return Err(Error::SyntaxError(
+                            "Syntax error: Aggregate functions are not allowed in WHERE"
+                                .to_string(),
+                        ));
+                    }
+                }
 
-                let (string_fields_values, number_fields_values) = match where_clause {
-                    Some(condition) => {
-                        let lefts_rights = self.get_where_lefts_rights(condition)?;
-                        let mut string_fields_values = Vec::new();
-                        let mut number_fields_values = Vec::new();
-                        for (field, value) in lefts_rights {
-                            match value {
-                                LiteralValue::String(s) => string_fields_values.push((field, s)),
-                                LiteralValue::Number(n) => number_fields_values.push((field, n)),
-                            }
+                if alias.is_some() || !joins.is_empty() {
+                    let aliases = self.emit_joined_scan(from, alias, joins, where_clause)?;
+                    for field in fields {
+                        match field {
+                            FieldExpression::Field(name)
+                            | FieldExpression::Aggregate {
+                                field: Some(name), ..
+                            } => check_qualified(name, &aliases)?,
+                            _ => {}
                         }
-                        (string_fields_values, number_fields_values)
                     }
-                    None => (Vec::new(), Vec::new()),
-                };
-
-                self.emit(Instruction::Scan {
-                    index_filter: Filter::Condition(index_scan_predicate),
-                    full_scan_filter: Filter::Condition(full_scan_predicate),
-                    string_fields_values,
-                    number_fields_values,
-                });
-
-                if fields.len() == 1 {
-                    match &fields[0] {
-                        FieldExpression::AllColumns => {}
-                        FieldExpression::Field(name) => {
-                            let name = name.clone();
-                            let instruction = Instruction::MapOutput {
-                                map_fn: Box::new(move |json_string: String| {
-                                    // take Json field with name and return Json with only that field
-                                    let json: serde_json::Value =
-                                        match serde_json::from_str(&json_string) {
-                                            Ok(json) => json,
-                                            Err(e) => {
-                                                println!("Error parsing JSON: {}", e);
-                                                let new_json = serde_json::Map::new();
-                                                return serde_json::Value::Object(new_json)
-                                                    .to_string();
-                                            }
-                                        };
-                                    let value = match json.get(&name) {
-                                        Some(value) => value.clone(),
-                                        None => serde_json::Value::Null,
-                                    };
-
-                                    // create new json with only the value
-                                    let mut new_json = serde_json::Map::new();
-                                    new_json.insert(name.clone(), value.clone());
-                                    serde_json::Value::Object(new_json).to_string()
-                                }),
+                    for name in group_by {
+                        check_qualified(name, &aliases)?;
+                    }
+                } else {
+                    match from {
+                        TableSource::Storage(name) => {
+                            // Load the table
+                            self.emit(Instruction::UseStorage { name: name.clone() });
+                            self.emit_scan(where_clause)?;
+                        }
+                        TableSource::Neighbours { key, rel_type } => {
+                            let full_scan_predicate = match where_clause {
+                                Some(condition) => self.generate_full_scan_condition(condition)?,
+                                None => Box::new(|_: &serde_json::Value| true),
                             };
-                            self.emit(instruction);
+                            self.emit(Instruction::ScanNeighbours {
+                                key: key.clone(),
+                                rel_type: rel_type.clone(),
+                                full_scan_filter: Filter::Condition(full_scan_predicate),
+                            });
                         }
                     }
-                } else if fields.len() > 1 {
+                }
+
+                let mut aggregates = Vec::new();
+                for field in fields {
+                    if let FieldExpression::Aggregate {
+                        function,
+                        field,
+                        alias,
+                    } = field
+                    {
+                        aggregates.push(Aggregate {
+                            function: *function,
+                            field: field.clone(),
+                            name: alias
+                                .clone()
+                                .unwrap_or_else(|| aggregate_name(*function, field)),
+                        });
+                    }
+                }
+
+                let grouped = !aggregates.is_empty() || !group_by.is_empty();
+                if grouped {
                     for field in fields {
-                        // check if all fields are not "*"
                         match field {
                             FieldExpression::AllColumns => {
                                 return Err(Error::SyntaxError(
-                                    "Syntax error: Multiple fields not allowed in SELECT with *"
+                                    "Syntax error: * is not allowed with aggregate functions"
                                         .to_string(),
-                                ));
+                                ))
                             }
-                            FieldExpression::Field(_) => {}
+                            FieldExpression::Field(name) if !group_by.contains(name) => {
+                                return Err(Error::SyntaxError(format!(
+                                    "Syntax error: '{}' must appear in GROUP BY or be used in an aggregate function",
+                                    name
+                                )))
+                            }
+                            _ => {}
                         }
                     }
 
-                    let fields = fields.clone();
-                    let instruction = Instruction::MapOutput {
-                        map_fn: Box::new(move |json_string: String| {
-                            let json: serde_json::Value = match serde_json::from_str(&json_string) {
-                                Ok(json) => json,
-                                Err(e) => {
-                                    println!("Error parsing JSON: {}", e);
-                                    let new_json = serde_json::Map::new();
-                                    return serde_json::Value::Object(new_json).to_string();
-                                }
-                            };
+                    // Aggregates used only in HAVING are computed under their own name
+                    if let Some(condition) = having {
+                        for (function, field) in collect_aggregates(condition) {
+                            let name = aggregate_name(function, &field);
+                            if !aggregates.iter().any(|a| a.name == name) {
+                                aggregates.push(Aggregate {
+                                    function,
+                                    field,
+                                    name,
+                                });
+                            }
+                        }
+                    }
 
-                            let mut new_json = serde_json::Map::new();
+                    let having = match having {
+                        Some(condition) => Some(Filter::Condition(
+                            self.generate_full_scan_condition(condition)?,
+                        )),
+                        None => None,
+                    };
+                    self.emit(Instruction::Aggregate {
+                        group_by: group_by.clone(),
+                        aggregates,
+                        having,
+                    });
+                } else if having.is_some() {
+                    return Err(Error::SyntaxError(
+                        "Syntax error: HAVING requires GROUP BY or aggregate functions".to_string(),
+                    ));
+                }
 
-                            for field in fields.clone() {
-                                match field {
-                                    FieldExpression::AllColumns => {
-                                        // will never happen, but
-                                        let new_json = serde_json::Map::new();
-                                        return serde_json::Value::Object(new_json).to_string();
-                                    }
-                                    FieldExpression::Field(name) => {
-                                        let value = match json.get(&name) {
-                                            Some(value) => value.clone(),
-                                            None => serde_json::Value::Null,
-                                        };
+                // Sorting and paging happen before the projection,
+                // so ORDER BY can use columns that are not selected
+                if let Some(order_by) = order_by {
+                    let instruction = Instruction::SortOutput {
+                        field: order_by.clone(),
+                    };
+                    self.emit(instruction);
+                }
 
-                                        new_json.insert(name.clone(), value.clone());
-                                    }
-                                }
+                if let Some(offset_val) = offset {
+                    self.emit(Instruction::SetOffset {
+                        count: *offset_val as u64,
+                    });
+                }
+
+                if let Some(limit_val) = limit {
+                    self.emit(Instruction::SetLimit {
+                        count: *limit_val as u64,
+                    });
+                }
+
+                if !matches!(fields[0], FieldExpression::AllColumns) {
+                    let names = fields
+                        .iter()
+                        .map(|field| match field {
+                            FieldExpression::AllColumns => String::new(),
+                            FieldExpression::Field(name) => name.clone(),
+                            FieldExpression::Aggregate {
+                                function,
+                                field,
+                                alias,
+                            } => alias
+                                .clone()
+                                .unwrap_or_else(|| aggregate_name(*function, field)),
+                        })
+                        .collect::<Vec<String>>();
+                    self.columns = Some(names.clone());
+
+                    let instruction = Instruction::MapOutput {
+                        map_fn: Box::new(move |json: &serde_json::Value| {
+                            // take Json fields with names and return Json with only those fields
+                            let mut new_json = serde_json::Map::new();
+                            for name in names.iter() {
+                                let value = match column_value(json, name) {
+                                    Some(value) => value.clone(),
+                                    None => serde_json::Value::Null,
+                                };
+                                new_json.insert(name.clone(), value);
                             }
 
-                            serde_json::Value::Object(new_json).to_string()
+                            serde_json::Value::Object(new_json)
                         }),
                     };
                     self.emit(instruction);
                 } else {
+                    self.columns = None;
+                }
+
+                Ok(())
+            }
+            AstNode::Insert {
+                into,
+                columns,
+                rows,
+            } => {
+                // KEY is not a document field, it names the key of the row
+                let Some(key_position) = columns.iter().position(|c| c.eq_ignore_ascii_case("KEY"))
+                else {
                     return Err(Error::SyntaxError(
-                        "Syntax error: No fields in SELECT".to_string(),
+                        "Syntax error: INSERT requires a KEY column".to_string(),
                     ));
-                }
+                };
+
+                self.emit(Instruction::UseStorage { name: into.clone() });
+
+                for row in rows {
+                    if row.len() != columns.len() {
+                        return Err(Error::SyntaxError(format!(
+                            "Syntax error: INSERT has {} columns but {} values",
+                            columns.len(),
+                            row.len()
+                        )));
+                    }
 
-                if let Some(order_by) = order_by {
-                    let instruction = Instruction::SortOutput {
-                        field: order_by.clone(),
+                    let key = match &row[key_position] {
+                        LiteralValue::String(s) => s.clone(),
+                        LiteralValue::Number(n) => {
+                            literal_to_json(&LiteralValue::Number(*n)).to_string()
+                        }
                     };
-                    self.emit(instruction);
-                }
 
-                if let Some(offset_val) = offset {
-                    self.emit(Instruction::SetOffset {
-                        count: *offset_val as u64,
+                    let mut document = serde_json::Value::Object(serde_json::Map::new());
+                    for (i, (column, value)) in columns.iter().zip(row.iter()).enumerate() {
+                        if i != key_position {
+                            set_column_value(&mut document, column, literal_to_json(value))?;
+                        }
+                    }
+
+                    self.emit(Instruction::Set {
+                        key,
+                        value: document,
                     });
                 }
 
-                if let Some(limit_val) = limit {
-                    self.emit(Instruction::SetLimit {
-                        count: *limit_val as u64,
-                    });
+                self.emit_affected_rows();
+                Ok(())
+            }
+            AstNode::Update {
+                table,
+                assignments,
+                where_clause,
+            } => {
+                for (column, value) in assignments {
+                    if column.eq_ignore_ascii_case("KEY") {
+                        return Err(Error::SyntaxError(
+                            "Syntax error: KEY can not be changed with UPDATE".to_string(),
+                        ));
+                    }
+                    validate_value_expression(value)?;
                 }
 
+                self.emit(Instruction::UseStorage {
+                    name: table.clone(),
+                });
+                self.emit_scan(where_clause)?;
+
+                let assignments = assignments.clone();
+                self.emit(Instruction::UpdateScanned {
+                    update_fn: Box::new(move |json: &serde_json::Value| {
+                        if !json.is_object() {
+                            return Err(Error::ExecutionError(
+                                "Can not UPDATE a value that is not a JSON object".to_string(),
+                            ));
+                        }
+
+                        // Every assignment sees the document as it was before the UPDATE
+                        let mut new_document = json.clone();
+                        for (column, value) in assignments.iter() {
+                            set_column_value(
+                                &mut new_document,
+                                column,
+                                evaluate_value(value, json)?,
+                            )?;
+                        }
+                        Ok(new_document)
+                    }),
+                });
+                self.emit_affected_rows();
+                Ok(())
+            }
+            AstNode::Delete { from, where_clause } => {
+                self.emit(Instruction::UseStorage { name: from.clone() });
+                self.emit_scan(where_clause)?;
+                self.emit(Instruction::DeleteScanned);
+                self.emit_affected_rows();
+                Ok(())
+            }
+            AstNode::CreateTable { name } => {
+                self.emit(Instruction::CreateStorage { name: name.clone() });
+                Ok(())
+            }
+            AstNode::DropTable { name, if_exists } => {
+                self.emit(Instruction::DropStorage {
+                    name: name.clone(),
+                    if_exists: *if_exists,
+                });
+                Ok(())
+            }
+            AstNode::CreateIndex {
+                table,
+                field,
+                field_type,
+                unique,
+                ordered,
+            } => {
+                self.emit(Instruction::UseStorage {
+                    name: table.clone(),
+                });
+                self.emit(Instruction::CreateIndex {
+                    field_name: field.clone(),
+                    field_type: field_type.clone(),
+                    unique: *unique,
+                    ordered: *ordered,
+                });
+                Ok(())
+            }
+            AstNode::DropIndex { table, field } => {
+                self.emit(Instruction::UseStorage {
+                    name: table.clone(),
+                });
+                self.emit(Instruction::DropIndex {
+                    field_name: field.clone(),
+                });
                 Ok(())
             }
             _ => Err(Error::SyntaxError("unhandled case".to_string())), // Other node types would be handled here
         }
     }
 
-    fn generate_full_scan_condition(
+    fn emit_affected_rows(&mut self) {
+        self.emit(Instruction::OutputAffectedRows);
+        self.columns = Some(vec!["rows_affected".to_string()]);
+    }
+
+    // Handle WHERE clause if present and emit the scan over the current storage
+    fn emit_scan(&mut self, where_clause: &Option<Box<AstNode>>) -> Result<(), Error> {
+        // Handle WHERE clause if present
+        let full_scan_predicate = match where_clause {
+            Some(condition) => self.generate_full_scan_condition(condition)?,
+            None => Box::new(|_: &serde_json::Value| true),
+        };
+
+        let index_plan = where_clause
+            .as_ref()
+            .and_then(|condition| generate_index_plan(condition));
+
+        self.emit(Instruction::Scan {
+            index_plan,
+            full_scan_filter: Filter::Condition(full_scan_predicate),
+        });
+
+        Ok(())
+    }
+
+    // Rows of a query with aliases hold the document of every table under its alias,
+    // e.g. {"o": {...}, "u": {...}}, so WHERE runs after the joins. Returns the aliases
+    fn emit_joined_scan(
         &mut self,
-        condition: &AstNode,
-    ) -> Result<Box<dyn Fn(String) -> bool + 'static>, Error> {
+        from: &TableSource,
+        alias: &Option<String>,
+        joins: &[Join],
+        where_clause: &Option<Box<AstNode>>,
+    ) -> Result<Vec<String>, Error> {
+        let base_alias = alias.clone().unwrap_or_else(|| match from {
+            TableSource::Storage(name) => name.clone(),
+            TableSource::Neighbours { .. } => "neighbors".to_string(),
+        });
+        let mut aliases = vec![base_alias.clone()];
+        for join in joins {
+            if aliases.contains(&join.alias) {
+                return Err(Error::SyntaxError(format!(
+                    "Syntax error: table alias '{}' is used more than once",
+                    join.alias
+                )));
+            }
+            check_qualified(&join.left, &aliases)?;
+            aliases.push(join.alias.clone());
+        }
+        if let Some(condition) = where_clause {
+            for column in collect_columns(condition) {
+                check_qualified(&column, &aliases)?;
+            }
+        }
+
+        match from {
+            TableSource::Storage(name) => {
+                self.emit(Instruction::UseStorage { name: name.clone() });
+                // Conditions on the first table may still narrow its rows down with an index
+                let index_plan = where_clause
+                    .as_ref()
+                    .and_then(|condition| generate_index_plan(&unqualify(condition, &base_alias)));
+                self.emit(Instruction::Scan {
+                    index_plan,
+                    full_scan_filter: Filter::Condition(Box::new(|_: &serde_json::Value| true)),
+                });
+            }
+            TableSource::Neighbours { key, rel_type } => {
+                self.emit(Instruction::ScanNeighbours {
+                    key: key.clone(),
+                    rel_type: rel_type.clone(),
+                    full_scan_filter: Filter::Condition(Box::new(|_: &serde_json::Value| true)),
+                });
+            }
+        }
+        self.emit(Instruction::MapOutput {
+            map_fn: Box::new(move |json: &serde_json::Value| {
+                let mut row = serde_json::Map::new();
+                row.insert(base_alias.clone(), json.clone());
+                serde_json::Value::Object(row)
+            }),
+        });
+
+        for join in joins {
+            let TableSource::Storage(_) = from else {
+                return Err(Error::SyntaxError(
+                    "Syntax error: neighbors() can not be joined".to_string(),
+                ));
+            };
+            self.emit(Instruction::Join {
+                storage_name: join.table.clone(),
+                alias: join.alias.clone(),
+                kind: join.kind,
+                left_column: join.left.clone(),
+                right_field: join.right.clone(),
+            });
+        }
+
+        if let Some(condition) = where_clause {
+            let predicate = self.generate_full_scan_condition(condition)?;
+            self.emit(Instruction::FilterOutput {
+                filter: Filter::Condition(predicate),
+            });
+        }
+        Ok(aliases)
+    }
+
+    fn generate_full_scan_condition(&mut self, condition: &AstNode) -> Result<Predicate, Error> {
         match condition {
+            AstNode::Not(operand) => {
+                let predicate = self.generate_full_scan_condition(operand)?;
+                Ok(Box::new(move |value: &serde_json::Value| !predicate(value)))
+            }
+            AstNode::In { left, values } => {
+                let col = condition_column(left)?;
+                let values = values.clone();
+                Ok(Box::new(move |json: &serde_json::Value| {
+                    let Some(field) = column_value(json, &col) else {
+                        return false;
+                    };
+                    values.iter().any(|value| match value {
+                        LiteralValue::Number(n) => field.as_f64() == Some(*n),
+                        LiteralValue::String(s) => field.as_str() == Some(s.as_str()),
+                    })
+                }))
+            }
+            AstNode::Like { left, pattern } => {
+                let col = condition_column(left)?;
+                let pattern: Vec<char> = pattern.chars().collect();
+                Ok(Box::new(
+                    move |json: &serde_json::Value| match column_value(json, &col)
+                        .and_then(|v| v.as_str())
+                    {
+                        Some(field) => like_matches(field, &pattern),
+                        None => false,
+                    },
+                ))
+            }
+            AstNode::IsNull(left) => {
+                let col = condition_column(left)?;
+                Ok(Box::new(move |json: &serde_json::Value| {
+                    column_value(json, &col).is_none_or(|v| v.is_null())
+                }))
+            }
             AstNode::BinaryOp {
                 left,
                 operator,
@@ -763,62 +1931,47 @@ impl CodeGenerator {
                     "AND" => {
                         let left_pred = self.generate_full_scan_condition(left)?;
                         let right_pred = self.generate_full_scan_condition(right)?;
-                        Ok(Box::new(move |value: String| {
-                            left_pred(value.clone()) && right_pred(value)
+                        Ok(Box::new(move |value: &serde_json::Value| {
+                            left_pred(value) && right_pred(value)
                         }))
                     }
                     "OR" => {
                         let left_pred = self.generate_full_scan_condition(left)?;
                         let right_pred = self.generate_full_scan_condition(right)?;
-                        Ok(Box::new(move |value: String| {
-                            left_pred(value.clone()) || right_pred(value)
+                        Ok(Box::new(move |value: &serde_json::Value| {
+                            left_pred(value) || right_pred(value)
                         }))
                     }
                     _ => {
                         // Handle comparison operators
-                        match (&**left, &**right) {
-                            (AstNode::Column(col), AstNode::Literal(lit)) => {
-                                let col = col.clone();
+                        let col = condition_column(left)?;
+                        match &**right {
+                            AstNode::Literal(lit) => {
                                 match lit {
                                     LiteralValue::Number(n) => {
                                         let operator = operator.clone();
-                                        let n = n.clone();
-                                        Ok(Box::new(move |value: String| {
-                                            if let Ok(json) =
-                                                serde_json::from_str::<serde_json::Value>(&value)
+                                        let n = *n;
+                                        Ok(Box::new(move |json: &serde_json::Value| {
+                                            match column_value(json, &col).and_then(|v| v.as_f64())
                                             {
-                                                if let Some(field) =
-                                                    json.get(col.as_str()).and_then(|v| v.as_i64())
-                                                {
-                                                    return num_cond(
-                                                        field,
-                                                        operator.clone(),
-                                                        n as i64,
-                                                    );
-                                                }
+                                                Some(field) => num_cond(field, operator.clone(), n),
+                                                None => false,
                                             }
-                                            false
                                         }))
                                     }
                                     LiteralValue::String(s) => {
                                         let operator = operator.clone();
                                         let s: String = s.clone();
-                                        Ok(Box::new(move |value: String| {
-                                            if let Ok(json) =
-                                                serde_json::from_str::<serde_json::Value>(&value)
+                                        Ok(Box::new(move |json: &serde_json::Value| {
+                                            match column_value(json, &col).and_then(|v| v.as_str())
                                             {
-                                                if let Some(name) = json
-                                                    .get(col.clone().as_str())
-                                                    .and_then(|v| v.as_str())
-                                                {
-                                                    return str_cond(
-                                                        name.to_string(),
-                                                        operator.clone(),
-                                                        s.clone(),
-                                                    );
-                                                }
+                                                Some(name) => str_cond(
+                                                    name.to_string(),
+                                                    operator.clone(),
+                                                    s.clone(),
+                                                ),
+                                                None => false,
                                             }
-                                            false
                                         }))
                                     }
                                 }
@@ -831,133 +1984,277 @@ impl CodeGenerator {
             _ => Err(Error::SyntaxError("Unhandled Condition".to_string())),
         }
     }
+}
 
-    fn generate_index_scan_condition(
-        &mut self,
-        condition: &AstNode,
-    ) -> Result<Box<dyn Fn(String) -> bool + 'static>, Error> {
-        match condition {
-            AstNode::BinaryOp {
-                left,
-                operator,
-                right,
-            } => {
-                match operator.as_str() {
-                    "AND" => {
-                        let left_pred = self.generate_index_scan_condition(left)?;
-                        let right_pred = self.generate_index_scan_condition(right)?;
-                        Ok(Box::new(move |value: String| {
-                            left_pred(value.clone()) && right_pred(value)
-                        }))
-                    }
-                    "OR" => {
-                        let left_pred = self.generate_index_scan_condition(left)?;
-                        let right_pred = self.generate_index_scan_condition(right)?;
-                        Ok(Box::new(move |value: String| {
-                            left_pred(value.clone()) || right_pred(value)
-                        }))
-                    }
-                    _ => {
-                        // Handle comparison operators
-                        match (&**left, &**right) {
-                            (AstNode::Column(_), AstNode::Literal(lit)) => {
-                                match lit {
-                                    LiteralValue::Number(n) => {
-                                        let operator = operator.clone();
-                                        let n = n.clone();
-                                        Ok(Box::new(move |value: String| {
-                                            // if let Ok(json) =
-                                            //     serde_json::from_str::<serde_json::Value>(&value)
-                                            // {
-                                            //     if let Some(field) =
-                                            //         json.get(col.as_str()).and_then(|v| v.as_i64())
-                                            //     {
-                                            //         return num_cond(
-                                            //             field,
-                                            //             operator.clone(),
-                                            //             n as i64,
-                                            //         );
-                                            //     }
-                                            // }
-                                            // false
-                                            match value.parse::<i64>() {
-                                                Ok(num_val) => {
-                                                    return num_cond(
-                                                        num_val,
-                                                        operator.clone(),
-                                                        n as i64,
-                                                    );
-                                                }
-                                                Err(_) => {
-                                                    return false;
-                                                }
-                                            }
-                                            // return num_cond(
-                                            //     field,
-                                            //     operator.clone(),
-                                            //     n as i64,
-                                            // );
-                                        }))
-                                    }
-                                    LiteralValue::String(s) => {
-                                        let operator = operator.clone();
-                                        let s: String = s.clone();
-                                        Ok(Box::new(move |value: String| {
-                                            return str_cond(
-                                                value.to_string(),
-                                                operator.clone(),
-                                                s.clone(),
-                                            );
-                                        }))
-                                    }
-                                }
-                            }
-                            _ => Err(Error::SyntaxError("Unhandled Condition".to_string())),
-                        }
-                    }
-                }
-            }
-            _ => Err(Error::SyntaxError("Unhandled Condition".to_string())),
+fn negate(node: AstNode, negated: bool) -> AstNode {
+    if negated {
+        AstNode::Not(Box::new(node))
+    } else {
+        node
+    }
+}
+
+// The column a condition looks at
+fn condition_column(left: &AstNode) -> Result<String, Error> {
+    match left {
+        AstNode::Column(col) => Ok(col.clone()),
+        // HAVING compares the aggregated column
+        AstNode::Aggregate { function, field } => Ok(aggregate_name(*function, field)),
+        _ => Err(Error::SyntaxError("Unhandled Condition".to_string())),
+    }
+}
+
+fn boolean_operator(token: &Token) -> Option<(u8, &'static str)> {
+    match token {
+        Token::Or => Some((1, "OR")),
+        Token::And => Some((2, "AND")),
+        _ => None,
+    }
+}
+
+// Comparisons of a column with a literal, IN and LIKE 'prefix%' may be answered by an index.
+// NOT and IS NULL are left to the filter, documents without the field match them but are in no index
+fn generate_index_plan(condition: &AstNode) -> Option<IndexPlan> {
+    let (left, operator, right) = match condition {
+        AstNode::BinaryOp {
+            left,
+            operator,
+            right,
+        } => (left, operator, right),
+        AstNode::In { left, values } => {
+            let AstNode::Column(field) = &**left else {
+                return None;
+            };
+            return Some(IndexPlan::In {
+                field: field.clone(),
+                values: values.iter().map(literal_to_field_value).collect(),
+            });
+        }
+        AstNode::Like { left, pattern } => {
+            let AstNode::Column(field) = &**left else {
+                return None;
+            };
+            let prefix: String = pattern
+                .chars()
+                .take_while(|c| *c != '%' && *c != '_')
+                .collect();
+            return if prefix.len() == pattern.len() {
+                Some(IndexPlan::Compare {
+                    field: field.clone(),
+                    operator: "=".to_string(),
+                    value: FieldValue::String(prefix),
+                })
+            } else if prefix.is_empty() {
+                None
+            } else {
+                Some(IndexPlan::Prefix {
+                    field: field.clone(),
+                    prefix,
+                })
+            };
+        }
+        _ => return None,
+    };
+
+    match operator.as_str() {
+        "AND" => match (generate_index_plan(left), generate_index_plan(right)) {
+            (Some(left), Some(right)) => Some(IndexPlan::And(Box::new(left), Box::new(right))),
+            (Some(plan), None) | (None, Some(plan)) => Some(plan),
+            (None, None) => None,
+        },
+        "OR" => Some(IndexPlan::Or(
+            Box::new(generate_index_plan(left)?),
+            Box::new(generate_index_plan(right)?),
+        )),
+        _ => match (&**left, &**right) {
+            (AstNode::Column(field), AstNode::Literal(lit)) => Some(IndexPlan::Compare {
+                field: field.clone(),
+                operator: operator.clone(),
+                value: literal_to_field_value(lit),
+            }),
+            _ => None,
+        },
+    }
+}
+
+// With several tables every column names its table, as in u.name
+fn check_qualified(column: &str, aliases: &[String]) -> Result<(), Error> {
+    match column.split_once('.') {
+        Some((table, _)) if aliases.iter().any(|alias| alias == table) => Ok(()),
+        _ => Err(Error::SyntaxError(format!(
+            "Syntax error: column '{}' must start with one of the table aliases {}",
+            column,
+            aliases.join(", ")
+        ))),
+    }
+}
+
+fn collect_columns(node: &AstNode) -> Vec<String> {
+    match node {
+        AstNode::Column(name) => vec![name.clone()],
+        AstNode::Not(operand) | AstNode::IsNull(operand) => collect_columns(operand),
+        AstNode::In { left, .. } | AstNode::Like { left, .. } => collect_columns(left),
+        AstNode::BinaryOp { left, right, .. } => {
+            let mut result = collect_columns(left);
+            result.extend(collect_columns(right));
+            result
         }
+        _ => Vec::new(),
     }
+}
 
-    fn get_where_lefts_rights(
-        &self,
-        condition: &AstNode,
-    ) -> Result<Vec<(String, LiteralValue)>, Error> {
-        let mut result: Vec<(String, LiteralValue)> = Vec::new();
-        match condition {
-            AstNode::BinaryOp {
-                left,
-                operator,
-                right,
-            } => match operator.as_str() {
-                "OR" => {
-                    result.extend(self.get_where_lefts_rights(left)?);
-                    result.extend(self.get_where_lefts_rights(right)?);
-                }
-                "AND" => {}
-                _ => {
-                    let field = match &**left {
-                        AstNode::Column(name) => name.clone(),
-                        _ => return Err(Error::SyntaxError("Unhandled Condition".to_string())),
-                    };
+// The condition with the columns of one table named like in its documents
+fn unqualify(node: &AstNode, alias: &str) -> AstNode {
+    match node {
+        AstNode::Column(name) => match name
+            .strip_prefix(alias)
+            .and_then(|name| name.strip_prefix('.'))
+        {
+            Some(field) => AstNode::Column(field.to_string()),
+            None => node.clone(),
+        },
+        AstNode::BinaryOp {
+            left,
+            operator,
+            right,
+        } => AstNode::BinaryOp {
+            left: Box::new(unqualify(left, alias)),
+            operator: operator.clone(),
+            right: Box::new(unqualify(right, alias)),
+        },
+        AstNode::Not(operand) => AstNode::Not(Box::new(unqualify(operand, alias))),
+        AstNode::In { left, values } => AstNode::In {
+            left: Box::new(unqualify(left, alias)),
+            values: values.clone(),
+        },
+        AstNode::Like { left, pattern } => AstNode::Like {
+            left: Box::new(unqualify(left, alias)),
+            pattern: pattern.clone(),
+        },
+        AstNode::IsNull(operand) => AstNode::IsNull(Box::new(unqualify(operand, alias))),
+        _ => node.clone(),
+    }
+}
+
+// Column name of an aggregate without alias, e.g. COUNT(*) or AVG(age)
+fn aggregate_name(function: AggregateFunction, field: &Option<String>) -> String {
+    format!(
+        "{}({})",
+        function.name(),
+        field.clone().unwrap_or("*".to_string())
+    )
+}
+
+fn contains_aggregate(node: &AstNode) -> bool {
+    !collect_aggregates(node).is_empty()
+}
+
+fn collect_aggregates(node: &AstNode) -> Vec<(AggregateFunction, Option<String>)> {
+    match node {
+        AstNode::Aggregate { function, field } => vec![(*function, field.clone())],
+        AstNode::Not(operand) | AstNode::IsNull(operand) => collect_aggregates(operand),
+        AstNode::In { left, .. } | AstNode::Like { left, .. } => collect_aggregates(left),
+        AstNode::BinaryOp { left, right, .. } => {
+            let mut result = collect_aggregates(left);
+            result.extend(collect_aggregates(right));
+            result
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn literal_to_field_value(value: &LiteralValue) -> FieldValue {
+    match value {
+        LiteralValue::Number(n) => FieldValue::Number(*n),
+        LiteralValue::String(s) => FieldValue::String(s.clone()),
+    }
+}
+
+fn literal_to_json(value: &LiteralValue) -> serde_json::Value {
+    match value {
+        LiteralValue::Number(n) => number_to_json(*n),
+        LiteralValue::String(s) => serde_json::Value::String(s.clone()),
+    }
+}
 
-                    let field_value = match &**right {
-                        AstNode::Literal(val) => val.clone(),
-                        _ => return Err(Error::SyntaxError("Unhandled Condition".to_string())),
+// Only columns, literals and arithmetic are allowed on the right side of SET
+fn validate_value_expression(node: &AstNode) -> Result<(), Error> {
+    match node {
+        AstNode::Column(_) | AstNode::Literal(_) => Ok(()),
+        AstNode::BinaryOp {
+            left,
+            operator,
+            right,
+        } => match operator.as_str() {
+            "+" | "-" | "*" | "/" => {
+                validate_value_expression(left)?;
+                validate_value_expression(right)
+            }
+            _ => Err(Error::SyntaxError(format!(
+                "Syntax error: unexpected operator '{}' in value expression",
+                operator
+            ))),
+        },
+        _ => Err(Error::SyntaxError(
+            "Syntax error: unexpected value expression".to_string(),
+        )),
+    }
+}
+
+fn evaluate_value(node: &AstNode, json: &serde_json::Value) -> Result<serde_json::Value, Error> {
+    match node {
+        AstNode::Column(name) => Ok(column_value(json, name).cloned().unwrap_or_default()),
+        AstNode::Literal(lit) => Ok(literal_to_json(lit)),
+        AstNode::BinaryOp {
+            left,
+            operator,
+            right,
+        } => {
+            let left = evaluate_value(left, json)?;
+            let right = evaluate_value(right, json)?;
+            match (&left, &right) {
+                // Arithmetic on a missing value gives a missing value
+                (serde_json::Value::Null, _) | (_, serde_json::Value::Null) => {
+                    Ok(serde_json::Value::Null)
+                }
+                (serde_json::Value::Number(l), serde_json::Value::Number(r)) => {
+                    let l = l.as_f64().unwrap_or_default();
+                    let r = r.as_f64().unwrap_or_default();
+                    let result = match operator.as_str() {
+                        "+" => l + r,
+                        "-" => l - r,
+                        "*" => l * r,
+                        "/" => {
+                            if r == 0.0 {
+                                return Err(Error::ExecutionError("Division by zero".to_string()));
+                            }
+                            l / r
+                        }
+                        _ => {
+                            return Err(Error::ExecutionError(format!(
+                                "Unknown operator '{}'",
+                                operator
+                            )))
+                        }
                     };
-                    result.push((field, field_value));
+                    Ok(number_to_json(result))
                 }
-            },
-            _ => return Err(Error::SyntaxError("Unhandled Condition".to_string())),
+                (serde_json::Value::String(l), serde_json::Value::String(r)) if operator == "+" => {
+                    Ok(serde_json::Value::String(format!("{}{}", l, r)))
+                }
+                _ => Err(Error::ExecutionError(format!(
+                    "Can not apply '{}' to {} and {}",
+                    operator, left, right
+                ))),
+            }
         }
-
-        Ok(result)
+        _ => Err(Error::ExecutionError(
+            "Unexpected value expression".to_string(),
+        )),
     }
 }
 
-fn num_cond(field: i64, operator: String, value: i64) -> bool {
+fn num_cond(field: f64, operator: String, value: f64) -> bool {
     match operator.as_str() {
         ">" => field > value,
         ">=" => field >= value,
@@ -971,13 +2268,915 @@ fn num_cond(field: i64, operator: String, value: i64) -> bool {
 
 fn str_cond(field: String, operator: String, value: String) -> bool {
     match operator.as_str() {
+        ">" => field > value,
+        ">=" => field >= value,
+        "<" => field < value,
+        "<=" => field <= value,
         "=" => field == value,
         "!=" => field != value,
         _ => false,
     }
 }
 
+// % matches any run of characters, _ exactly one, everything else itself
+fn like_matches(value: &str, pattern: &[char]) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let (mut v, mut p) = (0, 0);
+    // Position after the last % and the value position it is matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                backtrack = Some((p, v));
+            }
+            Some(c) if *c == '_' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((after_percent, matched)) => {
+                    p = after_percent;
+                    v = matched + 1;
+                    backtrack = Some((after_percent, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+#[cfg(test)]
 mod tests {
-    #[allow(unused_imports)]
     use super::*;
+    use crate::graph::store::GraphStore;
+    use crate::kv::database::Database;
+    use crate::kv::witchvm_kv::{ExplainStep, WitchVMKV};
+
+    fn run_query(database: &mut Database, query: &str) -> Result<Vec<serde_json::Value>, Error> {
+        let tokens = Lexer::new(query).tokenize();
+        let statements = Parser::new(tokens).parse_script()?;
+        let mut vm = WitchVMKV::new();
+        for ast in statements.iter() {
+            let mut generator = CodeGenerator::new();
+            generator.generate(ast)?;
+            vm.execute(database, &GraphStore::new(), generator.instructions)?;
+        }
+        Ok(vm.into_output())
+    }
+
+    fn test_database() -> Database {
+        let mut database = Database::new();
+        database.create_storage("main".to_string()).unwrap();
+        database
+    }
+
+    #[test]
+    fn test_insert_multiple_rows() {
+        let mut database = test_database();
+        let output = run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name, age) VALUES ('person7', 'Ann', 31), ('person8', 'O''Neil', 40.5)",
+        )
+        .unwrap();
+        assert_eq!(output, vec![serde_json::json!({ "rows_affected": 2 })]);
+
+        let ann: serde_json::Value = serde_json::from_str(
+            &database
+                .get("main".to_string(), "person7".to_string())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(ann["name"], "Ann");
+        assert_eq!(ann["age"].as_i64(), Some(31));
+
+        let oneil: serde_json::Value = serde_json::from_str(
+            &database
+                .get("main".to_string(), "person8".to_string())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(oneil["name"], "O'Neil");
+        assert_eq!(oneil["age"].as_f64(), Some(40.5));
+    }
+
+    #[test]
+    fn test_insert_rolls_back_on_unique_violation() {
+        let mut database = test_database();
+        database
+            .create_index(
+                "main".to_string(),
+                "name".to_string(),
+                FieldType::String,
+                true,
+                false,
+            )
+            .unwrap();
+
+        let result = run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name) VALUES ('a', 'Ann'), ('b', 'Bob'), ('c', 'Ann')",
+        );
+        assert!(result.is_err());
+        assert!(database.get("main".to_string(), "a".to_string()).is_err());
+        assert!(database.get("main".to_string(), "b".to_string()).is_err());
+
+        // The unique index must not keep entries from the failed statement
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name) VALUES ('d', 'Ann')",
+        )
+        .unwrap();
+    }
+
+    fn get_json(database: &Database, key: &str) -> serde_json::Value {
+        serde_json::from_str(&database.get("main".to_string(), key.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_unreadable_text_is_a_syntax_error() {
+        let mut database = test_database();
+        run_query(&mut database, "INSERT INTO main (KEY, a) VALUES ('k1', 1)").unwrap();
+
+        // Unknown characters fail the query instead of ending it early
+        for (query, message) in [
+            (
+                "SELECT * FROM main WHERE a = 1 # junk",
+                "Unexpected character '#' at line 1, column 32",
+            ),
+            (
+                "SELECT * FROM main;\nDELETE FROM main WHERE a = 1 @",
+                "Unexpected character '@' at line 2, column 30",
+            ),
+            (
+                "SELECT * FROM main WHERE a = 'open",
+                "Unterminated string at line 1, column 30",
+            ),
+        ] {
+            assert!(matches!(
+                run_query(&mut database, query),
+                Err(Error::SyntaxError(e)) if e == message
+            ));
+        }
+        assert!(database.get("main".to_string(), "k1".to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_update_with_where() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name, age) VALUES ('a', 'John', 30), ('b', 'Jane', 25), ('c', 'John', 41)",
+        )
+        .unwrap();
+
+        let output = run_query(
+            &mut database,
+            "UPDATE main SET age = age + 1, status = 'x', title = 'Mr ' + name WHERE name = 'John'",
+        )
+        .unwrap();
+        assert_eq!(output, vec![serde_json::json!({ "rows_affected": 2 })]);
+
+        let a = get_json(&database, "a");
+        assert_eq!(a["age"].as_i64(), Some(31));
+        assert_eq!(a["status"], "x");
+        assert_eq!(a["title"], "Mr John");
+        assert_eq!(get_json(&database, "c")["age"].as_i64(), Some(42));
+
+        let b = get_json(&database, "b");
+        assert_eq!(b["age"].as_i64(), Some(25));
+        assert!(b.get("status").is_none());
+    }
+
+    #[test]
+    fn test_update_without_matches() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, age) VALUES ('a', 10)",
+        )
+        .unwrap();
+        let output = run_query(
+            &mut database,
+            "UPDATE main SET age = (age - 4) * 2 WHERE age > 100",
+        )
+        .unwrap();
+        assert_eq!(output, vec![serde_json::json!({ "rows_affected": 0 })]);
+
+        run_query(&mut database, "UPDATE main SET age = (age - 4) * 2").unwrap();
+        assert_eq!(get_json(&database, "a")["age"].as_i64(), Some(12));
+    }
+
+    #[test]
+    fn test_delete_with_where() {
+        let mut database = test_database();
+        database
+            .create_index(
+                "main".to_string(),
+                "name".to_string(),
+                FieldType::String,
+                true,
+                false,
+            )
+            .unwrap();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name, age) VALUES ('a', 'John', 85), ('b', 'Jane', 25), ('c', 'Jim', 90)",
+        )
+        .unwrap();
+
+        let output = run_query(&mut database, "DELETE FROM main WHERE age > 80").unwrap();
+        assert_eq!(output, vec![serde_json::json!({ "rows_affected": 2 })]);
+        assert!(database.get("main".to_string(), "a".to_string()).is_err());
+        assert!(database.get("main".to_string(), "c".to_string()).is_err());
+        assert_eq!(get_json(&database, "b")["name"], "Jane");
+
+        // Index entries of deleted rows are gone, so the names can be reused
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name) VALUES ('d', 'John')",
+        )
+        .unwrap();
+
+        let output = run_query(&mut database, "DELETE FROM main").unwrap();
+        assert_eq!(output, vec![serde_json::json!({ "rows_affected": 2 })]);
+    }
+
+    #[test]
+    fn test_ddl_script() {
+        let mut database = Database::new();
+        run_query(
+            &mut database,
+            "CREATE TABLE users;
+            CREATE UNIQUE INDEX ON users (email) TYPE string;
+            CREATE TABLE users;
+            INSERT INTO users (KEY, email, type) VALUES ('u1', 'a@b.c', 'admin');",
+        )
+        .unwrap_err();
+        // The script stopped at the second CREATE TABLE
+        let storage = database.get_storage("users".to_string()).unwrap();
+        assert!(storage.indexes.index_exists(&"email".to_string()));
+        assert!(storage.data.is_empty());
+
+        run_query(
+            &mut database,
+            "INSERT INTO users (KEY, email, type) VALUES ('u1', 'a@b.c', 'admin');
+            DROP INDEX ON users (email);",
+        )
+        .unwrap();
+        let storage = database.get_storage("users".to_string()).unwrap();
+        assert!(!storage.indexes.index_exists(&"email".to_string()));
+        assert!(run_query(&mut database, "DROP INDEX ON users (email)").is_err());
+
+        assert!(run_query(&mut database, "CREATE TABLE users").is_err());
+        run_query(&mut database, "DROP TABLE users").unwrap();
+        assert!(run_query(&mut database, "DROP TABLE users").is_err());
+        run_query(&mut database, "DROP TABLE IF EXISTS users").unwrap();
+        assert!(database.get_storage("users".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_group_by_having() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, gender, age) VALUES
+                ('a', 'male', 30), ('b', 'female', 25), ('c', 'male', 41),
+                ('d', 'female', 100), ('e', 'female', 20);
+            INSERT INTO main (KEY, gender) VALUES ('f', 'male')",
+        )
+        .unwrap();
+
+        let rows = run_query(
+                &mut database,
+                "SELECT gender, COUNT(*), COUNT(age) AS aged, SUM(age), AVG(age) AS avg_age, MIN(age), MAX(age)
+                FROM main GROUP BY gender HAVING COUNT(*) > 1 ORDER BY avg_age",
+            )
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["gender"], "male");
+        assert_eq!(rows[0]["COUNT(*)"].as_i64(), Some(3));
+        assert_eq!(rows[0]["aged"].as_i64(), Some(2));
+        assert_eq!(rows[0]["SUM(age)"].as_i64(), Some(71));
+        assert_eq!(rows[0]["avg_age"].as_f64(), Some(35.5));
+        assert_eq!(rows[0]["MIN(age)"].as_i64(), Some(30));
+        assert_eq!(rows[0]["MAX(age)"].as_i64(), Some(41));
+        assert_eq!(rows[1]["gender"], "female");
+        assert_eq!(rows[1]["MAX(age)"].as_i64(), Some(100));
+
+        // HAVING can use aggregates that are not selected
+        let rows = run_query(
+            &mut database,
+            "SELECT gender FROM main GROUP BY gender HAVING MAX(age) >= 100",
+        )
+        .unwrap();
+        assert_eq!(rows, vec![serde_json::json!({"gender": "female"})]);
+    }
+
+    #[test]
+    fn test_aggregate_without_group_by() {
+        let mut database = test_database();
+        let rows = run_query(&mut database, "SELECT COUNT(*), AVG(age) FROM main").unwrap();
+        assert_eq!(
+            rows,
+            vec![serde_json::json!({"COUNT(*)": 0, "AVG(age)": null})]
+        );
+
+        assert!(run_query(&mut database, "SELECT name, COUNT(*) FROM main").is_err());
+        assert!(run_query(&mut database, "SELECT * FROM main WHERE COUNT(*) > 1").is_err());
+    }
+
+    #[test]
+    fn test_insert_requires_key_column() {
+        let mut database = test_database();
+        assert!(run_query(&mut database, "INSERT INTO main (name) VALUES ('Ann')").is_err());
+    }
+
+    fn keys(rows: Vec<serde_json::Value>) -> Vec<String> {
+        rows.iter()
+            .map(|row| row["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_boolean_precedence() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name, a, b, c) VALUES
+                ('1', 'x', 1, 0, 0), ('2', 'y', 0, 2, 0), ('3', 'z', 0, 2, 3), ('4', 'w', 1, 2, 3)",
+        )
+        .unwrap();
+        let names = |database: &mut Database, condition: &str| {
+            keys(
+                run_query(
+                    database,
+                    &format!("SELECT name FROM main WHERE {} ORDER BY name", condition),
+                )
+                .unwrap(),
+            )
+        };
+
+        // AND binds stronger than OR
+        assert_eq!(
+            names(&mut database, "a = 1 OR b = 2 AND c = 3"),
+            vec!["w", "x", "z"]
+        );
+        assert_eq!(
+            names(&mut database, "b = 2 AND c = 3 OR a = 1"),
+            vec!["w", "x", "z"]
+        );
+        assert_eq!(
+            names(&mut database, "(a = 1 OR b = 2) AND c = 3"),
+            vec!["w", "z"]
+        );
+        assert_eq!(
+            names(&mut database, "NOT (a = 1 OR b = 2)"),
+            Vec::<String>::new()
+        );
+        assert_eq!(names(&mut database, "NOT a = 1 AND NOT (c = 3)"), vec!["y"]);
+        assert_eq!(
+            names(&mut database, "((a = 0)) AND NOT NOT b = 2"),
+            vec!["y", "z"]
+        );
+
+        for condition in ["(a = 1", "a = 1)", "NOT", "a = 1 AND OR b = 2"] {
+            assert!(
+                run_query(
+                    &mut database,
+                    &format!("SELECT name FROM main WHERE {}", condition)
+                )
+                .is_err(),
+                "{}",
+                condition
+            );
+        }
+
+        // Index plans follow the same grouping, NOT is only checked by the filter
+        run_query(&mut database, "CREATE INDEX ON main (c) TYPE number").unwrap();
+        assert!(used_index(&explain(
+            &mut database,
+            "SELECT name FROM main WHERE (a = 1 OR b = 2) AND c = 3"
+        )));
+        assert!(!used_index(&explain(
+            &mut database,
+            "SELECT name FROM main WHERE a = 1 OR b = 2 AND c = 3"
+        )));
+        assert!(!used_index(&explain(
+            &mut database,
+            "SELECT name FROM main WHERE NOT c = 3"
+        )));
+        assert_eq!(names(&mut database, "NOT c = 3"), vec!["x", "y"]);
+    }
+
+    fn explain(database: &mut Database, query: &str) -> Vec<ExplainStep> {
+        let tokens = Lexer::new(query).tokenize();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut generator = CodeGenerator::new();
+        generator.generate(&ast).unwrap();
+        WitchVMKV::new()
+            .execute(database, &GraphStore::new(), generator.instructions)
+            .unwrap()
+    }
+
+    fn used_index(steps: &[ExplainStep]) -> bool {
+        steps
+            .iter()
+            .any(|step| matches!(step, ExplainStep::IndexScan { .. }))
+    }
+
+    #[test]
+    fn test_number_range_index() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name, age) VALUES
+                ('a', 'Ann', 30), ('b', 'Bob', 35), ('c', 'Cid', 35),
+                ('d', 'Dan', 39.5), ('e', 'Eve', 40), ('f', 'Fay', 'old');
+            CREATE INDEX ON main (age) TYPE number",
+        )
+        .unwrap();
+
+        let query = "SELECT name FROM main WHERE age > 30 AND age < 40 ORDER BY name";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(
+            run_query(&mut database, query).unwrap(),
+            vec![
+                serde_json::json!({"name": "Bob"}),
+                serde_json::json!({"name": "Cid"}),
+                serde_json::json!({"name": "Dan"}),
+            ]
+        );
+
+        let query = "SELECT name FROM main WHERE age = 35 OR age >= 40 ORDER BY name";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(run_query(&mut database, query).unwrap().len(), 3);
+
+        // The other side of AND is checked on the rows the index found
+        let query = "SELECT name FROM main WHERE age <= 35 AND name = 'Bob'";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(run_query(&mut database, query).unwrap().len(), 1);
+
+        // OR with an unindexed side has to look at every row
+        let query = "SELECT name FROM main WHERE age <= 30 OR name = 'Eve'";
+        assert!(!used_index(&explain(&mut database, query)));
+        assert_eq!(run_query(&mut database, query).unwrap().len(), 2);
+
+        // Index entries follow updates and deletes
+        run_query(
+            &mut database,
+            "UPDATE main SET age = 50 WHERE name = 'Bob'; DELETE FROM main WHERE name = 'Cid'",
+        )
+        .unwrap();
+        assert_eq!(
+            run_query(&mut database, "SELECT name FROM main WHERE age = 35").unwrap(),
+            Vec::<serde_json::Value>::new()
+        );
+    }
+
+    fn index_keys(database: &Database, field: &str, value: &str) -> Vec<String> {
+        database
+            .get_storage("main".to_string())
+            .unwrap()
+            .indexes
+            .get_index(&field.to_string())
+            .unwrap()
+            .search("=", &FieldValue::String(value.to_string()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_index_maintenance() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "CREATE UNIQUE INDEX ON main (email) TYPE string;
+            CREATE UNIQUE INDEX ON main (nick) TYPE string;
+            CREATE INDEX ON main (city) TYPE string;
+            INSERT INTO main (KEY, email, nick, city) VALUES
+                ('a', 'a@x', 'ann', 'Oslo'), ('b', 'b@x', 'bob', 'Oslo')",
+        )
+        .unwrap();
+
+        // Old values leave the index
+        run_query(
+            &mut database,
+            "UPDATE main SET city = 'Rome' WHERE nick = 'ann'",
+        )
+        .unwrap();
+        assert_eq!(index_keys(&database, "city", "Oslo"), vec!["b"]);
+        assert_eq!(index_keys(&database, "city", "Rome"), vec!["a"]);
+
+        // Saving a document with its own unique value is fine
+        run_query(
+            &mut database,
+            "UPDATE main SET email = 'a@x', city = 'Oslo' WHERE nick = 'ann'",
+        )
+        .unwrap();
+
+        // A failed write does not touch any index
+        assert!(run_query(
+            &mut database,
+            "UPDATE main SET email = 'b@x', nick = 'anna' WHERE nick = 'ann'"
+        )
+        .is_err());
+        assert!(index_keys(&database, "nick", "anna").is_empty());
+        assert_eq!(index_keys(&database, "nick", "ann"), vec!["a"]);
+        assert!(run_query(
+            &mut database,
+            "INSERT INTO main (KEY, email, nick) VALUES ('c', 'a@x', 'cid')"
+        )
+        .is_err());
+        assert!(index_keys(&database, "nick", "cid").is_empty());
+        assert_eq!(index_keys(&database, "email", "a@x"), vec!["a"]);
+
+        run_query(&mut database, "DELETE FROM main WHERE nick = 'bob'").unwrap();
+        assert!(index_keys(&database, "email", "b@x").is_empty());
+        assert_eq!(index_keys(&database, "city", "Oslo"), vec!["a"]);
+
+        // Duplicates make a unique index fail to build
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, city) VALUES ('d', 'Oslo'); DROP INDEX ON main (city)",
+        )
+        .unwrap();
+        assert!(run_query(
+            &mut database,
+            "CREATE UNIQUE INDEX ON main (city) TYPE string"
+        )
+        .is_err());
+    }
+
+    fn join_database() -> Database {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "CREATE TABLE users; CREATE TABLE orders;
+            INSERT INTO users (KEY, name, id) VALUES ('u1', 'Ann', 1), ('u2', 'Bob', 2), ('u3', 'Cid', 3);
+            INSERT INTO orders (KEY, user_key, user_id, total) VALUES
+                ('o1', 'u1', 1, 10), ('o2', 'u1', 1, 25), ('o3', 'u2', 2, 5), ('o4', 'u9', 9, 7)",
+        )
+        .unwrap();
+        database
+    }
+
+    #[test]
+    fn test_join() {
+        let mut database = join_database();
+        let rows = run_query(
+            &mut database,
+            "SELECT o.total, u.name FROM orders o JOIN users AS u ON u.KEY = o.user_key
+            WHERE o.total > 5 ORDER BY o.total",
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                serde_json::json!({"o.total": 10, "u.name": "Ann"}),
+                serde_json::json!({"o.total": 25, "u.name": "Ann"}),
+            ]
+        );
+
+        // Orders without a user are dropped by JOIN, users without orders are kept by LEFT JOIN
+        let rows = run_query(
+            &mut database,
+            "SELECT u.name, o.total FROM users u LEFT OUTER JOIN orders o ON u.id = o.user_id
+            ORDER BY o.total",
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                serde_json::json!({"u.name": "Cid", "o.total": null}),
+                serde_json::json!({"u.name": "Bob", "o.total": 5}),
+                serde_json::json!({"u.name": "Ann", "o.total": 10}),
+                serde_json::json!({"u.name": "Ann", "o.total": 25}),
+            ]
+        );
+
+        let rows = run_query(
+            &mut database,
+            "SELECT u.name, COUNT(*) AS orders, SUM(o.total) FROM orders o INNER JOIN users u
+            ON o.user_id = u.id WHERE u.name = 'Ann' OR o.total < 10 GROUP BY u.name ORDER BY orders",
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                serde_json::json!({"u.name": "Bob", "orders": 1, "SUM(o.total)": 5}),
+                serde_json::json!({"u.name": "Ann", "orders": 2, "SUM(o.total)": 35}),
+            ]
+        );
+
+        let rows = run_query(
+            &mut database,
+            "SELECT * FROM orders o JOIN users u ON o.user_key = u.KEY WHERE o.total = 5",
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![serde_json::json!({
+                "o": {"user_key": "u2", "user_id": 2, "total": 5},
+                "u": {"name": "Bob", "id": 2},
+            })]
+        );
+
+        for query in [
+            "SELECT name FROM orders o JOIN users u ON o.user_id = u.id",
+            "SELECT o.total FROM orders o JOIN users u ON o.user_id = u.id WHERE total > 1",
+            "SELECT o.total FROM orders o JOIN users o ON o.user_id = o.id",
+            "SELECT o.total FROM orders o JOIN users u ON o.user_id = o.total",
+        ] {
+            assert!(run_query(&mut database, query).is_err(), "{}", query);
+        }
+    }
+
+    fn used_join(steps: &[ExplainStep]) -> &'static str {
+        for step in steps {
+            match step {
+                ExplainStep::HashJoin { .. } => return "hash",
+                ExplainStep::IndexJoin { .. } => return "index",
+                _ => {}
+            }
+        }
+        "none"
+    }
+
+    #[test]
+    fn test_join_strategies() {
+        let mut database = join_database();
+        let query = "SELECT o.total FROM orders o JOIN users u ON o.user_id = u.id";
+        assert_eq!(used_join(&explain(&mut database, query)), "hash");
+        assert_eq!(
+            used_join(&explain(
+                &mut database,
+                "SELECT o.total FROM orders o JOIN users u ON o.user_key = u.KEY"
+            )),
+            "index"
+        );
+
+        run_query(
+            &mut database,
+            "CREATE UNIQUE INDEX ON users (id) TYPE number",
+        )
+        .unwrap();
+        assert_eq!(used_join(&explain(&mut database, query)), "index");
+        let mut totals: Vec<i64> = run_query(&mut database, query)
+            .unwrap()
+            .iter()
+            .map(|row| row["o.total"].as_i64().unwrap())
+            .collect();
+        totals.sort();
+        assert_eq!(totals, vec![5, 10, 25]);
+
+        // Conditions on the first table use its indexes before the join
+        run_query(&mut database, "CREATE INDEX ON orders (total) TYPE number").unwrap();
+        let steps = explain(
+            &mut database,
+            "SELECT o.total FROM orders o JOIN users u ON o.user_id = u.id WHERE o.total >= 10 AND u.name = 'Ann'",
+        );
+        assert!(used_index(&steps));
+    }
+
+    #[test]
+    fn test_in_between_like_is_null() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name, status, age) VALUES
+                ('1', 'Joe', 'a', 20), ('2', 'John', 'b', 25), ('3', 'Ann', 'c', 30), ('4', 'Jo', 'a', 31);
+            INSERT INTO main (KEY, name) VALUES ('5', 'Bo_b')",
+        )
+        .unwrap();
+        let names = |database: &mut Database, condition: &str| {
+            keys(
+                run_query(
+                    database,
+                    &format!("SELECT name FROM main WHERE {} ORDER BY name", condition),
+                )
+                .unwrap(),
+            )
+        };
+
+        assert_eq!(
+            names(&mut database, "status IN ('a', 'b')"),
+            vec!["Jo", "Joe", "John"]
+        );
+        assert_eq!(names(&mut database, "age IN (25, 30)"), vec!["Ann", "John"]);
+        assert_eq!(
+            names(&mut database, "status NOT IN ('a', 'b')"),
+            vec!["Ann", "Bo_b"]
+        );
+        assert_eq!(
+            names(&mut database, "age BETWEEN 25 AND 30"),
+            vec!["Ann", "John"]
+        );
+        assert_eq!(
+            names(&mut database, "age NOT BETWEEN 25 AND 30 AND age > 0"),
+            vec!["Jo", "Joe"]
+        );
+        assert_eq!(
+            names(&mut database, "name LIKE 'Jo%'"),
+            vec!["Jo", "Joe", "John"]
+        );
+        assert_eq!(names(&mut database, "name LIKE 'J_%n'"), vec!["John"]);
+        assert_eq!(names(&mut database, "name LIKE '%o_'"), vec!["Joe"]);
+        assert_eq!(names(&mut database, "name NOT LIKE '%o%'"), vec!["Ann"]);
+        assert_eq!(names(&mut database, "status IS NULL"), vec!["Bo_b"]);
+        assert_eq!(
+            names(&mut database, "age IS NOT NULL AND name > 'Jo'"),
+            vec!["Joe", "John"]
+        );
+
+        for condition in [
+            "status IN ()",
+            "age BETWEEN 1",
+            "name LIKE 5",
+            "age NOT = 1",
+            "status IS 'a'",
+        ] {
+            assert!(
+                run_query(
+                    &mut database,
+                    &format!("SELECT name FROM main WHERE {}", condition)
+                )
+                .is_err(),
+                "{}",
+                condition
+            );
+        }
+    }
+
+    #[test]
+    fn test_in_and_prefix_index() {
+        let mut database = test_database();
+        run_query(
+            &mut database,
+            "INSERT INTO main (KEY, name, status) VALUES
+                ('1', 'Joe', 'a'), ('2', 'John', 'b'), ('3', 'Ann', 'c'), ('4', 'Jon', 'a');
+            CREATE INDEX ON main (status)",
+        )
+        .unwrap();
+
+        let query = "SELECT name FROM main WHERE status IN ('a', 'b', 'a') ORDER BY name";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(
+            keys(run_query(&mut database, query).unwrap()),
+            vec!["Joe", "John", "Jon"]
+        );
+
+        // Hash indexes only answer =
+        run_query(&mut database, "CREATE INDEX ON main (name) TYPE string").unwrap();
+        assert!(!used_index(&explain(
+            &mut database,
+            "SELECT name FROM main WHERE name LIKE 'Jo%'"
+        )));
+        assert!(used_index(&explain(
+            &mut database,
+            "SELECT name FROM main WHERE name LIKE 'John'"
+        )));
+
+        run_query(
+            &mut database,
+            "DROP INDEX ON main (name); CREATE INDEX ON main (name) TYPE string USING BTREE",
+        )
+        .unwrap();
+        let query = "SELECT name FROM main WHERE name LIKE 'Jo%n' ORDER BY name";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(
+            keys(run_query(&mut database, query).unwrap()),
+            vec!["John", "Jon"]
+        );
+        let query = "SELECT name FROM main WHERE name BETWEEN 'B' AND 'Joe' ORDER BY name";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(keys(run_query(&mut database, query).unwrap()), vec!["Joe"]);
+        assert!(!used_index(&explain(
+            &mut database,
+            "SELECT name FROM main WHERE name LIKE '%n'"
+        )));
+
+        // Ordered entries follow updates
+        run_query(
+            &mut database,
+            "UPDATE main SET name = 'Ann' WHERE name = 'Jon'",
+        )
+        .unwrap();
+        let query = "SELECT name FROM main WHERE name LIKE 'Jo%' ORDER BY name";
+        assert_eq!(
+            keys(run_query(&mut database, query).unwrap()),
+            vec!["Joe", "John"]
+        );
+
+        assert!(run_query(
+            &mut database,
+            "CREATE INDEX ON main (age) TYPE number USING HASH"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_nested_paths() {
+        let mut database = test_database();
+        for (key, document) in [
+            (
+                "1",
+                serde_json::json!({"name": "Ann", "tags": ["vip", "new"], "stats": {"score": 7},
+                    "profile": {"address": {"city": "Oslo"}}}),
+            ),
+            (
+                "2",
+                serde_json::json!({"name": "Bob", "tags": ["vip"], "stats": {"score": 3},
+                    "profile": {"address": {"city": "Bergen"}}}),
+            ),
+            (
+                "3",
+                serde_json::json!({"name": "Cid", "tags": [], "profile": {}, "a.b": 1}),
+            ),
+        ] {
+            database
+                .insert_document("main".to_string(), key.to_string(), document)
+                .unwrap();
+        }
+
+        assert_eq!(
+            run_query(
+                &mut database,
+                "SELECT profile.address.city FROM main WHERE tags[0] = 'vip' ORDER BY stats.score"
+            )
+            .unwrap(),
+            vec![
+                serde_json::json!({"profile.address.city": "Bergen"}),
+                serde_json::json!({"profile.address.city": "Oslo"}),
+            ]
+        );
+        let names = |database: &mut Database, condition: &str| {
+            keys(
+                run_query(
+                    database,
+                    &format!("SELECT name FROM main WHERE {} ORDER BY name", condition),
+                )
+                .unwrap(),
+            )
+        };
+        assert_eq!(names(&mut database, "tags[1] IS NOT NULL"), vec!["Ann"]);
+        assert_eq!(
+            names(&mut database, "profile.address.city IS NULL"),
+            vec!["Cid"]
+        );
+        // A key with a dot is found before the path
+        assert_eq!(names(&mut database, "a.b = 1"), vec!["Cid"]);
+
+        // Missing objects are created, array elements are replaced
+        run_query(
+            &mut database,
+            "UPDATE main SET profile.address.city = 'Rome', tags[0] = 'old' WHERE name = 'Ann';
+            UPDATE main SET profile.address.city = 'Rome' WHERE name = 'Cid';
+            INSERT INTO main (KEY, name, stats.score) VALUES ('4', 'Dan', 1)",
+        )
+        .unwrap();
+        assert_eq!(
+            get_json(&database, "1")["tags"],
+            serde_json::json!(["old", "new"])
+        );
+        assert_eq!(
+            get_json(&database, "4")["stats"],
+            serde_json::json!({"score": 1})
+        );
+        assert_eq!(
+            names(&mut database, "profile.address.city = 'Rome'"),
+            vec!["Ann", "Cid"]
+        );
+        assert!(run_query(&mut database, "UPDATE main SET tags[5] = 'x'").is_err());
+
+        // Indexes on paths follow the documents
+        database
+            .create_index(
+                "main".to_string(),
+                "profile.address.city".to_string(),
+                FieldType::String,
+                false,
+                false,
+            )
+            .unwrap();
+        let query = "SELECT name FROM main WHERE profile.address.city = 'Rome' ORDER BY name";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(
+            keys(run_query(&mut database, query).unwrap()),
+            vec!["Ann", "Cid"]
+        );
+        run_query(
+            &mut database,
+            "UPDATE main SET profile.address.city = 'Oslo' WHERE name = 'Cid';
+            CREATE INDEX ON main (stats.score) TYPE number",
+        )
+        .unwrap();
+        assert_eq!(keys(run_query(&mut database, query).unwrap()), vec!["Ann"]);
+        let query = "SELECT name FROM main WHERE stats.score > 2 ORDER BY name";
+        assert!(used_index(&explain(&mut database, query)));
+        assert_eq!(
+            keys(run_query(&mut database, query).unwrap()),
+            vec!["Ann", "Bob"]
+        );
+    }
 }
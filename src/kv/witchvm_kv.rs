@@ -45,13 +45,41 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::common::{FieldType, FieldValue};
+use crate::graph::store::GraphStore;
+use crate::kv::database::Database;
 use crate::kv::error::Error;
-use crate::kv::{database::Database, index::Index};
+use crate::kv::index::{IndexList, Key};
+use crate::kv::json_path::column_value;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use tokio::time::{Duration, Instant};
 
 pub struct WitchVMKV {
     instruction_storage_name: Option<String>,
-    output: Vec<String>,
+    output: Vec<serde_json::Value>,
+    // Keys of the rows found by the last Scan, in output order
+    scanned_keys: Vec<String>,
+    affected_rows: u64,
+    // Writes done by the current execution, undone if a later instruction fails
+    undo_log: Vec<UndoEntry>,
+}
+
+enum UndoEntry {
+    Inserted {
+        storage_name: String,
+        key: String,
+    },
+    Updated {
+        storage_name: String,
+        key: String,
+        old_value: serde_json::Value,
+    },
+    Deleted {
+        storage_name: String,
+        key: String,
+        old_value: serde_json::Value,
+    },
 }
 
 impl WitchVMKV {
@@ -59,213 +87,533 @@ impl WitchVMKV {
         Self {
             instruction_storage_name: None,
             output: Vec::new(),
+            scanned_keys: Vec::new(),
+            affected_rows: 0,
+            undo_log: Vec::new(),
         }
     }
 
-    pub fn into_output(self) -> Vec<String> {
+    pub fn into_output(self) -> Vec<serde_json::Value> {
         self.output
     }
 
     pub fn execute(
         &mut self,
         database: &mut Database,
+        graph: &GraphStore,
         instructions: Vec<Instruction>,
     ) -> Result<Vec<ExplainStep>, Error> {
         let mut explain = Vec::new();
         for instruction in instructions {
-            match instruction {
-                Instruction::Get { key } => {
-                    let Some(storage_name) = self.instruction_storage_name.clone() else {
-                        return Err(Error::ExecutionError(
-                            "No storage name provided".to_string(),
-                        ));
-                    };
+            if let Err(e) = self.execute_instruction(database, graph, instruction, &mut explain) {
+                return Err(match self.rollback(database) {
+                    Ok(()) => e,
+                    Err(rollback_error) => Error::ExecutionError(format!(
+                        "{}, rollback is incomplete: {}",
+                        e.into_string(),
+                        rollback_error
+                    )),
+                });
+            }
+        }
+        self.undo_log.clear();
+
+        Ok(explain)
+    }
 
-                    let value = database.get(storage_name, key.clone())?;
-                    self.output.push(value);
+    // Undoes every entry, even after one fails, and reports the ones that failed
+    fn rollback(&mut self, database: &mut Database) -> Result<(), String> {
+        let mut errors = Vec::new();
+        while let Some(entry) = self.undo_log.pop() {
+            match entry {
+                UndoEntry::Inserted { storage_name, key } => {
+                    if let Err(e) = database.delete(storage_name, key.clone()) {
+                        errors.push(format!(
+                            "insert of '{}' was not undone: {}",
+                            key,
+                            e.into_string()
+                        ));
+                    }
                 }
-                Instruction::Set { key, value } => {
-                    let Some(storage_name) = self.instruction_storage_name.clone() else {
-                        return Err(Error::ExecutionError(
-                            "No storage name provided".to_string(),
+                UndoEntry::Updated {
+                    storage_name,
+                    key,
+                    old_value,
+                } => {
+                    if let Err(e) = database.update_document(storage_name, key.clone(), old_value) {
+                        errors.push(format!(
+                            "update of '{}' was not undone: {}",
+                            key,
+                            e.into_string()
                         ));
-                    };
-                    database.insert(storage_name, key.clone(), value)?;
+                    }
                 }
-                Instruction::GetJsonField { key, field } => {
-                    let Some(storage_name) = self.instruction_storage_name.clone() else {
-                        return Err(Error::ExecutionError(
-                            "No storage name provided".to_string(),
+                UndoEntry::Deleted {
+                    storage_name,
+                    key,
+                    old_value,
+                } => {
+                    if let Err(e) = database.insert_document(storage_name, key.clone(), old_value) {
+                        errors.push(format!(
+                            "delete of '{}' was not undone: {}",
+                            key,
+                            e.into_string()
                         ));
-                    };
+                    }
+                }
+            }
+        }
+        self.affected_rows = 0;
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
 
-                    match database.get(storage_name, key.clone()) {
-                        Ok(value) => match serde_json::from_str::<serde_json::Value>(&value) {
-                            Ok(json_value) => match json_value.get(&field) {
-                                Some(field_value) => println!(
-                                    "JSON field '{}' in key '{}': {}",
-                                    field, key, field_value
-                                ),
-                                None => {
-                                    return Err(Error::ExecutionError(format!(
-                                        "JSON field '{}' not found in key '{}'",
-                                        field, key
-                                    )))
-                                }
-                            },
-                            Err(e) => {
-                                return Err(Error::ExecutionError(format!(
-                                    "Value for key '{}' is not valid JSON: {}",
-                                    key, e
-                                )))
-                            }
-                        },
-                        Err(e) => {
+    fn delete_key(
+        &mut self,
+        database: &mut Database,
+        storage_name: String,
+        key: String,
+    ) -> Result<(), Error> {
+        let old_value = database
+            .get_document(storage_name.clone(), key.clone())?
+            .clone();
+        database.delete(storage_name.clone(), key.clone())?;
+        self.undo_log.push(UndoEntry::Deleted {
+            storage_name,
+            key,
+            old_value,
+        });
+        self.affected_rows += 1;
+        Ok(())
+    }
+
+    fn execute_instruction(
+        &mut self,
+        database: &mut Database,
+        graph: &GraphStore,
+        instruction: Instruction,
+        explain: &mut Vec<ExplainStep>,
+    ) -> Result<(), Error> {
+        match instruction {
+            Instruction::Get { key } => {
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+
+                let value = database.get_document(storage_name, key.clone())?;
+                self.output.push(value.clone());
+            }
+            Instruction::Set { key, value } => {
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+                database.insert_document(storage_name.clone(), key.clone(), value)?;
+                self.undo_log.push(UndoEntry::Inserted {
+                    storage_name,
+                    key: key.clone(),
+                });
+                self.affected_rows += 1;
+                explain.push(ExplainStep::Set(key));
+            }
+            Instruction::OutputAffectedRows => {
+                self.output
+                    .push(serde_json::json!({ "rows_affected": self.affected_rows }));
+            }
+            Instruction::GetJsonField { key, field } => {
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+
+                match database.get_document(storage_name, key.clone()) {
+                    Ok(json_value) => match json_value.get(&field) {
+                        Some(field_value) => {
+                            println!("JSON field '{}' in key '{}': {}", field, key, field_value)
+                        }
+                        None => {
                             return Err(Error::ExecutionError(format!(
-                                "Key '{}' not found: {:?}",
-                                key, e
+                                "JSON field '{}' not found in key '{}'",
+                                field, key
                             )))
                         }
+                    },
+                    Err(e) => {
+                        return Err(Error::ExecutionError(format!(
+                            "Key '{}' not found: {:?}",
+                            key, e
+                        )))
                     }
                 }
-                Instruction::UseStorage { name } => {
-                    self.instruction_storage_name = Some(name.clone());
-                    explain.push(ExplainStep::SetStorage(name));
+            }
+            Instruction::UseStorage { name } => {
+                self.instruction_storage_name = Some(name.clone());
+                explain.push(ExplainStep::SetStorage(name));
+            }
+            Instruction::CreateStorage { name } => {
+                database.create_storage(name.clone())?;
+                explain.push(ExplainStep::CreateStorage(name));
+            }
+            Instruction::DropStorage { name, if_exists } => {
+                if !if_exists {
+                    database.get_storage(name.clone())?;
                 }
-                Instruction::Scan {
-                    index_filter,
-                    full_scan_filter,
-                    string_fields_values,
-                    number_fields_values,
-                } => {
-                    let start = Instant::now();
-                    let Some(storage_name) = self.instruction_storage_name.clone() else {
-                        return Err(Error::ExecutionError(
-                            "No storage name provided".to_string(),
-                        ));
-                    };
-
-                    let indexes = &database.get_storage(storage_name.clone())?.indexes;
+                database.delete_storage(name.clone())?;
+                explain.push(ExplainStep::DropStorage(name));
+            }
+            Instruction::CreateIndex {
+                field_name,
+                field_type,
+                unique,
+                ordered,
+            } => {
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+                database.create_index(
+                    storage_name,
+                    field_name.clone(),
+                    field_type,
+                    unique,
+                    ordered,
+                )?;
+                explain.push(ExplainStep::CreateIndex(field_name));
+            }
+            Instruction::DropIndex { field_name } => {
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+                database.drop_index(storage_name, field_name.clone())?;
+                explain.push(ExplainStep::DropIndex(field_name));
+            }
+            Instruction::Scan {
+                index_plan,
+                full_scan_filter,
+            } => {
+                let start = Instant::now();
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+                self.scanned_keys.clear();
 
-                    let mut start_index_search = false;
+                let storage = database.get_storage(storage_name)?;
+                let condition = full_scan_filter.condition();
+                let candidate_keys = index_plan
+                    .as_ref()
+                    .and_then(|plan| plan.candidate_keys(&storage.indexes));
 
-                    // Check if all fields are indexed
-                    // if not - then full scan
-                    // not sure it's correct
-                    let maybe_string_fields_indexed = string_fields_values
-                        .iter()
-                        .map(|x| indexes.index_exists(&x.0))
-                        .reduce(|x, y| x && y);
-
-                    // TODO: implement Number indexes not supported yet
-                    // let maybe_num_fields_indexed = number_fields_values
-                    //     .iter()
-                    //     .map(|x| indexes.index_exists(&x.0))
-                    //     .reduce(|x, y| x && y);
-
-                    if string_fields_values.len() > 0 && number_fields_values.len() > 0 {
-                        start_index_search = maybe_string_fields_indexed.unwrap_or(false)
-                        // TODO: implement Number indexes not supported yet
-                        // && maybe_num_fields_indexed.unwrap_or(false);
-                    } else if string_fields_values.len() > 0 && number_fields_values.len() == 0 {
-                        start_index_search = maybe_string_fields_indexed.unwrap_or(false);
-                    } else if string_fields_values.len() == 0 && number_fields_values.len() > 0 {
-                        // TODO: implement Number indexes not supported yet
-                        // all_fields_indexed = maybe_num_fields_indexed.unwrap_or(false);
+                // Index lookups only narrow the rows down, the filter still decides
+                if let Some(keys) = candidate_keys {
+                    for key in keys {
+                        if let Some(value) = storage.data.get(&key) {
+                            if condition(value) {
+                                self.scanned_keys.push(key);
+                                self.output.push(value.clone());
+                            }
+                        }
                     }
+                    explain.push(ExplainStep::IndexScan {
+                        time: start.elapsed(),
+                    });
+                } else {
+                    for (key, value) in storage.data.iter() {
+                        if condition(value) {
+                            self.scanned_keys.push(key.clone());
+                            self.output.push(value.clone());
+                        }
+                    }
+                    explain.push(ExplainStep::FullScan {
+                        time: start.elapsed(),
+                    });
+                }
+            }
+            Instruction::ScanNeighbours {
+                key,
+                rel_type,
+                full_scan_filter,
+            } => {
+                let start = Instant::now();
+                self.scanned_keys.clear();
 
-                    println!("All fields indexed: {}", start_index_search);
-
-                    if start_index_search {
-                        for (field, _) in string_fields_values.iter() {
-                            if let Some(index) = indexes.get_index(field) {
-                                match index {
-                                    Index::Hash(_) => {
-                                        let string_values = database.string_index_search(
-                                            storage_name.clone(),
-                                            index,
-                                            index_filter.condition(),
-                                        )?;
-                                        self.output.extend(string_values);
-                                    }
-                                    Index::HashUnique(_) => {
-                                        let string_values = database.string_index_search(
-                                            storage_name.clone(),
-                                            index,
-                                            index_filter.condition(),
-                                        )?;
-                                        self.output.extend(string_values);
-                                    }
-                                    Index::BTreeUnique(_) => {
-                                        return Err(Error::ExecutionError(
-                                            "BTreeUnique indexes are for numbers only".to_string(),
-                                        ));
-                                    }
-                                }
-                            }
+                let condition = full_scan_filter.condition();
+                for document in graph.document_neighbours(&key, &rel_type) {
+                    // A node can still reference a document that was deleted since
+                    let Ok(value) = database.get_document(document.storage_name, document.key)
+                    else {
+                        continue;
+                    };
+                    if condition(value) {
+                        self.output.push(value.clone());
+                    }
+                }
+                explain.push(ExplainStep::NeighbourScan {
+                    time: start.elapsed(),
+                });
+            }
+            Instruction::Join {
+                storage_name,
+                alias,
+                kind,
+                left_column,
+                right_field,
+            } => {
+                let start = Instant::now();
+                let storage = database.get_storage(storage_name)?;
+                let index = storage.indexes.get_index(&right_field);
+                let by_key = right_field == "KEY";
 
-                            // for number fields
-                            // TODO: implement
+                // Values the index can not hold, and all values without a key or an index,
+                // are looked up in the joined storage hashed on the column, built once when needed
+                let mut hash_table: Option<HashMap<String, Vec<&serde_json::Value>>> = None;
+                let mut output = Vec::new();
+                for row in std::mem::take(&mut self.output) {
+                    let value =
+                        column_value(&row, &left_column).unwrap_or(&serde_json::Value::Null);
+                    let index_value = match (index, value) {
+                        (Some(index), serde_json::Value::String(s))
+                            if index.field_type() == FieldType::String =>
+                        {
+                            Some(FieldValue::String(s.clone()))
                         }
-                        explain.push(ExplainStep::IndexScan {
-                            time: start.elapsed(),
-                        });
-                    } else {
-                        let storage = database.get_storage(storage_name)?;
-                        for (_, value) in storage.data.iter() {
-                            match full_scan_filter {
-                                Filter::Condition(ref condition) => {
-                                    if condition(value.clone()) {
-                                        self.output.push(value.clone());
-                                    }
+                        (Some(index), serde_json::Value::Number(n))
+                            if index.field_type() == FieldType::Number =>
+                        {
+                            n.as_f64().map(FieldValue::Number)
+                        }
+                        _ => None,
+                    };
+
+                    let matches: Vec<&serde_json::Value> = if by_key {
+                        document_key(value)
+                            .and_then(|key| storage.data.get(&key))
+                            .into_iter()
+                            .collect()
+                    } else if let (Some(index), Some(index_value)) = (index, index_value) {
+                        index
+                            .search("=", &index_value)
+                            .unwrap_or_default()
+                            .iter()
+                            .filter_map(|key| storage.data.get(key))
+                            .collect()
+                    } else if let Some(key) = join_key(value) {
+                        let hash_table = hash_table.get_or_insert_with(|| {
+                            let mut hash_table: HashMap<String, Vec<&serde_json::Value>> =
+                                HashMap::new();
+                            for document in storage.data.values() {
+                                if let Some(key) =
+                                    column_value(document, &right_field).and_then(join_key)
+                                {
+                                    hash_table.entry(key).or_default().push(document);
                                 }
                             }
-                        }
-                        explain.push(ExplainStep::FullScan {
-                            time: start.elapsed(),
+                            hash_table
                         });
+                        hash_table.get(&key).cloned().unwrap_or_default()
+                    } else {
+                        Vec::new()
+                    };
+
+                    if matches.is_empty() && kind == JoinKind::Left {
+                        output.push(with_table(&row, &alias, serde_json::Value::Null));
+                    }
+                    for document in matches {
+                        output.push(with_table(&row, &alias, document.clone()));
                     }
                 }
-                Instruction::MapOutput { map_fn } => {
-                    self.output = self
-                        .output
-                        .iter()
-                        .map(|value| map_fn(value.clone()))
-                        .collect();
-                    explain.push(ExplainStep::MapOutput);
-                }
-                Instruction::SortOutput { field } => {
-                    self.output.sort_by(|x, y| {
-                        let json_x: serde_json::Value = serde_json::from_str(x).unwrap_or_default();
-                        let json_y: serde_json::Value = serde_json::from_str(y).unwrap_or_default();
 
-                        let x_field = json_x[&field].to_string();
-                        let y_field = json_y[&field].to_string();
+                let rows = output.len();
+                self.output = output;
+                // Joined rows do not belong to a single key
+                self.scanned_keys.clear();
+                let time = start.elapsed();
+                explain.push(if (by_key || index.is_some()) && hash_table.is_none() {
+                    ExplainStep::IndexJoin { rows, time }
+                } else {
+                    ExplainStep::HashJoin { rows, time }
+                });
+            }
+            Instruction::FilterOutput { filter } => {
+                let start = Instant::now();
+                let condition = filter.condition();
+                self.output.retain(|json| condition(json));
+                explain.push(ExplainStep::Filter {
+                    rows: self.output.len(),
+                    time: start.elapsed(),
+                });
+            }
+            Instruction::UpdateScanned { update_fn } => {
+                let start = Instant::now();
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
 
-                        x_field.cmp(&y_field)
+                let keys = std::mem::take(&mut self.scanned_keys);
+                let rows = keys.len();
+                for key in keys {
+                    let old_value = database
+                        .get_document(storage_name.clone(), key.clone())?
+                        .clone();
+                    let new_value = update_fn(&old_value)?;
+                    database.update_document(storage_name.clone(), key.clone(), new_value)?;
+                    self.undo_log.push(UndoEntry::Updated {
+                        storage_name: storage_name.clone(),
+                        key,
+                        old_value,
                     });
-                    explain.push(ExplainStep::SortOutput);
+                    self.affected_rows += 1;
                 }
-                Instruction::SetLimit { count } => {
-                    if count < self.output.len() as u64 {
-                        self.output = self.output[0..count as usize].to_vec();
+                // Scanned rows are not part of the result of an UPDATE
+                self.output.clear();
+                explain.push(ExplainStep::Update {
+                    rows,
+                    time: start.elapsed(),
+                });
+            }
+            Instruction::Delete { key } => {
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+                self.delete_key(database, storage_name, key.clone())?;
+                explain.push(ExplainStep::Delete { rows: 1 });
+            }
+            Instruction::DeleteScanned => {
+                let Some(storage_name) = self.instruction_storage_name.clone() else {
+                    return Err(Error::ExecutionError(
+                        "No storage name provided".to_string(),
+                    ));
+                };
+
+                let keys = std::mem::take(&mut self.scanned_keys);
+                let rows = keys.len();
+                for key in keys {
+                    self.delete_key(database, storage_name.clone(), key)?;
+                }
+                // Scanned rows are not part of the result of a DELETE
+                self.output.clear();
+                explain.push(ExplainStep::Delete { rows });
+            }
+            Instruction::Aggregate {
+                group_by,
+                aggregates,
+                having,
+            } => {
+                let start = Instant::now();
+
+                // Groups keep the order in which they were first seen
+                let mut group_positions: HashMap<String, usize> = HashMap::new();
+                let mut groups: Vec<(Vec<serde_json::Value>, Vec<Accumulator>)> = Vec::new();
+
+                for json in self.output.iter() {
+                    let group_values = group_by
+                        .iter()
+                        .map(|field| column_value(json, field).cloned().unwrap_or_default())
+                        .collect::<Vec<serde_json::Value>>();
+                    let group_key = serde_json::Value::Array(group_values.clone()).to_string();
+
+                    let position = *group_positions.entry(group_key).or_insert_with(|| {
+                        groups.push((group_values, vec![Accumulator::default(); aggregates.len()]));
+                        groups.len() - 1
+                    });
+
+                    for (aggregate, accumulator) in
+                        aggregates.iter().zip(groups[position].1.iter_mut())
+                    {
+                        match &aggregate.field {
+                            None => accumulator.add_row(),
+                            Some(field) => accumulator.add_value(
+                                column_value(json, field).unwrap_or(&serde_json::Value::Null),
+                            ),
+                        }
                     }
-                    explain.push(ExplainStep::Limit);
                 }
-                Instruction::SetOffset { count } => {
-                    if count < self.output.len() as u64 {
-                        self.output = self.output[count as usize..].to_vec();
-                        explain.push(ExplainStep::Offset);
-                    } else {
-                        self.output = Vec::new();
-                        explain.push(ExplainStep::Offset);
+
+                // Aggregates over an empty input still give one row when there is no GROUP BY
+                if groups.is_empty() && group_by.is_empty() {
+                    groups.push((Vec::new(), vec![Accumulator::default(); aggregates.len()]));
+                }
+
+                let group_count = groups.len();
+                let mut output = Vec::new();
+                for (group_values, accumulators) in groups {
+                    let mut row = serde_json::Map::new();
+                    for (field, value) in group_by.iter().zip(group_values) {
+                        row.insert(field.clone(), value);
+                    }
+                    for (aggregate, accumulator) in aggregates.iter().zip(accumulators.iter()) {
+                        row.insert(
+                            aggregate.name.clone(),
+                            accumulator.result(aggregate.function),
+                        );
+                    }
+
+                    let row = serde_json::Value::Object(row);
+                    let keep = match &having {
+                        Some(filter) => filter.condition()(&row),
+                        None => true,
+                    };
+                    if keep {
+                        output.push(row);
                     }
                 }
-                _ => (),
+
+                self.output = output;
+                // Aggregated rows do not belong to a single key
+                self.scanned_keys.clear();
+                explain.push(ExplainStep::Aggregate {
+                    groups: group_count,
+                    time: start.elapsed(),
+                });
+            }
+            Instruction::MapOutput { map_fn } => {
+                self.output = self.output.iter().map(map_fn).collect();
+                explain.push(ExplainStep::MapOutput);
+            }
+            Instruction::SortOutput { field } => {
+                let null = serde_json::Value::Null;
+                self.output.sort_by(|x, y| {
+                    compare_json_values(
+                        column_value(x, &field).unwrap_or(&null),
+                        column_value(y, &field).unwrap_or(&null),
+                    )
+                });
+                explain.push(ExplainStep::SortOutput);
+            }
+            Instruction::SetLimit { count } => {
+                if count < self.output.len() as u64 {
+                    self.output = self.output[0..count as usize].to_vec();
+                }
+                explain.push(ExplainStep::Limit);
+            }
+            Instruction::SetOffset { count } => {
+                if count < self.output.len() as u64 {
+                    self.output = self.output[count as usize..].to_vec();
+                    explain.push(ExplainStep::Offset);
+                } else {
+                    self.output = Vec::new();
+                    explain.push(ExplainStep::Offset);
+                }
             }
+            _ => (),
         }
 
-        Ok(explain)
+        Ok(())
     }
 }
 
@@ -274,18 +622,56 @@ pub enum Instruction {
     UseStorage {
         name: String,
     },
+    CreateStorage {
+        name: String,
+    },
+    DropStorage {
+        name: String,
+        if_exists: bool,
+    },
+    CreateIndex {
+        field_name: String,
+        field_type: FieldType,
+        unique: bool,
+        // Ordered string indexes answer ranges and LIKE prefixes
+        ordered: bool,
+    },
+    DropIndex {
+        field_name: String,
+    },
     ClearOutput,
     ChooseScanPath {
         filter: Filter,
     },
     Scan {
-        index_filter: Filter,
+        index_plan: Option<IndexPlan>,
+        full_scan_filter: Filter,
+    },
+    // Documents of the graph neighbours of the nodes referencing the key
+    ScanNeighbours {
+        key: String,
+        rel_type: String,
         full_scan_filter: Filter,
-        string_fields_values: Vec<(String, String)>,
-        number_fields_values: Vec<(String, f64)>,
+    },
+    // Adds the matching documents of a storage to every row under the alias
+    Join {
+        storage_name: String,
+        alias: String,
+        kind: JoinKind,
+        left_column: String,
+        // KEY joins on the keys of the storage
+        right_field: String,
+    },
+    FilterOutput {
+        filter: Filter,
+    },
+    Aggregate {
+        group_by: Vec<String>,
+        aggregates: Vec<Aggregate>,
+        having: Option<Filter>,
     },
     MapOutput {
-        map_fn: Box<dyn Fn(String) -> String>,
+        map_fn: Box<dyn Fn(&serde_json::Value) -> serde_json::Value>,
     },
     SortOutput {
         field: String,
@@ -301,11 +687,16 @@ pub enum Instruction {
     },
     Set {
         key: String,
-        value: String,
+        value: serde_json::Value,
+    },
+    UpdateScanned {
+        update_fn: UpdateFn,
     },
+    OutputAffectedRows,
     Delete {
         key: String,
     },
+    DeleteScanned,
     Print {
         key: String,
     },
@@ -316,25 +707,354 @@ pub enum Instruction {
     Clear,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    // Rows without a match are kept with null for the joined table
+    Left,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "COUNT",
+            AggregateFunction::Sum => "SUM",
+            AggregateFunction::Avg => "AVG",
+            AggregateFunction::Min => "MIN",
+            AggregateFunction::Max => "MAX",
+        }
+    }
+}
+
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    // None aggregates whole rows, as in COUNT(*)
+    pub field: Option<String>,
+    // Output column
+    pub name: String,
+}
+
+// Running state of one aggregate in one group
+#[derive(Clone, Default)]
+struct Accumulator {
+    count: u64,
+    numbers: u64,
+    sum: f64,
+    min: Option<serde_json::Value>,
+    max: Option<serde_json::Value>,
+}
+
+impl Accumulator {
+    fn add_row(&mut self) {
+        self.count += 1;
+    }
+
+    // Missing fields and nulls are skipped, like NULL in SQL
+    fn add_value(&mut self, value: &serde_json::Value) {
+        if value.is_null() {
+            return;
+        }
+        self.count += 1;
+        if let Some(n) = value.as_f64() {
+            self.numbers += 1;
+            self.sum += n;
+        }
+        if self
+            .min
+            .as_ref()
+            .is_none_or(|min| compare_json_values(value, min) == Ordering::Less)
+        {
+            self.min = Some(value.clone());
+        }
+        if self
+            .max
+            .as_ref()
+            .is_none_or(|max| compare_json_values(value, max) == Ordering::Greater)
+        {
+            self.max = Some(value.clone());
+        }
+    }
+
+    fn result(&self, function: AggregateFunction) -> serde_json::Value {
+        match function {
+            AggregateFunction::Count => serde_json::Value::from(self.count),
+            AggregateFunction::Sum if self.numbers > 0 => number_to_json(self.sum),
+            AggregateFunction::Avg if self.numbers > 0 => {
+                number_to_json(self.sum / self.numbers as f64)
+            }
+            AggregateFunction::Min => self.min.clone().unwrap_or_default(),
+            AggregateFunction::Max => self.max.clone().unwrap_or_default(),
+            _ => serde_json::Value::Null,
+        }
+    }
+}
+
+// Whole numbers are stored as JSON integers, so that number indexes can pick them up
+pub fn number_to_json(n: f64) -> serde_json::Value {
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n <= i64::MAX as f64 {
+        serde_json::Value::from(n as i64)
+    } else {
+        serde_json::Value::from(n)
+    }
+}
+
+// Joined values are equal when their keys are, null never joins
+fn join_key(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::Number(n) => Some(number_to_json(n.as_f64()?).to_string()),
+        value => Some(value.to_string()),
+    }
+}
+
+// Numbers name the same key as INSERT gives them
+fn document_key(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(number_to_json(n.as_f64()?).to_string()),
+        _ => None,
+    }
+}
+
+fn with_table(row: &serde_json::Value, alias: &str, value: serde_json::Value) -> serde_json::Value {
+    let mut row = row.clone();
+    if let Some(tables) = row.as_object_mut() {
+        tables.insert(alias.to_string(), value);
+    }
+    row
+}
+
+// Numbers are compared by value and sort before strings, missing values come first
+pub fn compare_json_values(x: &serde_json::Value, y: &serde_json::Value) -> Ordering {
+    use serde_json::Value;
+
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+
+    match (x, y) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .unwrap_or_default()
+            .total_cmp(&b.as_f64().unwrap_or_default()),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        _ if rank(x) == rank(y) => x.to_string().cmp(&y.to_string()),
+        _ => rank(x).cmp(&rank(y)),
+    }
+}
+
+pub type Predicate = Box<dyn Fn(&serde_json::Value) -> bool>;
+
+pub type UpdateFn = Box<dyn Fn(&serde_json::Value) -> Result<serde_json::Value, Error>>;
+
 pub enum Filter {
-    Condition(Box<dyn Fn(String) -> bool>),
+    Condition(Predicate),
 }
 
 impl Filter {
-    pub fn condition(&self) -> &Box<dyn Fn(String) -> bool> {
+    pub fn condition(&self) -> &dyn Fn(&serde_json::Value) -> bool {
         match self {
             Filter::Condition(condition) => condition,
         }
     }
 }
 
+// Index lookups that can answer a WHERE clause,
+// the indexes are picked when the Scan runs, so the plan survives CREATE INDEX and DROP INDEX
+pub enum IndexPlan {
+    Compare {
+        field: String,
+        operator: String,
+        value: FieldValue,
+    },
+    // field IN (values), one lookup per value
+    In {
+        field: String,
+        values: Vec<FieldValue>,
+    },
+    // field LIKE 'prefix%'
+    Prefix {
+        field: String,
+        prefix: String,
+    },
+    And(Box<IndexPlan>, Box<IndexPlan>),
+    Or(Box<IndexPlan>, Box<IndexPlan>),
+}
+
+impl IndexPlan {
+    // Keys of the rows that may match, None when the indexes can not narrow the rows down
+    pub fn candidate_keys(&self, indexes: &IndexList) -> Option<Vec<Key>> {
+        match self {
+            IndexPlan::Compare {
+                field,
+                operator,
+                value,
+            } => indexes.get_index(field)?.search(operator, value),
+            IndexPlan::In { field, values } => {
+                let index = indexes.get_index(field)?;
+                let mut keys = Vec::new();
+                let mut seen = HashSet::new();
+                for value in values {
+                    for key in index.search("=", value)? {
+                        if seen.insert(key.clone()) {
+                            keys.push(key);
+                        }
+                    }
+                }
+                Some(keys)
+            }
+            IndexPlan::Prefix { field, prefix } => indexes.get_index(field)?.search_prefix(prefix),
+            // One usable side is enough, the other side is checked by the filter
+            IndexPlan::And(left, right) => {
+                match (left.candidate_keys(indexes), right.candidate_keys(indexes)) {
+                    (Some(left), Some(right)) => {
+                        let right: HashSet<Key> = right.into_iter().collect();
+                        Some(left.into_iter().filter(|k| right.contains(k)).collect())
+                    }
+                    (Some(keys), None) | (None, Some(keys)) => Some(keys),
+                    (None, None) => None,
+                }
+            }
+            IndexPlan::Or(left, right) => {
+                let mut keys = left.candidate_keys(indexes)?;
+                let mut seen: HashSet<Key> = keys.iter().cloned().collect();
+                for key in right.candidate_keys(indexes)? {
+                    if seen.insert(key.clone()) {
+                        keys.push(key);
+                    }
+                }
+                Some(keys)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ExplainStep {
     SetStorage(String),
+    CreateStorage(String),
+    DropStorage(String),
+    CreateIndex(String),
+    DropIndex(String),
+    Set(String),
+    Update { rows: usize, time: Duration },
+    Delete { rows: usize },
+    Aggregate { groups: usize, time: Duration },
     FullScan { time: Duration },
     IndexScan { time: Duration },
+    NeighbourScan { time: Duration },
+    HashJoin { rows: usize, time: Duration },
+    IndexJoin { rows: usize, time: Duration },
+    Filter { rows: usize, time: Duration },
     MapOutput,
     SortOutput,
     Limit,
     Offset,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_reads_updated_document() {
+        let mut database = Database::new();
+        database.create_storage("main".to_string()).unwrap();
+        database
+            .insert(
+                "main".to_string(),
+                "a".to_string(),
+                "{\"age\":30}".to_string(),
+            )
+            .unwrap();
+        database
+            .update_document(
+                "main".to_string(),
+                "a".to_string(),
+                serde_json::json!({"age": 31, "tags": ["new"]}),
+            )
+            .unwrap();
+
+        let scan = |database: &mut Database, age: f64| {
+            let mut vm = WitchVMKV::new();
+            vm.execute(
+                database,
+                &GraphStore::new(),
+                vec![
+                    Instruction::UseStorage {
+                        name: "main".to_string(),
+                    },
+                    Instruction::Scan {
+                        index_plan: None,
+                        full_scan_filter: Filter::Condition(Box::new(
+                            move |json: &serde_json::Value| {
+                                json["age"].as_f64() == Some(age) && json["tags"][0] == "new"
+                            },
+                        )),
+                    },
+                ],
+            )
+            .unwrap();
+            vm.into_output()
+        };
+
+        // The predicate gets the stored document itself, there is no text to parse again
+        assert_eq!(
+            scan(&mut database, 31.0),
+            vec![serde_json::json!({"age": 31, "tags": ["new"]})]
+        );
+        assert!(scan(&mut database, 30.0).is_empty());
+    }
+
+    #[test]
+    fn test_incomplete_rollback_is_reported() {
+        let mut database = Database::new();
+        database.create_storage("main".to_string()).unwrap();
+
+        // The storage of the first write is gone before the rollback can undo it
+        let mut vm = WitchVMKV::new();
+        let result = vm.execute(
+            &mut database,
+            &GraphStore::new(),
+            vec![
+                Instruction::UseStorage {
+                    name: "main".to_string(),
+                },
+                Instruction::Set {
+                    key: "a".to_string(),
+                    value: serde_json::json!(1),
+                },
+                Instruction::DropStorage {
+                    name: "main".to_string(),
+                    if_exists: false,
+                },
+                Instruction::Set {
+                    key: "b".to_string(),
+                    value: serde_json::json!(2),
+                },
+            ],
+        );
+        assert!(matches!(
+            result,
+            Err(Error::ExecutionError(e))
+                if e.contains("rollback is incomplete: insert of 'a' was not undone")
+        ));
+    }
+}
@@ -43,19 +43,50 @@
 // MMMMMMMMMMMMdy+/---``---:+sdMMMMMMMMMMMM
 // MMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMMM
 
+use crate::graph::error::Error as GraphError;
+use crate::graph::formats;
+use crate::graph::query_handler::{explain_cypher_query, handle_cypher_query};
+use crate::graph::store::GraphStore;
 use crate::kv::database::Database;
+use crate::kv::persistence::PersistenceConfig;
 use crate::kv::query_handler::{explain_query, handle_query};
 use crate::server_models::*;
+use axum::extract::{FromRef, Query};
 use axum::routing::{delete, post, put};
 use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+// Handlers take the part of the state they need, e.g. State<Arc<Mutex<Database>>>
+#[derive(Clone)]
+struct AppState {
+    database: Arc<Mutex<Database>>,
+    graph: Arc<Mutex<GraphStore>>,
+}
+
+impl FromRef<AppState> for Arc<Mutex<Database>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.database.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Mutex<GraphStore>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.graph.clone()
+    }
+}
+
 pub async fn run_witch_server() {
     greet();
     println!("Running 🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙🧙 server on localhost:3000");
 
-    let database = Arc::new(Mutex::new(Database::new()));
+    let database = match PersistenceConfig::from_env().and_then(Database::open) {
+        Ok(database) => Arc::new(Mutex::new(database)),
+        Err(e) => {
+            println!("Failed to open database: {}", e.into_string());
+            return;
+        }
+    };
 
     #[cfg(feature = "local")]
     {
@@ -72,8 +103,16 @@ pub async fn run_witch_server() {
         .route("/kv/change_value", put(change_value))
         .route("/kv/get_value", get(get_value))
         .route("/kv/create_index", post(create_index))
+        .route("/kv/drop_index", delete(drop_index))
         .route("/kv/explain", get(explain))
-        .with_state(database);
+        .route("/graph/cypher", get(handle_cypher_request))
+        .route("/graph/explain", get(explain_cypher))
+        .route("/graph/import", post(import_graph))
+        .route("/graph/export", get(export_graph))
+        .with_state(AppState {
+            database,
+            graph: Arc::new(Mutex::new(GraphStore::new())),
+        });
 
     // run our app
     let listener = match tokio::net::TcpListener::bind("localhost:3000").await {
@@ -91,10 +130,43 @@ pub async fn run_witch_server() {
 }
 
 async fn handle_sql_request(
-    State(database): State<Arc<Mutex<Database>>>,
+    State(state): State<AppState>,
+    Query(params): Query<SQLRequestParams>,
     Json(request): Json<SQLRequest>,
 ) -> Result<String, (StatusCode, String)> {
-    match handle_query(database, request.sql).await {
+    let result = handle_query(state.database, state.graph, request.sql)
+        .await
+        .and_then(|result| {
+            match params.format {
+                // Comma separated documents, kept for older clients
+                Some(ResponseFormat::Raw) => Ok(result.into_raw_string()),
+                Some(ResponseFormat::Json) | None => result.try_to_string(),
+            }
+        });
+    match result {
+        Ok(result) => Ok(result),
+        Err(e) => {
+            let err_response = match e.into_response_string() {
+                Ok(response) => response,
+                Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.into_string())),
+            };
+            Err((StatusCode::BAD_REQUEST, err_response))
+        }
+    }
+}
+
+async fn handle_cypher_request(
+    State(state): State<AppState>,
+    Json(request): Json<CypherRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let result = handle_cypher_query(state.database, state.graph, request.query, request.params)
+        .await
+        .and_then(|result| {
+            result
+                .try_to_string()
+                .map_err(|e| GraphError::JsonError(e.into_string()))
+        });
+    match result {
         Ok(result) => Ok(result),
         Err(e) => {
             let err_response = match e.into_response_string() {
@@ -187,22 +259,78 @@ async fn create_index(
         request.field_name,
         request.field_type,
         request.unique,
+        request.ordered,
     ) {
         Ok(_) => Ok("".to_string()),
         Err(e) => Err((StatusCode::BAD_REQUEST, e.into_string())),
     }
 }
 
-async fn explain(
+async fn drop_index(
     State(database): State<Arc<Mutex<Database>>>,
+    Json(request): Json<DropIndexRequest>,
+) -> Result<String, (StatusCode, String)> {
+    match database
+        .lock()
+        .await
+        .drop_index(request.storage_name, request.field_name)
+    {
+        Ok(_) => Ok("".to_string()),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e.into_string())),
+    }
+}
+
+async fn explain(
+    State(state): State<AppState>,
     Json(request): Json<ExplainRequest>,
 ) -> Result<String, (StatusCode, String)> {
-    match explain_query(database, request.sql).await {
+    match explain_query(state.database, state.graph, request.sql).await {
         Ok(result) => Ok(result),
         Err(e) => Err((StatusCode::BAD_REQUEST, e.into_string())),
     }
 }
 
+async fn explain_cypher(
+    State(state): State<AppState>,
+    Json(request): Json<CypherRequest>,
+) -> Result<String, (StatusCode, String)> {
+    match explain_cypher_query(state.database, state.graph, request.query, request.params).await {
+        Ok(result) => Ok(result),
+        Err(e) => match e.into_response_string() {
+            Ok(response) => Err((StatusCode::BAD_REQUEST, response)),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.into_string())),
+        },
+    }
+}
+
+async fn import_graph(
+    State(graph): State<Arc<Mutex<GraphStore>>>,
+    Json(request): Json<GraphImportRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let mut graph = graph.lock().await;
+    let summary = match request.format {
+        GraphFormat::Csv => formats::import_csv(&mut graph, &request.nodes, &request.edges),
+        GraphFormat::GraphML => formats::import_graphml(&mut graph, &request.data),
+    };
+    let result = summary
+        .map_err(GraphError::ImportError)
+        .and_then(|summary| {
+            serde_json::to_string(&summary).map_err(|e| GraphError::JsonError(e.to_string()))
+        });
+    match result {
+        Ok(result) => Ok(result),
+        Err(e) => match e.into_response_string() {
+            Ok(response) => Err((StatusCode::BAD_REQUEST, response)),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.into_string())),
+        },
+    }
+}
+
+async fn export_graph(State(graph): State<Arc<Mutex<GraphStore>>>) -> String {
+    let graph = graph.lock().await;
+    formats::export_graphml(&graph)
+}
+
 fn greet() {
     println!("{}", pentagram());
 }
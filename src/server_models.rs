@@ -88,15 +88,63 @@ pub struct SQLRequest {
     pub sql: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    Json,
+    Raw,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SQLRequestParams {
+    pub format: Option<ResponseFormat>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateIndexRequest {
     pub storage_name: String,
     pub field_name: String,
     pub field_type: FieldType,
     pub unique: bool,
+    // Ordered string indexes also answer <, >, BETWEEN and LIKE 'prefix%'
+    #[serde(default)]
+    pub ordered: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DropIndexRequest {
+    pub storage_name: String,
+    pub field_name: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExplainRequest {
     pub sql: String,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    Csv,
+    GraphML,
+}
+
+// CSV imports read nodes and edges, GraphML imports read data
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GraphImportRequest {
+    pub format: GraphFormat,
+    #[serde(default)]
+    pub nodes: String,
+    #[serde(default)]
+    pub edges: String,
+    #[serde(default)]
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CypherRequest {
+    pub query: String,
+    // Values of the $name placeholders in the query
+    #[serde(default)]
+    pub params: serde_json::Map<String, serde_json::Value>,
+}